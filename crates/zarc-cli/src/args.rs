@@ -4,9 +4,13 @@ use clap::{ArgAction, Parser, Subcommand, ValueHint};
 
 use crate::{
 	// debug::DebugArgs,
+	extract_subset::ExtractSubsetArgs,
 	list_files::ListFilesArgs,
+	merge::MergeArgs,
 	pack::PackArgs,
+	sfx::SfxArgs,
 	unpack::UnpackArgs,
+	verify::VerifyArgs,
 };
 
 /// Zarc: a novel archive format and tool.
@@ -70,6 +74,7 @@ pub struct Args {
 }
 
 #[derive(Debug, Clone, Subcommand)]
+#[allow(clippy::large_enum_variant)] // parsed once
 pub enum Action {
 	/// Pack files into a Zarc archive.
 	Pack(PackArgs),
@@ -79,6 +84,18 @@ pub enum Action {
 
 	/// Walk a Zarc and print filenames.
 	ListFiles(ListFilesArgs),
+
+	/// Merge several Zarc archives into one.
+	Merge(MergeArgs),
+
+	/// Copy some files from a Zarc into a new, smaller Zarc.
+	ExtractSubset(ExtractSubsetArgs),
+
+	/// Verify the integrity and signatures of a Zarc.
+	Verify(VerifyArgs),
+
+	/// Make a self-extracting Zarc, which unpacks itself when run.
+	Sfx(SfxArgs),
 	// /// Walk a Zarc and print detailed information about its structure.
 	// Debug(DebugArgs),
 }
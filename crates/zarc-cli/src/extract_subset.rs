@@ -0,0 +1,214 @@
+use std::{collections::HashSet, fs::File, path::PathBuf};
+
+use base64ct::{Base64, Encoding};
+use clap::{Parser, ValueHint};
+use globset::Glob;
+use miette::IntoDiagnostic;
+use regex::Regex;
+use tracing::{debug, info, warn};
+use zarc::{
+	decode::Decoder,
+	directory::Pathname,
+	encode::{Encoder, MergeConflict},
+};
+
+#[derive(Debug, Clone, Parser)]
+pub struct ExtractSubsetArgs {
+	/// Input file.
+	#[arg(
+		value_hint = ValueHint::AnyPath,
+		value_name = "PATH",
+	)]
+	pub input: PathBuf,
+
+	/// Output file.
+	#[arg(long,
+		value_hint = ValueHint::AnyPath,
+		value_name = "PATH",
+	)]
+	pub output: PathBuf,
+
+	/// Filter files by name (with a regex).
+	///
+	/// Can be given multiple times, and files will be matched if they match any of the regexes or
+	/// globs.
+	#[arg(long, value_name = "REGEX")]
+	pub filter: Vec<Regex>,
+
+	/// Filter files by name (with a glob).
+	///
+	/// Can be given multiple times, and files will be matched if they match any of the regexes or
+	/// globs.
+	#[arg(long, value_name = "GLOB")]
+	pub glob: Vec<Glob>,
+}
+
+pub(crate) fn extract_subset(args: ExtractSubsetArgs) -> miette::Result<()> {
+	info!("initialise decoder");
+	let mut zarc = Decoder::open(args.input)?;
+	zarc.read_directory()?;
+	let zarc = zarc;
+
+	let globs: Vec<_> = args.glob.iter().map(Glob::compile_matcher).collect();
+	let matches = |name: &Pathname| {
+		if args.filter.is_empty() && globs.is_empty() {
+			return true;
+		}
+
+		let path = name.to_path();
+		let string = path.display().to_string();
+		args.filter.iter().any(|filter| filter.is_match(&string))
+			|| globs.iter().any(|glob| glob.is_match(&path))
+	};
+
+	info!("select files");
+	let mut queue: Vec<Pathname> = zarc
+		.files()
+		.filter(|entry| matches(&entry.name))
+		.map(|entry| entry.name.clone())
+		.collect();
+
+	// pull in parent directories and link targets, so the subset stands on its own
+	let mut selected = HashSet::new();
+	while let Some(name) = queue.pop() {
+		if selected.contains(&name) {
+			continue;
+		}
+
+		for depth in 1..name.0.len() {
+			let parent = Pathname(name.0[..depth].to_vec());
+			if !selected.contains(&parent) && zarc.files_by_name(parent.clone()).is_some() {
+				debug!(?parent, "select parent directory");
+				queue.push(parent);
+			}
+		}
+
+		for entry in zarc.files_by_name(name.clone()).unwrap_or_default() {
+			let Some(target) = entry.link_target_pathname() else {
+				continue;
+			};
+
+			if zarc.files_by_name(target.clone()).is_some() {
+				debug!(?target, "select link target");
+				queue.push(target);
+			} else if entry.is_hardlink() {
+				warn!(path=?entry.name.to_path(), ?target, "hardlink target not found");
+			}
+		}
+
+		selected.insert(name);
+	}
+
+	info!(path=?args.output, "create output file");
+	let mut file = File::create(args.output).into_diagnostic()?;
+
+	info!("initialise encoder");
+	let mut subset = Encoder::new(&mut file).into_diagnostic()?;
+
+	info!("copy files");
+	subset
+		.merge_with(&zarc, MergeConflict::KeepBoth, |entry| {
+			selected.contains(&entry.name)
+		})
+		.into_diagnostic()?;
+
+	info!("finalising zarc");
+	let digest = subset.finalise().into_diagnostic()?;
+
+	eprintln!("extracted {} paths", selected.len());
+	println!("digest: {}", Base64::encode_string(&digest));
+	Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn selects_parents_and_link_targets() {
+	use std::{collections::BTreeSet, fs, os::unix::fs::symlink};
+
+	use crate::pack::{pack, PackArgs};
+
+	let dir = std::env::temp_dir().join(format!("zarc-subset-{}", std::process::id()));
+	let content = dir.join("content");
+	for (path, text) in [
+		("keep/one.txt", "first"),
+		("docs/readme.md", "docs"),
+		("other/target.txt", "target"),
+		("other/unselected.txt", "other"),
+		("skip/unrelated.txt", "unrelated"),
+	] {
+		let path = content.join(path);
+		fs::create_dir_all(path.parent().expect("parent")).expect("create dir");
+		fs::write(path, text).expect("write file");
+	}
+	symlink("../other/target.txt", content.join("keep/link")).expect("create symlink");
+
+	let input = dir.join("input.zarc");
+	let pack_args = |extra: &[&str]| {
+		let mut args = vec!["pack", "--output", input.to_str().expect("utf-8 path")];
+		args.extend_from_slice(extra);
+		args.push(content.to_str().expect("utf-8 path"));
+		PackArgs::try_parse_from(args).expect("parse args")
+	};
+	pack(pack_args(&[])).expect("pack first edition");
+	fs::write(content.join("keep/one.txt"), "second").expect("write file");
+	pack(pack_args(&["--append"])).expect("append second edition");
+
+	let output = dir.join("output.zarc");
+	extract_subset(
+		ExtractSubsetArgs::try_parse_from([
+			"extract-subset",
+			"--output",
+			output.to_str().expect("utf-8 path"),
+			"--glob",
+			"**/one.txt",
+			"--filter",
+			r"\.md$",
+			"--filter",
+			"/link$",
+			input.to_str().expect("utf-8 path"),
+		])
+		.expect("parse args"),
+	)
+	.expect("extract subset");
+
+	let mut input = Decoder::open(input).expect("open input");
+	input.read_directory().expect("read directory");
+	let mut subset = Decoder::open(output).expect("open subset");
+	subset.read_directory().expect("read directory");
+
+	let name = |path: &str| Pathname::from_normal_components(&content.join(path));
+	let names: BTreeSet<_> = subset.files().map(|file| file.name.clone()).collect();
+	let expected: BTreeSet<_> = [
+		"",
+		"keep",
+		"keep/one.txt",
+		"keep/link",
+		"docs",
+		"docs/readme.md",
+		"other",
+		"other/target.txt",
+	]
+	.into_iter()
+	.map(name)
+	.collect();
+	assert_eq!(names, expected);
+	assert_eq!(
+		subset.files().count(),
+		input
+			.files()
+			.filter(|file| expected.contains(&file.name))
+			.count(),
+		"all entries of the selected paths are kept"
+	);
+
+	// both entries of the changed file are kept, and the latest is the one read
+	let one = name("keep/one.txt");
+	assert_eq!(subset.files_by_name(one.clone()).expect("one.txt").len(), 2);
+	let latest = subset.latest_file(one).expect("one.txt");
+	assert_eq!(
+		subset.read_at(latest, 0, 64).expect("read one.txt"),
+		b"second"
+	);
+
+	fs::remove_dir_all(&dir).expect("clean up");
+}
@@ -0,0 +1,72 @@
+use std::{
+	fs::read_to_string,
+	io::{Error, Result},
+	path::Path,
+};
+
+use base64ct::{Base64, Encoding};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use zarc::{
+	decode::{error::Result as DecodeResult, Decoder},
+	encryption::EncryptionKey,
+	integrity::{SigningKey, VerifyingKey},
+	ondemand::OnDemand,
+};
+
+/// Read an Ed25519 private key from a file.
+///
+/// The file is either PKCS#8 PEM, or the base64 of the raw 32-byte secret key.
+pub(crate) fn read_signing_key(path: &Path) -> Result<SigningKey> {
+	let contents = read_to_string(path)?;
+	if contents.trim_start().starts_with("-----BEGIN") {
+		SigningKey::from_pkcs8_pem(&contents)
+			.map_err(|err| Error::other(format!("invalid private key in {path:?}: {err}")))
+	} else {
+		raw_key(contents.trim())
+			.map(|bytes| SigningKey::from_bytes(&bytes))
+			.ok_or_else(|| Error::other(format!("invalid private key in {path:?}")))
+	}
+}
+
+/// Read an Ed25519 public key.
+///
+/// The argument is either a path to a file, in SPKI PEM format or containing the base64 of the
+/// raw 32-byte public key, or that base64 directly.
+pub(crate) fn read_verifying_key(arg: &str) -> Result<VerifyingKey> {
+	let path = Path::new(arg);
+	let contents = if path.is_file() {
+		read_to_string(path)?
+	} else {
+		arg.to_owned()
+	};
+
+	if contents.trim_start().starts_with("-----BEGIN") {
+		VerifyingKey::from_public_key_pem(&contents)
+			.map_err(|err| Error::other(format!("invalid public key {arg:?}: {err}")))
+	} else {
+		raw_key(contents.trim())
+			.and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+			.ok_or_else(|| Error::other(format!("invalid public key {arg:?}")))
+	}
+}
+
+/// Read an encryption key from a file.
+///
+/// The file contains the base64 of the raw 32-byte key.
+pub(crate) fn read_encryption_key(path: &Path) -> Result<EncryptionKey> {
+	raw_key(read_to_string(path)?.trim())
+		.map(EncryptionKey::from_bytes)
+		.ok_or_else(|| Error::other(format!("invalid encryption key in {path:?}")))
+}
+
+/// Open a Zarc, decrypting it with the key in a file if given.
+pub(crate) fn open_zarc<R: OnDemand>(source: R, key: Option<&Path>) -> DecodeResult<Decoder<R>> {
+	match key {
+		Some(key) => Decoder::open_with_key(source, &read_encryption_key(key)?),
+		None => Decoder::open(source),
+	}
+}
+
+fn raw_key(base64: &str) -> Option<[u8; 32]> {
+	Base64::decode_vec(base64).ok()?.try_into().ok()
+}
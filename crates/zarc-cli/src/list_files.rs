@@ -1,9 +1,12 @@
 use std::path::PathBuf;
 
 use clap::{Parser, ValueHint};
+use miette::IntoDiagnostic;
 use regex::Regex;
 use tracing::info;
-use zarc::{decode::Decoder, directory::SpecialFileKind};
+use zarc::directory::SpecialFileKind;
+
+use crate::{keys::open_zarc, source::Source};
 
 #[derive(Debug, Clone, Parser)]
 pub struct ListFilesArgs {
@@ -29,16 +32,28 @@ pub struct ListFilesArgs {
 	/// Can be given multiple times, and files will be matched if they match any of the regexes.
 	#[arg(long, value_name = "REGEX")]
 	pub filter: Vec<Regex>,
+
+	/// Decrypt the Zarc with the key in this file.
+	///
+	/// The file contains the base64 of a 32-byte key, as given to 'zarc pack --encrypt'.
+	#[arg(long,
+		value_hint = ValueHint::FilePath,
+		value_name = "KEYFILE",
+	)]
+	pub decrypt: Option<PathBuf>,
 }
 
 pub(crate) fn list_files(args: ListFilesArgs) -> miette::Result<()> {
 	info!("initialise decoder");
-	let mut zarc = Decoder::open(args.input)?;
+	let mut zarc = open_zarc(
+		Source::new(args.input).into_diagnostic()?,
+		args.decrypt.as_deref(),
+	)?;
 	zarc.read_directory()?;
 	let zarc = zarc;
 
 	info!("list files");
-	for entry in zarc.files() {
+	for entry in zarc.latest_files() {
 		if args.only_files && entry.special.is_some() {
 			continue;
 		}
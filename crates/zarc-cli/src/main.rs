@@ -9,14 +9,25 @@ use crate::args::Action;
 
 mod args;
 // mod debug;
+mod extract_subset;
+mod keys;
 mod list_files;
 mod logs;
+mod merge;
 mod pack;
+mod sfx;
+mod source;
 mod unpack;
+mod verify;
 
 fn main() -> miette::Result<()> {
 	let logs_on = logs::from_env().into_diagnostic()?;
 
+	if let Some(source) = sfx::embedded() {
+		debug!(?source, "found appended zarc, self-extracting");
+		return sfx::self_extract(source, sfx::SelfExtractArgs::parse());
+	}
+
 	debug!("parsing arguments");
 	let args = args::Args::parse();
 
@@ -30,8 +41,12 @@ fn main() -> miette::Result<()> {
 
 	match args.action {
 		// Action::Debug(args) => debug::debug(args).into_diagnostic(),
+		Action::ExtractSubset(args) => extract_subset::extract_subset(args),
 		Action::ListFiles(args) => list_files::list_files(args),
+		Action::Merge(args) => merge::merge(args),
 		Action::Pack(args) => pack::pack(args).into_diagnostic(),
+		Action::Sfx(args) => sfx::sfx(args),
 		Action::Unpack(args) => unpack::unpack(args),
+		Action::Verify(args) => verify::verify(args),
 	}
 }
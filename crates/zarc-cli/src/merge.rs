@@ -0,0 +1,81 @@
+use std::{fs::File, path::PathBuf};
+
+use base64ct::{Base64, Encoding};
+use clap::{Parser, ValueEnum, ValueHint};
+use miette::IntoDiagnostic;
+use tracing::info;
+use zarc::{
+	decode::Decoder,
+	encode::{Encoder, MergeConflict},
+};
+
+#[derive(Debug, Clone, Parser)]
+pub struct MergeArgs {
+	/// Output file.
+	#[arg(long,
+		value_hint = ValueHint::AnyPath,
+		value_name = "PATH",
+	)]
+	pub output: PathBuf,
+
+	/// Zarcs to merge.
+	///
+	/// Files are added in order, and identical content is only stored once.
+	#[arg(
+		required = true,
+		value_hint = ValueHint::AnyPath,
+		value_name = "PATH",
+	)]
+	pub inputs: Vec<PathBuf>,
+
+	/// What to do when several inputs have a file at the same path.
+	///
+	/// With 'keep-both', all entries are kept and unpacking prefers the last one. With 'last-wins',
+	/// only the entry from the last input is kept. With 'error', merging stops. Directories never
+	/// conflict with other directories.
+	#[arg(long, value_enum, default_value_t = OnConflict::KeepBoth)]
+	pub on_conflict: OnConflict,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OnConflict {
+	KeepBoth,
+	LastWins,
+	Error,
+}
+
+impl From<OnConflict> for MergeConflict {
+	fn from(on_conflict: OnConflict) -> Self {
+		match on_conflict {
+			OnConflict::KeepBoth => Self::KeepBoth,
+			OnConflict::LastWins => Self::LastWins,
+			OnConflict::Error => Self::Error,
+		}
+	}
+}
+
+pub(crate) fn merge(args: MergeArgs) -> miette::Result<()> {
+	info!(path=?args.output, "create output file");
+	let mut file = File::create(args.output).into_diagnostic()?;
+
+	info!("initialise encoder");
+	let mut zarc = Encoder::new(&mut file).into_diagnostic()?;
+
+	let mut decoders = Vec::with_capacity(args.inputs.len());
+	for input in args.inputs {
+		info!(?input, "read input");
+		let mut decoder = Decoder::open(input)?;
+		decoder.read_directory()?;
+		decoders.push(decoder);
+	}
+
+	info!("merge inputs");
+	zarc.merge_all(&decoders, args.on_conflict.into())
+		.into_diagnostic()?;
+
+	info!("finalising zarc");
+	let digest = zarc.finalise().into_diagnostic()?;
+
+	println!("digest: {}", Base64::encode_string(&digest));
+	Ok(())
+}
@@ -1,10 +1,28 @@
-use std::{fs::File, path::PathBuf};
+use std::{
+	collections::{BTreeMap, HashMap},
+	ffi::OsString,
+	fs::{File, OpenOptions},
+	io::{Error, Seek, SeekFrom},
+	path::{Path, PathBuf},
+};
 
 use base64ct::{Base64, Encoding};
+use chrono::{DateTime, Utc};
 use clap::{Parser, ValueHint};
-use tracing::{debug, info};
+use globset::{Glob, GlobMatcher};
+use tracing::{debug, info, warn};
 use walkdir::WalkDir;
-use zarc::encode::{Encoder, ZstdParameter, ZstdStrategy};
+use zarc::{
+	decode::Decoder,
+	directory::{CborString, Pathname, Timestamp},
+	encode::{
+		train_dictionary, AutoCompression, ChunkSizes, Compression, CompressionPolicy, Encoder,
+		ZstdParameter, ZstdStrategy,
+	},
+	integrity::Digest,
+};
+
+use crate::keys::{open_zarc, read_encryption_key, read_signing_key};
 
 #[derive(Debug, Clone, Parser)]
 pub struct PackArgs {
@@ -53,6 +71,64 @@ pub struct PackArgs {
 	#[arg(long)]
 	pub store: bool,
 
+	/// Compress all content, even if it doesn't compress well.
+	///
+	/// By default, content that's already compressed (like archives, images, audio, and video) or
+	/// which doesn't compress well is stored uncompressed, which is faster to pack and unpack.
+	#[arg(long)]
+	pub compress_all: bool,
+
+	/// Always store files with this extension uncompressed.
+	///
+	/// Can be given multiple times. This adds to the built-in list of already-compressed formats.
+	#[arg(long, value_name = "EXT")]
+	pub store_ext: Vec<String>,
+
+	/// Always compress files with this extension.
+	///
+	/// Can be given multiple times. This overrides all other checks, including '--store-ext'.
+	#[arg(long, value_name = "EXT")]
+	pub compress_ext: Vec<String>,
+
+	/// Always store content of this MIME type uncompressed.
+	///
+	/// Can be given multiple times, and can end with '/*' to match a whole category (like
+	/// 'image/*'). Types are detected from the content, for a limited set of formats only.
+	#[arg(long, value_name = "TYPE")]
+	pub store_mime: Vec<String>,
+
+	/// Always compress content smaller than this many bytes.
+	///
+	/// Defaults to 1 KiB.
+	#[arg(long, value_name = "BYTES")]
+	pub compress_below: Option<usize>,
+
+	/// Minimum gain for content to be compressed, in percent.
+	///
+	/// A sample of the content is compressed, and if that saves less than this percentage of its
+	/// size, the content is stored uncompressed instead. Defaults to 5%.
+	#[arg(
+		long,
+		value_name = "PERCENT",
+		value_parser = clap::value_parser!(u8).range(0..=100),
+	)]
+	pub min_gain: Option<u8>,
+
+	/// Compression level for files matching a glob.
+	///
+	/// Takes 'GLOB=LEVEL', where LEVEL is a compression level, or 'store' to store matching files
+	/// uncompressed. Globs are matched against the path of files as they're stored, and '*' matches
+	/// across directories. Can be given multiple times, and the first matching rule applies; files
+	/// which don't match any rule use '--level'. With '--solid', matching files get frames of their
+	/// own instead of going in solid blocks. Conflicts with '--store'.
+	#[arg(
+		long,
+		value_name = "GLOB=LEVEL",
+		value_parser = parse_level_rule,
+		conflicts_with = "store",
+	)]
+	pub level_for: Vec<LevelRule>,
+
 	/// Follow symlinks.
 	///
 	/// This destroys symlinks inside the Zarc: when unpacked, files will be duplicated.
@@ -81,6 +157,231 @@ pub struct PackArgs {
 	/// or to unpack the stored content.
 	#[arg(long, hide = true)]
 	pub follow_and_store_external_symlinks: bool,
+
+	/// Append to an existing Zarc.
+	///
+	/// If the output file exists, files are added to it as a new edition instead of replacing it.
+	/// Content that's already in the Zarc isn't stored again. When unpacking, the latest version
+	/// of a path wins.
+	///
+	/// The new edition is written to a copy of the Zarc, next to it, which then replaces it. If
+	/// packing fails partway, the existing Zarc is left as it was.
+	#[arg(long)]
+	pub append: bool,
+
+	/// Append only what changed since the last edition.
+	///
+	/// Like '--append', but files whose size, modification time, mode, type, and content haven't
+	/// changed since they were last packed are skipped. Files that were in the Zarc under the given
+	/// paths but aren't there anymore are recorded as deleted. Each edition is then a snapshot.
+	#[arg(long)]
+	pub incremental: bool,
+
+	/// Make a thin Zarc against a base Zarc.
+	///
+	/// Content that's already in the base isn't stored, only referenced. The resulting Zarc is
+	/// smaller, but the base is needed to unpack it (see 'zarc unpack --base'). When appending to
+	/// a thin Zarc with '--delta', give its bases again so content in them can be referenced.
+	///
+	/// Can be given multiple times.
+	#[arg(long,
+		value_hint = ValueHint::AnyPath,
+		value_name = "PATH",
+	)]
+	pub base: Vec<PathBuf>,
+
+	/// Split file content into chunks, for deduplication within files.
+	///
+	/// Files are split at content-defined boundaries, so that files which are mostly similar
+	/// (like successive versions of a log or database) share most of their chunks, which are only
+	/// stored once. This is most useful with '--append' or '--incremental'.
+	#[arg(long)]
+	pub chunked: bool,
+
+	/// Average chunk size in bytes, with '--chunked'.
+	///
+	/// Chunks will be between a quarter and four times this size. Defaults to 64 KiB.
+	#[arg(
+		long,
+		value_name = "BYTES",
+		value_parser = clap::value_parser!(u32).range(256..=4_194_304),
+		requires = "chunked",
+	)]
+	pub chunk_size: Option<u32>,
+
+	/// Split large files into independently-compressed frames of this size, for random access.
+	///
+	/// Reading part of a file then only decompresses the frames holding that part, instead of the
+	/// whole file up to there. Smaller frames make random access faster, larger frames compress
+	/// better. Optionally takes the frame size in bytes, which defaults to 1 MiB.
+	#[arg(
+		long,
+		value_name = "SIZE",
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "1048576",
+		value_parser = clap::value_parser!(u64).range(1..),
+		conflicts_with = "chunked",
+	)]
+	pub seekable: Option<u64>,
+
+	/// Pack small files together in solid frames.
+	///
+	/// The content of files smaller than the block size is concatenated and compressed as one,
+	/// which compresses many small files much better. Extracting one file then requires
+	/// decompressing its block up to that file.
+	#[arg(long)]
+	pub solid: bool,
+
+	/// Size of solid blocks in bytes, with '--solid'.
+	///
+	/// Larger blocks compress better, smaller blocks make extracting individual files faster.
+	/// Defaults to 1 MiB.
+	#[arg(
+		long,
+		value_name = "BYTES",
+		value_parser = clap::value_parser!(u64).range(1..),
+	)]
+	pub solid_block_size: Option<u64>,
+
+	/// Store BLAKE3 outboards for frames larger than this many bytes.
+	///
+	/// An outboard lets readers verify parts of a large frame as they're read, without reading the
+	/// entire frame first. It takes 32 bytes per 16 KiB of content.
+	#[arg(long, value_name = "BYTES")]
+	pub outboard_above: Option<u64>,
+
+	/// Train a compression dictionary on the files to pack.
+	///
+	/// The dictionary is stored in the Zarc and used to compress content, which improves
+	/// compression of many small similar files a lot. Optionally takes the size of the dictionary
+	/// in bytes, which defaults to 110 KiB.
+	///
+	/// This reads small files twice: once to sample them for training, and once to pack them.
+	#[arg(
+		long,
+		value_name = "SIZE",
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "112640",
+	)]
+	pub train_dict: Option<usize>,
+
+	/// Make a reproducible Zarc.
+	///
+	/// Packing the same files gives a byte-identical Zarc, across runs and machines: the owners,
+	/// the access and creation times, and the attributes and extended attributes of files aren't
+	/// stored, and the edition is timestamped with SOURCE_DATE_EPOCH, or the Unix epoch if that's
+	/// not set.
+	///
+	/// SOURCE_DATE_EPOCH is used for the edition timestamp even without this flag.
+	#[arg(long)]
+	pub reproducible: bool,
+
+	/// Clamp modification times of files to SOURCE_DATE_EPOCH.
+	///
+	/// Files modified after that time are stored as modified at that time. SOURCE_DATE_EPOCH must
+	/// be set.
+	#[arg(long)]
+	pub clamp_mtime: bool,
+
+	/// Compress files against similar content already packed.
+	///
+	/// When a file has the same path as one already in the Zarc (e.g. with '--append'), or the same
+	/// name as one in another directory, and a similar size, its content is compressed using the
+	/// other file's content as reference. This shrinks successive versions of mostly-unchanged files
+	/// dramatically, but extracting a file requires decompressing its reference too.
+	#[arg(long)]
+	pub delta: bool,
+
+	/// Sign the Zarc with an Ed25519 private key.
+	///
+	/// The key is read from a file, either in PKCS#8 PEM format, as generated by e.g. 'openssl
+	/// genpkey -algorithm ed25519', or as the base64 of the raw 32-byte secret key. The signature
+	/// covers the directory digest, and thus all content in the Zarc; check it with 'zarc verify'.
+	///
+	/// Can be given multiple times to sign with several keys. With '--append', signatures from
+	/// previous editions are kept.
+	#[arg(long,
+		value_hint = ValueHint::FilePath,
+		value_name = "KEYFILE",
+	)]
+	pub sign: Vec<PathBuf>,
+
+	/// Encrypt the Zarc with the key in this file.
+	///
+	/// The file contains the base64 of a random 32-byte key, as generated by e.g. 'head -c32
+	/// /dev/urandom | base64'. File contents, names, and metadata are all encrypted; only the
+	/// overall size and number of frames can be seen without the key.
+	///
+	/// Can be given multiple times, and any one of the keys can then decrypt the Zarc. When
+	/// appending to an encrypted Zarc, new content is encrypted with the same keys, and the first
+	/// key is used to decrypt the existing Zarc if '--decrypt' isn't given. Keys can't be added to
+	/// an existing Zarc, so every key given must already open it.
+	#[arg(long,
+		value_hint = ValueHint::FilePath,
+		value_name = "KEYFILE",
+	)]
+	pub encrypt: Vec<PathBuf>,
+
+	/// Decrypt the existing Zarc and bases with the key in this file.
+	#[arg(long,
+		value_hint = ValueHint::FilePath,
+		value_name = "KEYFILE",
+	)]
+	pub decrypt: Option<PathBuf>,
+}
+
+/// A compression level for files matching a glob.
+#[derive(Debug, Clone)]
+pub struct LevelRule {
+	glob: Glob,
+	level: Option<i32>,
+}
+
+fn parse_level_rule(value: &str) -> Result<LevelRule, String> {
+	let (glob, level) = value
+		.rsplit_once('=')
+		.ok_or_else(|| "expected a GLOB=LEVEL pair".to_string())?;
+	let glob = Glob::new(glob).map_err(|err| err.to_string())?;
+	let level = match level {
+		"store" => None,
+		level => {
+			let level: i32 = level
+				.parse()
+				.map_err(|_| format!("expected a compression level or 'store', got {level:?}"))?;
+			if !((-1 << 17)..22).contains(&level) {
+				return Err(format!("compression level {level} is out of range"));
+			}
+			Some(level)
+		}
+	};
+	Ok(LevelRule { glob, level })
+}
+
+/// Compression policy for packing: rules by glob, then the automatic policy if enabled.
+struct PackPolicy {
+	rules: Vec<(GlobMatcher, Option<i32>)>,
+	auto: Option<AutoCompression>,
+}
+
+impl CompressionPolicy for PackPolicy {
+	fn compression(&mut self, file: Option<&zarc::directory::File>, content: &[u8]) -> Compression {
+		if let Some(file) = file {
+			let path = file.name.to_path();
+			if let Some((_, level)) = self.rules.iter().find(|(glob, _)| glob.is_match(&path)) {
+				return match level {
+					Some(level) => Compression::With(vec![ZstdParameter::CompressionLevel(*level)]),
+					None => Compression::Store,
+				};
+			}
+		}
+
+		match &mut self.auto {
+			Some(auto) => auto.compression(file, content),
+			None => Compression::Compress,
+		}
+	}
 }
 
 #[derive(Clone)]
@@ -217,11 +518,114 @@ impl clap::builder::TypedValueParser for ParseZstdParam {
 }
 
 pub(crate) fn pack(args: PackArgs) -> std::io::Result<()> {
-	info!(path=?args.output, "create output file");
-	let mut file = File::create(args.output)?;
+	let source_date = source_date_epoch()?;
+	if args.clamp_mtime && source_date.is_none() {
+		return Err(Error::other(
+			"--clamp-mtime requires SOURCE_DATE_EPOCH to be set",
+		));
+	}
+
+	let decrypt = args
+		.decrypt
+		.as_deref()
+		.or_else(|| args.encrypt.first().map(PathBuf::as_path));
+
+	let mut existing = None;
+	let mut staged = None;
+	let mut file = if (args.append || args.incremental) && args.output.exists() {
+		info!(path=?args.output, "read existing zarc");
+		let mut zarc = open_zarc(args.output.clone(), decrypt).map_err(Error::other)?;
+		zarc.read_directory().map_err(Error::other)?;
+		if !args.encrypt.is_empty() && !zarc.is_encrypted() {
+			return Err(Error::other(
+				"can't encrypt when appending to an unencrypted zarc",
+			));
+		}
+		for path in &args.encrypt {
+			if !zarc.opens_with(&read_encryption_key(path)?) {
+				return Err(Error::other(format!(
+					"key in {path:?} doesn't open the existing zarc, and keys can't be added to it"
+				)));
+			}
+		}
+
+		let copy = Staged::next_to(&args.output)?;
+		info!(path=?copy.path, "copy existing zarc");
+		std::fs::copy(&args.output, &copy.path)?;
+
+		info!("truncate existing directory");
+		let mut file = OpenOptions::new().write(true).open(&copy.path)?;
+		file.set_len(u64::try_from(zarc.trailer().directory_offset).map_err(Error::other)?)?;
+		file.seek(SeekFrom::End(0))?;
+
+		existing = Some(zarc);
+		staged = Some(copy);
+		file
+	} else {
+		info!(path=?args.output, "create output file");
+		File::create(&args.output)?
+	};
+
+	// the latest version of every file in the existing zarc, and its size
+	let mut unseen: BTreeMap<Pathname, (zarc::directory::File, Option<u64>)> =
+		match (&existing, args.incremental) {
+			(Some(zarc), true) => zarc
+				.latest_files()
+				.map(|entry| {
+					let size = zarc.content_size(entry);
+					(entry.name.clone(), (entry.clone(), size))
+				})
+				.collect(),
+			_ => BTreeMap::new(),
+		};
 
 	info!("initialise encoder");
-	let mut zarc = Encoder::new(&mut file)?;
+	let mut zarc = if let Some(existing) = &existing {
+		Encoder::append(&mut file, existing)?
+	} else if !args.encrypt.is_empty() {
+		let keys = args
+			.encrypt
+			.iter()
+			.map(|path| read_encryption_key(path))
+			.collect::<std::io::Result<Vec<_>>>()?;
+		Encoder::new_encrypted(&mut file, &keys)?
+	} else {
+		Encoder::new(&mut file)?
+	};
+
+	match source_date {
+		Some(timestamp) => {
+			debug!(%timestamp, "timestamp edition with SOURCE_DATE_EPOCH");
+			zarc.set_written_at(timestamp);
+		}
+		None if args.reproducible => {
+			debug!("timestamp edition with the Unix epoch");
+			zarc.set_written_at(Timestamp(DateTime::UNIX_EPOCH));
+		}
+		None => {}
+	}
+
+	for path in &args.sign {
+		info!(?path, "read signing key");
+		zarc.sign_with(read_signing_key(path)?);
+	}
+
+	for path in &args.base {
+		info!(?path, "read base zarc");
+		let mut base = open_zarc(path.clone(), decrypt).map_err(Error::other)?;
+		base.read_directory().map_err(Error::other)?;
+		zarc.add_base(&base, path.file_name().map(CborString::from))?;
+
+		// content of the existing zarc may be in the base, like references for delta compression
+		if let Some(existing) = &mut existing {
+			if existing
+				.externals()
+				.any(|external| external.digest == base.trailer().digest)
+			{
+				existing.add_base(base).map_err(Error::other)?;
+			}
+		}
+	}
 
 	debug!("enable zstd checksums");
 	zarc.set_zstd_parameter(ZstdParameter::ChecksumFlag(true))?;
@@ -236,14 +640,84 @@ pub(crate) fn pack(args: PackArgs) -> std::io::Result<()> {
 		zarc.set_zstd_parameter(param)?;
 	}
 
+	if let Some(size) = args.chunk_size {
+		debug!(%size, "set average chunk size");
+		zarc.set_chunk_sizes(ChunkSizes::around(size))?;
+	}
+
+	if let Some(size) = args.solid_block_size {
+		debug!(%size, "set solid block size");
+		zarc.set_solid_block_size(usize::try_from(size).map_err(Error::other)?)?;
+	}
+
+	if let Some(size) = args.outboard_above {
+		debug!(%size, "set outboard threshold");
+		zarc.set_outboard_threshold(Some(usize::try_from(size).map_err(Error::other)?));
+	}
+
+	if let Some(size) = args.train_dict {
+		info!(%size, "train dictionary");
+		let samples = collect_samples(&args.paths, args.follow_symlinks, size * 100)?;
+		match train_dictionary(&samples, size) {
+			Ok(dictionary) => {
+				zarc.set_dictionary(&dictionary)?;
+			}
+			Err(err) => {
+				warn!(%err, "could not train dictionary, continuing without");
+			}
+		}
+	}
+
 	if args.store {
 		debug!("disable compression for content");
 		zarc.enable_compression(false);
+	} else if !args.compress_all || !args.level_for.is_empty() {
+		let mut policy = AutoCompression::default();
+		policy.store_extensions.extend(
+			args.store_ext
+				.iter()
+				.map(|ext| ext.trim_start_matches('.').to_ascii_lowercase()),
+		);
+		policy.compress_extensions.extend(
+			args.compress_ext
+				.iter()
+				.map(|ext| ext.trim_start_matches('.').to_ascii_lowercase()),
+		);
+		policy
+			.store_mime_types
+			.extend(args.store_mime.iter().cloned());
+		if let Some(size) = args.compress_below {
+			policy.compress_below = size;
+		}
+		if let Some(gain) = args.min_gain {
+			policy.min_gain = f64::from(gain) / 100.0;
+		}
+
+		let rules = args
+			.level_for
+			.iter()
+			.map(|rule| (rule.glob.compile_matcher(), rule.level))
+			.collect();
+		let auto = (!args.compress_all).then_some(policy);
+		debug!(?auto, "store content that doesn't compress well");
+		zarc.set_compression_policy(PackPolicy { rules, auto });
 	}
 
+	// where content packed in this run came from, to use it as reference for delta compression
+	let mut packed: HashMap<Digest, PathBuf> = HashMap::new();
+
+	let seekable = args
+		.seekable
+		.map(usize::try_from)
+		.transpose()
+		.map_err(Error::other)?;
+
 	for path in &args.paths {
 		info!("walk {path:?}");
-		for entry in WalkDir::new(path).follow_links(args.follow_symlinks) {
+		for entry in WalkDir::new(path)
+			.follow_links(args.follow_symlinks)
+			.sort_by_file_name()
+		{
 			let entry = match entry {
 				Ok(file) => file,
 				Err(err) => {
@@ -256,17 +730,444 @@ pub(crate) fn pack(args: PackArgs) -> std::io::Result<()> {
 			debug!("read {filename:?}");
 
 			let mut file = zarc.build_file_with_metadata(filename, args.follow_symlinks)?;
-			if entry.file_type().is_file() {
-				let content = std::fs::read(filename)?;
-				file.digest(zarc.add_data_frame(&content)?);
+			if args.reproducible {
+				file.strip_host_metadata();
+			}
+			if let (true, Some(max)) = (args.clamp_mtime, source_date) {
+				file.clamp_modified(max);
+			}
+
+			let content = if entry.file_type().is_file() {
+				Some(std::fs::read(filename)?)
+			} else {
+				None
+			};
+
+			if let Some((previous, previous_size)) = unseen.remove(&file.0.name) {
+				if unchanged(&previous, previous_size, &file.0, content.as_deref()) {
+					debug!("unchanged since last edition, skip");
+					continue;
+				}
+			}
+
+			if let Some(content) = content {
+				// files larger than a block get their own frame(s)
+				if args.solid && zarc.fits_solid_block(content.len()) {
+					zarc.add_solid_file(file, &content)?;
+					continue;
+				}
+
+				let reference = if args.delta {
+					delta_reference(&zarc, &file.0.name, &content, existing.as_ref(), &packed)?
+				} else {
+					None
+				};
+
+				if args.chunked {
+					let (digest, chunks) = zarc.add_chunked_data_for(&file.0, &content)?;
+					file.chunks(digest, chunks);
+				} else if let Some(size) = seekable.filter(|size| content.len() > *size) {
+					let (digest, chunks) = zarc.add_seekable_data_for(&file.0, &content, size)?;
+					file.chunks(digest, chunks);
+				} else if let Some((reference, reference_content)) = reference {
+					debug!("compress against similar content");
+					file.digest(zarc.add_delta_frame_for(
+						&file.0,
+						&content,
+						&reference,
+						&reference_content,
+					)?);
+				} else {
+					file.digest(zarc.add_data_frame_for(&file.0, &content)?);
+				}
+
+				if args.delta {
+					if let Some(digest) = &file.0.digest {
+						packed.insert(digest.clone(), filename.to_path_buf());
+					}
+				}
 			}
 			zarc.add_file_entry(file)?;
 		}
 	}
 
+	if args.incremental {
+		let roots: Vec<_> = args
+			.paths
+			.iter()
+			.map(|path| Pathname::from_normal_components(path))
+			.collect();
+		for name in unseen.into_keys() {
+			if roots.iter().any(|root| name.0.starts_with(&root.0)) {
+				debug!(?name, "deleted since last edition, add tombstone");
+				let mut file = zarc.build_file(name);
+				file.tombstone();
+				zarc.add_file_entry(file)?;
+			}
+		}
+	}
+
 	info!("finalising zarc");
 	let digest = zarc.finalise()?;
 
+	if let Some(staged) = staged {
+		file.sync_all()?;
+		info!(path=?args.output, "replace existing zarc");
+		staged.replace()?;
+	}
+
 	println!("digest: {}", Base64::encode_string(&digest));
 	Ok(())
 }
+
+/// A copy of a file being written, which replaces the original once done.
+///
+/// If it's dropped before then, the copy is removed and the original is left untouched.
+struct Staged {
+	path: PathBuf,
+	original: PathBuf,
+	done: bool,
+}
+
+impl Staged {
+	/// Pick a path for a copy of a file, in the same directory so it can be renamed over it.
+	fn next_to(original: &Path) -> std::io::Result<Self> {
+		let Some(name) = original.file_name() else {
+			return Err(Error::other(format!("{original:?} is not a file")));
+		};
+
+		let mut staged_name = OsString::from(".");
+		staged_name.push(name);
+		staged_name.push(format!(".{}.tmp", std::process::id()));
+		Ok(Self {
+			path: original.with_file_name(staged_name),
+			original: original.to_path_buf(),
+			done: false,
+		})
+	}
+
+	/// Replace the original with the copy.
+	fn replace(mut self) -> std::io::Result<()> {
+		std::fs::rename(&self.path, &self.original)?;
+		self.done = true;
+		Ok(())
+	}
+}
+
+impl Drop for Staged {
+	fn drop(&mut self) {
+		if !self.done {
+			debug!(path=?self.path, "remove unfinished copy");
+			let _ = std::fs::remove_file(&self.path);
+		}
+	}
+}
+
+/// Read the SOURCE_DATE_EPOCH environment variable, if set.
+///
+/// See <https://reproducible-builds.org/specs/source-date-epoch/>.
+fn source_date_epoch() -> std::io::Result<Option<Timestamp>> {
+	let Some(value) = std::env::var_os("SOURCE_DATE_EPOCH") else {
+		return Ok(None);
+	};
+
+	value
+		.to_str()
+		.and_then(|value| value.trim().parse::<i64>().ok())
+		.and_then(|seconds| DateTime::<Utc>::from_timestamp(seconds, 0))
+		.map(|datetime| Some(Timestamp(datetime)))
+		.ok_or_else(|| Error::other(format!("invalid SOURCE_DATE_EPOCH: {value:?}")))
+}
+
+/// Find a frame to compress a file against, and get its content.
+fn delta_reference(
+	zarc: &Encoder<'_, File>,
+	name: &Pathname,
+	content: &[u8],
+	existing: Option<&Decoder<PathBuf>>,
+	packed: &HashMap<Digest, PathBuf>,
+) -> std::io::Result<Option<(Digest, Vec<u8>)>> {
+	let Some(frame) = zarc.delta_reference(name, content.len() as _) else {
+		return Ok(None);
+	};
+	let digest = frame.digest.clone();
+
+	// content packed in this run is read back from disk, if it hasn't changed since
+	if let Some(path) = packed.get(&digest) {
+		let reference = std::fs::read(path)?;
+		if blake3::hash(&reference).as_bytes() == digest.as_slice() {
+			return Ok(Some((digest, reference)));
+		}
+	}
+
+	if let Some(Some(mut frame)) = existing
+		.map(|zarc| zarc.read_content_frame(&digest))
+		.transpose()
+		.map_err(Error::other)?
+	{
+		let mut reference = Vec::with_capacity(frame.uncompressed_size() as _);
+		for bytes in &mut frame {
+			reference.extend(bytes.map_err(Error::other)?);
+		}
+		if frame.verify().unwrap_or(false) {
+			return Ok(Some((digest, reference)));
+		}
+	}
+
+	Ok(None)
+}
+
+/// Read small files to use as samples for training a dictionary, up to `budget` bytes in total.
+fn collect_samples(
+	paths: &[PathBuf],
+	follow_symlinks: bool,
+	budget: usize,
+) -> std::io::Result<Vec<Vec<u8>>> {
+	const MAX_SAMPLE: u64 = 128 * 1024;
+
+	let mut samples = Vec::new();
+	let mut total = 0;
+	for path in paths {
+		for entry in WalkDir::new(path)
+			.follow_links(follow_symlinks)
+			.sort_by_file_name()
+		{
+			let Ok(entry) = entry else {
+				continue;
+			};
+
+			if !entry.file_type().is_file() || entry.metadata()?.len() > MAX_SAMPLE {
+				continue;
+			}
+
+			let content = std::fs::read(entry.path())?;
+			total += content.len();
+			samples.push(content);
+			if total >= budget {
+				debug!(samples=%samples.len(), %total, "collected enough samples");
+				return Ok(samples);
+			}
+		}
+	}
+
+	debug!(samples=%samples.len(), %total, "collected all available samples");
+	Ok(samples)
+}
+
+/// Check whether a file has changed since it was last packed.
+///
+/// This compares size, modification time, mode, and special file type and target, and if those
+/// match, the digest of the content, as a file can be modified without changing its mtime.
+fn unchanged(
+	previous: &zarc::directory::File,
+	previous_size: Option<u64>,
+	current: &zarc::directory::File,
+	content: Option<&[u8]>,
+) -> bool {
+	previous_size == content.map(|content| content.len() as u64)
+		&& previous.mode == current.mode
+		&& previous.special == current.special
+		&& previous.timestamps.as_ref().and_then(|ts| ts.modified)
+			== current.timestamps.as_ref().and_then(|ts| ts.modified)
+		&& content.is_none_or(|content| {
+			previous
+				.digest
+				.as_ref()
+				.is_some_and(|digest| blake3::hash(content).as_bytes() == digest.as_slice())
+		})
+}
+
+#[test]
+fn interrupted_append_keeps_previous_edition() {
+	let dir = std::env::temp_dir().join(format!("zarc-append-{}", std::process::id()));
+	let content = dir.join("content");
+	std::fs::create_dir_all(&content).expect("create content dir");
+	std::fs::write(content.join("first.txt"), "first edition").expect("write file");
+	let output = dir.join("test.zarc");
+
+	let args = |extra: &[&str]| {
+		let mut args = vec!["pack", "--output", output.to_str().expect("utf-8 path")];
+		args.extend_from_slice(extra);
+		args.push(content.to_str().expect("utf-8 path"));
+		PackArgs::try_parse_from(args).expect("parse args")
+	};
+
+	pack(args(&[])).expect("pack first edition");
+	let before = std::fs::read(&output).expect("read zarc");
+
+	// fails after the encoder has started, when reading the signing key
+	std::fs::write(content.join("second.txt"), "second edition").expect("write file");
+	let missing = dir.join("missing.key");
+	pack(args(&[
+		"--append",
+		"--sign",
+		missing.to_str().expect("utf-8 path"),
+	]))
+	.expect_err("append with a missing key");
+
+	assert_eq!(std::fs::read(&output).expect("read zarc"), before);
+	let leftovers = std::fs::read_dir(&dir)
+		.expect("list dir")
+		.filter(|entry| {
+			entry.as_ref().map_or(true, |entry| {
+				entry.file_name().to_string_lossy().ends_with(".tmp")
+			})
+		})
+		.count();
+	assert_eq!(leftovers, 0, "the unfinished copy is removed");
+
+	let mut zarc = Decoder::open(output.clone()).expect("open zarc");
+	zarc.read_directory().expect("read directory");
+	assert_eq!(zarc.editions().count(), 1);
+
+	pack(args(&["--append"])).expect("append second edition");
+	let mut zarc = Decoder::open(output.clone()).expect("open zarc");
+	zarc.read_directory().expect("read directory");
+	assert_eq!(zarc.editions().count(), 2);
+
+	std::fs::remove_dir_all(&dir).expect("clean up");
+}
+
+#[test]
+fn incremental_notices_content_changes() {
+	use std::time::{Duration, SystemTime};
+
+	let dir = std::env::temp_dir().join(format!("zarc-incremental-{}", std::process::id()));
+	let content = dir.join("content");
+	std::fs::create_dir_all(&content).expect("create content dir");
+	let changed = content.join("changed.txt");
+	let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+	let write = |text: &str| {
+		std::fs::write(&changed, text).expect("write file");
+		std::fs::File::options()
+			.write(true)
+			.open(&changed)
+			.and_then(|file| file.set_modified(mtime))
+			.expect("set mtime");
+	};
+	std::fs::write(content.join("same.txt"), "same").expect("write file");
+	write("before");
+	let output = dir.join("test.zarc");
+
+	let args = || {
+		PackArgs::try_parse_from([
+			"pack",
+			"--incremental",
+			"--output",
+			output.to_str().expect("utf-8 path"),
+			content.to_str().expect("utf-8 path"),
+		])
+		.expect("parse args")
+	};
+	pack(args()).expect("pack first edition");
+
+	// same size and mtime, different content
+	write("after!");
+	pack(args()).expect("pack second edition");
+
+	let mut zarc = Decoder::open(output.clone()).expect("open zarc");
+	zarc.read_directory().expect("read directory");
+	let name = |path: &std::path::Path| Pathname::from_normal_components(path);
+	assert_eq!(
+		zarc.files_by_name(name(&content.join("same.txt")))
+			.expect("same.txt")
+			.len(),
+		1,
+		"unchanged file is skipped"
+	);
+	let file = zarc.latest_file(name(&changed)).expect("changed.txt");
+	assert_eq!(
+		zarc.read_at(file, 0, 64).expect("read changed.txt"),
+		b"after!"
+	);
+
+	std::fs::remove_dir_all(&dir).expect("clean up");
+}
+
+#[test]
+fn store_conflicts_with_level_rules() {
+	let err = PackArgs::try_parse_from([
+		"pack",
+		"--output",
+		"test.zarc",
+		"--store",
+		"--level-for",
+		"*.txt=3",
+		"content",
+	])
+	.expect_err("conflicting args");
+	assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+}
+
+#[cfg(unix)]
+#[test]
+fn reproducible_packs_are_identical() {
+	use std::{
+		fs::{self, FileTimes},
+		os::unix::fs::chown,
+		time::{Duration, SystemTime},
+	};
+
+	let dir = std::env::temp_dir().join(format!("zarc-reproducible-{}", std::process::id()));
+	let content = dir.join("content");
+	fs::create_dir_all(content.join("sub")).expect("create content dir");
+	let files = [("a.txt", "alpha"), ("sub/b.txt", "bravo")];
+	for (path, text) in files {
+		fs::write(content.join(path), text).expect("write file");
+	}
+
+	let pack_to = |name: &str, root: &std::path::Path| {
+		let output = dir.join(name);
+		pack(
+			PackArgs::try_parse_from([
+				"pack",
+				"--reproducible",
+				"--output",
+				output.to_str().expect("utf-8 path"),
+				root.to_str().expect("utf-8 path"),
+			])
+			.expect("parse args"),
+		)
+		.expect("pack");
+		fs::read(output).expect("read zarc")
+	};
+	let first = pack_to("first.zarc", &content);
+
+	let accessed = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+	for (path, _) in files {
+		let path = content.join(path);
+		fs::File::open(&path)
+			.and_then(|file| file.set_times(FileTimes::new().set_accessed(accessed)))
+			.expect("set atime");
+		// changing owners needs privileges, so this only changes them where it can
+		chown(&path, Some(1), Some(1)).ok();
+	}
+	let second = pack_to("second.zarc", &dir.join(".").join("content/"));
+
+	assert_eq!(first, second);
+	fs::remove_dir_all(&dir).expect("clean up");
+}
+
+#[test]
+fn clamp_mtime_requires_source_date_epoch() {
+	// the environment is shared with other tests, so this doesn't unset it
+	if std::env::var_os("SOURCE_DATE_EPOCH").is_some() {
+		return;
+	}
+
+	let dir = std::env::temp_dir().join(format!("zarc-clamp-{}", std::process::id()));
+	let output = dir.join("test.zarc");
+	let err = pack(
+		PackArgs::try_parse_from([
+			"pack",
+			"--clamp-mtime",
+			"--output",
+			output.to_str().expect("utf-8 path"),
+			dir.to_str().expect("utf-8 path"),
+		])
+		.expect("parse args"),
+	)
+	.expect_err("no SOURCE_DATE_EPOCH");
+	assert!(err.to_string().contains("SOURCE_DATE_EPOCH"));
+	assert!(!output.exists());
+}
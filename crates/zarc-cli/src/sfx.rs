@@ -0,0 +1,217 @@
+use std::{
+	env::current_exe,
+	fs::{self, File, OpenOptions},
+	io::{copy, Read, Seek, SeekFrom},
+	path::{Path, PathBuf},
+};
+
+use clap::{Parser, ValueHint};
+use miette::{bail, IntoDiagnostic};
+use tracing::info;
+use zarc::{decode::Decoder, ondemand::SubRange, ZARC_MAGIC};
+
+use crate::{
+	keys::open_zarc,
+	unpack::{unpack_zarc, UnpackOptions},
+};
+
+#[derive(Debug, Clone, Parser)]
+pub struct SfxArgs {
+	/// Zarc to make self-extracting.
+	#[arg(
+		value_hint = ValueHint::FilePath,
+		value_name = "PATH",
+	)]
+	pub input: PathBuf,
+
+	/// Output file.
+	///
+	/// This is the extractor followed by the Zarc. Running it unpacks the Zarc, and takes the same
+	/// options as 'zarc unpack', like '--destination' and '--filter'.
+	#[arg(long,
+		value_hint = ValueHint::FilePath,
+		value_name = "PATH",
+	)]
+	pub output: PathBuf,
+
+	/// Extractor program.
+	///
+	/// Defaults to this zarc program. Use a zarc built for the machines which will run the output.
+	#[arg(long,
+		value_hint = ValueHint::FilePath,
+		value_name = "PATH",
+	)]
+	pub extractor: Option<PathBuf>,
+}
+
+/// A self-extracting Zarc.
+///
+/// Run this program to unpack the Zarc which is appended to it.
+#[derive(Debug, Clone, Parser)]
+#[command(version)]
+pub struct SelfExtractArgs {
+	#[command(flatten)]
+	pub options: UnpackOptions,
+}
+
+pub(crate) fn sfx(args: SfxArgs) -> miette::Result<()> {
+	let extractor = match args.extractor {
+		Some(path) => path,
+		None => current_exe().into_diagnostic()?,
+	};
+	if Decoder::locate(&extractor).is_ok() {
+		bail!("the extractor already has a zarc appended: use a plain zarc program");
+	}
+
+	info!(input=?args.input, "check zarc");
+	Decoder::open(args.input.clone())?;
+
+	info!(?extractor, output=?args.output, "write extractor");
+	// this also copies the extractor's permissions, so the output is executable
+	let extractor_length = fs::copy(&extractor, &args.output).into_diagnostic()?;
+
+	info!("append zarc");
+	let mut output = OpenOptions::new()
+		.append(true)
+		.open(&args.output)
+		.into_diagnostic()?;
+	let mut input = File::open(&args.input).into_diagnostic()?;
+	copy(&mut input, &mut output).into_diagnostic()?;
+	output.sync_all().into_diagnostic()?;
+
+	let range = Decoder::locate(&args.output)?;
+	if range.start != extractor_length {
+		bail!(
+			"the zarc was found at byte {} instead of {extractor_length}",
+			range.start
+		);
+	}
+
+	eprintln!(
+		"wrote self-extracting zarc ({extractor_length} bytes of extractor, {} bytes of zarc)",
+		range.end - range.start
+	);
+	Ok(())
+}
+
+/// Find a Zarc appended to this program.
+///
+/// This runs on every invocation, so it first checks that the program ends with the Zarc magic,
+/// which most don't, before looking for the Zarc.
+pub(crate) fn embedded() -> Option<SubRange<PathBuf>> {
+	embedded_in(current_exe().ok()?)
+}
+
+/// Find a Zarc appended to a program.
+fn embedded_in(exe: PathBuf) -> Option<SubRange<PathBuf>> {
+	if !ends_with_magic(&exe).unwrap_or(false) {
+		return None;
+	}
+
+	let range = Decoder::locate(&exe).ok()?;
+	Some(SubRange::new(exe, range.start, range.end - range.start))
+}
+
+/// Whether a file ends with the Zarc magic, as a Zarc's trailer does.
+fn ends_with_magic(path: &Path) -> std::io::Result<bool> {
+	let mut file = File::open(path)?;
+	let mut end = [0; ZARC_MAGIC.len()];
+	file.seek(SeekFrom::End(-(end.len() as i64)))?;
+	file.read_exact(&mut end)?;
+	Ok(end == ZARC_MAGIC)
+}
+
+/// Unpack the Zarc appended to this program.
+pub(crate) fn self_extract(source: SubRange<PathBuf>, args: SelfExtractArgs) -> miette::Result<()> {
+	let decrypt = args.options.decrypt.as_deref();
+	let zarc = open_zarc(source, decrypt)?;
+
+	// bases may be standalone or self-extracting zarcs themselves
+	unpack_zarc(zarc, &args.options, |path| {
+		let range = Decoder::locate(&path)?;
+		open_zarc(
+			SubRange::new(path, range.start, range.end - range.start),
+			decrypt,
+		)
+	})
+}
+
+#[test]
+fn builds_and_finds_sfx() {
+	use std::path::Component;
+
+	use crate::pack::{pack, PackArgs};
+
+	let dir = std::env::temp_dir().join(format!("zarc-sfx-{}", std::process::id()));
+	let content = dir.join("content");
+	fs::create_dir_all(&content).expect("create content dir");
+	fs::write(content.join("file.txt"), "self-extracted").expect("write file");
+	let stub = dir.join("stub");
+	fs::write(&stub, "#!/bin/sh\nexit 0\n".repeat(100)).expect("write stub");
+	let zarc = dir.join("test.zarc");
+	let output = dir.join("test.sfx");
+	let path = |path: &Path| path.to_str().expect("utf-8 path").to_owned();
+
+	pack(
+		PackArgs::try_parse_from(["pack", "--output", &path(&zarc), &path(&content)])
+			.expect("parse args"),
+	)
+	.expect("pack");
+
+	// a plain program isn't mistaken for an sfx, even a tiny one
+	assert!(!ends_with_magic(&stub).expect("read stub"));
+	assert!(embedded_in(stub.clone()).is_none());
+	let tiny = dir.join("tiny");
+	fs::write(&tiny, "#!").expect("write tiny");
+	assert!(embedded_in(tiny).is_none());
+
+	sfx(SfxArgs::try_parse_from([
+		"sfx",
+		"--extractor",
+		&path(&stub),
+		"--output",
+		&path(&output),
+		&path(&zarc),
+	])
+	.expect("parse args"))
+	.expect("sfx");
+
+	let stub_length = fs::metadata(&stub).expect("stub metadata").len();
+	let zarc_length = fs::metadata(&zarc).expect("zarc metadata").len();
+	assert!(ends_with_magic(&output).expect("read output"));
+	let source = embedded_in(output.clone()).expect("find embedded zarc");
+	assert_eq!(
+		Decoder::locate(&output).expect("locate"),
+		stub_length..(stub_length + zarc_length)
+	);
+
+	let destination = dir.join("unpacked");
+	self_extract(
+		source,
+		SelfExtractArgs::try_parse_from(["sfx", "--destination", &path(&destination)])
+			.expect("parse args"),
+	)
+	.expect("self extract");
+	let unpacked: PathBuf = content
+		.components()
+		.filter(|component| matches!(component, Component::Normal(_)))
+		.collect();
+	assert_eq!(
+		fs::read_to_string(destination.join(unpacked).join("file.txt")).expect("read unpacked"),
+		"self-extracted"
+	);
+
+	// an sfx can't be the extractor for another
+	sfx(SfxArgs::try_parse_from([
+		"sfx",
+		"--extractor",
+		&path(&output),
+		"--output",
+		&path(&dir.join("double.sfx")),
+		&path(&zarc),
+	])
+	.expect("parse args"))
+	.expect_err("extractor already has a zarc");
+
+	fs::remove_dir_all(&dir).expect("clean up");
+}
@@ -0,0 +1,81 @@
+use std::{
+	fs::File,
+	io::{Read, Result, Seek, SeekFrom},
+	ops::Range,
+	path::PathBuf,
+};
+
+use zarc::ondemand::OnDemand;
+#[cfg(feature = "http")]
+use zarc::ondemand::{HttpReader, HttpSource};
+
+/// Where a Zarc is read from.
+///
+/// This is a file, or with the `http` feature, an `http://` or `https://` URL.
+#[derive(Clone, Debug)]
+pub(crate) enum Source {
+	File(PathBuf),
+	#[cfg(feature = "http")]
+	Http(HttpSource),
+}
+
+impl Source {
+	pub(crate) fn new(input: PathBuf) -> Result<Self> {
+		#[cfg(feature = "http")]
+		if let Some(url) = input
+			.to_str()
+			.filter(|input| input.starts_with("http://") || input.starts_with("https://"))
+		{
+			return HttpSource::new(url).map(Self::Http);
+		}
+
+		Ok(Self::File(input))
+	}
+}
+
+impl OnDemand for Source {
+	type Reader = SourceReader;
+
+	fn open(&self) -> Result<Self::Reader> {
+		match self {
+			Self::File(path) => path.open().map(SourceReader::File),
+			#[cfg(feature = "http")]
+			Self::Http(source) => source.open().map(SourceReader::Http),
+		}
+	}
+
+	fn open_range(&self, range: Range<u64>) -> Result<Self::Reader> {
+		match self {
+			Self::File(path) => path.open_range(range).map(SourceReader::File),
+			#[cfg(feature = "http")]
+			Self::Http(source) => source.open_range(range).map(SourceReader::Http),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub(crate) enum SourceReader {
+	File(File),
+	#[cfg(feature = "http")]
+	Http(HttpReader),
+}
+
+impl Read for SourceReader {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+		match self {
+			Self::File(file) => file.read(buf),
+			#[cfg(feature = "http")]
+			Self::Http(reader) => reader.read(buf),
+		}
+	}
+}
+
+impl Seek for SourceReader {
+	fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+		match self {
+			Self::File(file) => file.seek(pos),
+			#[cfg(feature = "http")]
+			Self::Http(reader) => reader.seek(pos),
+		}
+	}
+}
@@ -1,7 +1,8 @@
 use std::{
+	collections::BTreeSet,
 	fs::{create_dir_all, DirBuilder, File},
-	io::Write,
-	path::PathBuf,
+	io::{copy, ErrorKind},
+	path::{Path, PathBuf},
 };
 
 use base64ct::{Base64, Encoding};
@@ -10,11 +11,14 @@ use miette::{bail, IntoDiagnostic};
 use regex::Regex;
 use tracing::{error, info, warn};
 use zarc::{
-	decode::Decoder,
+	decode::{error::Result as DecodeResult, Decoder},
 	integrity::Digest,
 	metadata::decode::{set_ownership, set_permissions, set_timestamps},
+	ondemand::OnDemand,
 };
 
+use crate::{keys::open_zarc, source::Source};
+
 #[derive(Debug, Clone, Parser)]
 pub struct UnpackArgs {
 	/// Input file.
@@ -24,6 +28,22 @@ pub struct UnpackArgs {
 	)]
 	pub input: PathBuf,
 
+	#[command(flatten)]
+	pub options: UnpackOptions,
+}
+
+/// Options for unpacking, which self-extracting Zarcs also take.
+#[derive(Debug, Clone, clap::Args)]
+pub struct UnpackOptions {
+	/// Directory to unpack into.
+	///
+	/// Defaults to the current directory. It's created if it doesn't exist.
+	#[arg(long, short = 'C',
+		value_hint = ValueHint::DirPath,
+		value_name = "DIR",
+	)]
+	pub destination: Option<PathBuf>,
+
 	/// Filter files by name (with a regex).
 	///
 	/// Can be given multiple times, and files will be matched if they match any of the regexes.
@@ -33,14 +53,45 @@ pub struct UnpackArgs {
 	/// Verify that the Zarc directory matches the given digest.
 	#[arg(long, value_name = "DIGEST")]
 	pub verify: Option<String>,
+
+	/// Base Zarc to read content from, when unpacking a thin Zarc.
+	///
+	/// Can be given multiple times.
+	#[arg(long,
+		value_hint = ValueHint::AnyPath,
+		value_name = "PATH",
+	)]
+	pub base: Vec<PathBuf>,
+
+	/// Decrypt the Zarc (and its bases) with the key in this file.
+	///
+	/// The file contains the base64 of a 32-byte key, as given to 'zarc pack --encrypt'.
+	#[arg(long,
+		value_hint = ValueHint::FilePath,
+		value_name = "KEYFILE",
+	)]
+	pub decrypt: Option<PathBuf>,
 }
 
 pub(crate) fn unpack(args: UnpackArgs) -> miette::Result<()> {
 	info!("initialise decoder");
-	let mut zarc = Decoder::open(args.input)?;
+	let decrypt = args.options.decrypt.as_deref();
+	let zarc = open_zarc(Source::new(args.input).into_diagnostic()?, decrypt)?;
+	unpack_zarc(zarc, &args.options, |path| {
+		open_zarc(Source::new(path)?, decrypt)
+	})
+}
 
-	if let Some(string) = args.verify {
-		let expected = Digest(Base64::decode_vec(&string).into_diagnostic()?);
+/// Unpack an opened Zarc.
+///
+/// Bases given in the options are opened with `open_base`.
+pub(crate) fn unpack_zarc<R: OnDemand>(
+	mut zarc: Decoder<R>,
+	options: &UnpackOptions,
+	open_base: impl Fn(PathBuf) -> DecodeResult<Decoder<R>>,
+) -> miette::Result<()> {
+	if let Some(string) = &options.verify {
+		let expected = Digest(Base64::decode_vec(string).into_diagnostic()?);
 		if expected != zarc.trailer().digest {
 			bail!(
 				"integrity failure: zarc file digest is {}",
@@ -52,21 +103,57 @@ pub(crate) fn unpack(args: UnpackArgs) -> miette::Result<()> {
 	}
 
 	zarc.read_directory()?;
+
+	for path in &options.base {
+		info!(?path, "read base zarc");
+		let mut base = open_base(path.clone())?;
+		base.read_directory()?;
+		zarc.add_base(base)?;
+	}
+
+	let selected = |entry: &zarc::directory::File| {
+		let name = entry.name.to_path().display().to_string();
+		options.filter.is_empty() || options.filter.iter().any(|filter| filter.is_match(&name))
+	};
+
+	// only the bases holding content of the files being unpacked are needed
+	let missing: BTreeSet<_> = zarc
+		.latest_files()
+		.filter(|entry| entry.is_normal() && selected(entry))
+		.flat_map(|entry| zarc.missing_bases_for(entry))
+		.map(|external| {
+			external.name.as_ref().map_or_else(
+				|| Base64::encode_string(&external.digest),
+				|name| name.to_os_string().to_string_lossy().into_owned(),
+			)
+		})
+		.collect();
+	if !missing.is_empty() {
+		bail!(
+			"this is a thin zarc: provide its base with --base (missing {})",
+			missing.into_iter().collect::<Vec<_>>().join(", ")
+		);
+	}
+
 	let zarc = zarc;
 
+	let destination = options.destination.clone().unwrap_or_default();
+	if options.destination.is_some() {
+		create_dir_all(&destination).into_diagnostic()?;
+	}
+
 	// zarc.frames().for_each(|frame| {
 	// 	info!(offset=%frame.offset, digest=%Base64::encode_string(frame.digest.as_slice()), "frame");
 	// });
 
 	let mut unpacked = 0_u64;
-	for entry in zarc.files() {
-		let name = entry.name.to_path().display().to_string();
-		if !args.filter.is_empty() && !args.filter.iter().any(|filter| filter.is_match(&name)) {
+	for entry in zarc.latest_files() {
+		if !selected(entry) {
 			continue;
 		}
 
 		if entry.is_dir() {
-			let path = entry.name.to_path();
+			let path = destination.join(entry.name.to_path());
 			info!(?path, "unpack dir");
 			let mut dir = DirBuilder::new();
 			dir.recursive(true);
@@ -80,10 +167,8 @@ pub(crate) fn unpack(args: UnpackArgs) -> miette::Result<()> {
 			let file = File::open(path).into_diagnostic()?;
 			set_metadata(entry, &file)?;
 		} else if entry.is_normal() {
-			if let Some(digest) = &entry.digest {
-				extract_file(entry, digest, &zarc)?;
-				unpacked += 1;
-			}
+			extract_file(entry, &zarc, &destination)?;
+			unpacked += 1;
 		}
 	}
 
@@ -91,32 +176,32 @@ pub(crate) fn unpack(args: UnpackArgs) -> miette::Result<()> {
 	Ok(())
 }
 
-fn extract_file(
+fn extract_file<R: OnDemand>(
 	entry: &zarc::directory::File,
-	digest: &zarc::integrity::Digest,
-	zarc: &Decoder<PathBuf>,
+	zarc: &Decoder<R>,
+	destination: &Path,
 ) -> miette::Result<()> {
-	info!(path=?entry.name.to_path(), digest=%Base64::encode_string(digest.as_slice()), "unpack file");
-	let path = entry.name.to_path();
+	info!(path=?entry.name.to_path(), digest=?entry.digest.as_ref().map(|digest| Base64::encode_string(digest.as_slice())), "unpack file");
+	let path = destination.join(entry.name.to_path());
 
 	if let Some(dir) = path.parent() {
 		// create parent dir just in case its entry wasn't in the zarc
 		create_dir_all(dir).into_diagnostic()?;
 	}
 
-	let Some(mut frame) = zarc.read_content_frame(digest).into_diagnostic()? else {
+	let Some(mut reader) = zarc.file_reader(entry).into_diagnostic()? else {
 		warn!("frame not found");
 		return Ok(());
 	};
 
 	let mut file = File::create(path).into_diagnostic()?;
 
-	for bytes in &mut frame {
-		file.write_all(&bytes.into_diagnostic()?)
-			.into_diagnostic()?;
-	}
-	if !frame.verify().unwrap_or(false) {
-		error!(path=?entry.name, "frame verification failed!");
+	match copy(&mut reader, &mut file) {
+		Ok(_) => {}
+		Err(err) if err.kind() == ErrorKind::InvalidData => {
+			error!(path=?entry.name, "frame verification failed!");
+		}
+		Err(err) => return Err(err).into_diagnostic(),
 	}
 
 	set_metadata(entry, &file)?;
@@ -0,0 +1,210 @@
+use std::path::PathBuf;
+
+use base64ct::{Base64, Encoding};
+use clap::{Parser, ValueHint};
+use miette::{bail, IntoDiagnostic};
+use tracing::info;
+use zarc::decode::EditionLink;
+
+use crate::{
+	keys::{open_zarc, read_verifying_key},
+	source::Source,
+};
+
+#[derive(Debug, Clone, Parser)]
+pub struct VerifyArgs {
+	/// Input file.
+	#[arg(
+		value_hint = ValueHint::AnyPath,
+		value_name = "PATH",
+	)]
+	pub input: PathBuf,
+
+	/// Require a valid signature from an Ed25519 public key.
+	///
+	/// Either a path to a file containing the key, in SPKI PEM format as generated by e.g.
+	/// 'openssl pkey -pubout', or the base64 of the raw 32-byte key; or that base64 directly.
+	///
+	/// Can be given multiple times, and all keys must have signed the Zarc.
+	#[arg(long, value_name = "PUBKEY")]
+	pub key: Vec<String>,
+
+	/// Only check the directory and signatures, not the content frames.
+	#[arg(long)]
+	pub skip_content: bool,
+
+	/// Decrypt the Zarc with the key in this file.
+	///
+	/// The file contains the base64 of a 32-byte key, as given to 'zarc pack --encrypt'.
+	#[arg(long,
+		value_hint = ValueHint::FilePath,
+		value_name = "KEYFILE",
+	)]
+	pub decrypt: Option<PathBuf>,
+}
+
+pub(crate) fn verify(args: VerifyArgs) -> miette::Result<()> {
+	let keys = args
+		.key
+		.iter()
+		.map(|key| read_verifying_key(key))
+		.collect::<std::io::Result<Vec<_>>>()
+		.into_diagnostic()?;
+
+	info!("initialise decoder");
+	let mut zarc = open_zarc(
+		Source::new(args.input).into_diagnostic()?,
+		args.decrypt.as_deref(),
+	)?;
+	zarc.read_directory()?;
+	let zarc = zarc;
+
+	let digest = &zarc.trailer().digest;
+	println!("digest: {}", Base64::encode_string(digest));
+
+	if !args.skip_content {
+		info!("verify content frames");
+		let mut frames = 0_u64;
+		for frame in zarc.frames() {
+			let Some(mut content) = zarc.read_content_frame(&frame.digest)? else {
+				continue;
+			};
+			for chunk in &mut content {
+				chunk?;
+			}
+			if content.verify() != Some(true) {
+				bail!(
+					"integrity failure: frame at offset {} doesn't match its digest {}",
+					frame.offset,
+					Base64::encode_string(&frame.digest)
+				);
+			}
+			frames += 1;
+		}
+		println!("content: {frames} frames ok");
+	}
+
+	info!("verify edition chain");
+	let mut broken = false;
+	for (edition, link) in zarc.verify_edition_chain()? {
+		let status = match link {
+			EditionLink::First => "first",
+			EditionLink::Verified => "chained to previous",
+			EditionLink::Unlinked => "not chained",
+			EditionLink::Broken => {
+				broken = true;
+				"BROKEN CHAIN"
+			}
+		};
+		println!("edition {edition}: {status}");
+	}
+	if broken {
+		bail!("integrity failure: the history of previous editions was rewritten");
+	}
+
+	let mut valid = 0_usize;
+	for signature in zarc.signatures() {
+		let key = Base64::encode_string(&signature.public_key);
+		let status = match signature.signer() {
+			None => "INVALID",
+			Some(_) if !zarc.signs_edition(signature) => "UNVERIFIED: not over its edition",
+			Some(_) if &signature.digest == digest => {
+				valid += 1;
+				"valid"
+			}
+			Some(_) => "valid for a previous edition",
+		};
+		println!(
+			"signature: edition {} key {key} {:?} {status}",
+			signature.edition, signature.scheme
+		);
+	}
+
+	if keys.is_empty() {
+		if valid == 0 {
+			println!("not signed");
+		}
+		return Ok(());
+	}
+
+	for key in &keys {
+		if !zarc.is_signed_by(key) {
+			bail!(
+				"signature failure: not signed by {}",
+				Base64::encode_string(key.as_bytes())
+			);
+		}
+	}
+
+	println!("signed by all {} keys", keys.len());
+	Ok(())
+}
+
+#[test]
+fn verifies_signatures_across_editions() {
+	use std::fs;
+
+	use zarc::integrity::SigningKey;
+
+	use crate::pack::{pack, PackArgs};
+
+	let dir = std::env::temp_dir().join(format!("zarc-verify-{}", std::process::id()));
+	let content = dir.join("content");
+	fs::create_dir_all(&content).expect("create content dir");
+	fs::write(content.join("file.txt"), "signed").expect("write file");
+	let output = dir.join("test.zarc");
+
+	let keys: Vec<_> = [1, 2]
+		.into_iter()
+		.map(|n| {
+			let secret = dir.join(format!("{n}.key"));
+			fs::write(&secret, Base64::encode_string(&[n; 32])).expect("write key");
+			let public = SigningKey::from_bytes(&[n; 32]).verifying_key();
+			(secret, Base64::encode_string(public.as_bytes()))
+		})
+		.collect();
+
+	let pack_with = |key: &std::path::Path, extra: &[&str]| {
+		let mut args = vec![
+			"pack",
+			"--sign",
+			key.to_str().expect("utf-8 path"),
+			"--output",
+			output.to_str().expect("utf-8 path"),
+		];
+		args.extend_from_slice(extra);
+		args.push(content.to_str().expect("utf-8 path"));
+		pack(PackArgs::try_parse_from(args).expect("parse args")).expect("pack");
+	};
+	let verify_with = |key: &str| {
+		verify(
+			VerifyArgs::try_parse_from([
+				"verify",
+				"--key",
+				key,
+				output.to_str().expect("utf-8 path"),
+			])
+			.expect("parse args"),
+		)
+	};
+
+	pack_with(&keys[0].0, &[]);
+	verify_with(&keys[0].1).expect("signed by the first key");
+	verify_with(&keys[1].1).expect_err("not signed by the second key");
+
+	// the first signature is kept, but only covers the first edition
+	pack_with(&keys[1].0, &["--append"]);
+	verify_with(&keys[1].1).expect("signed by the second key");
+	verify_with(&keys[0].1).expect_err("first key only signed a previous edition");
+
+	let mut bytes = fs::read(&output).expect("read zarc");
+	let mut zarc = zarc::decode::Decoder::open(output.clone()).expect("open zarc");
+	zarc.read_directory().expect("read directory");
+	assert!(zarc.signatures().all(|sig| zarc.signs_edition(sig)));
+	let offset = usize::try_from(zarc.trailer().directory_offset).expect("offset");
+	bytes[offset + 20] ^= 0xff;
+	fs::write(&output, bytes).expect("write zarc");
+	verify_with(&keys[1].1).expect_err("tampered directory");
+
+	fs::remove_dir_all(&dir).expect("clean up");
+}
@@ -1,28 +1,58 @@
 //! Decoder types and functions.
 
 use std::{
-	collections::{BTreeMap, HashMap},
+	collections::{BTreeMap, HashMap, HashSet},
 	num::NonZeroU16,
+	ops::Bound,
 };
 
+use ed25519_dalek::VerifyingKey;
+
 use crate::{
-	directory::{Edition, File, Frame, Pathname},
+	directory::{Edition, External, ExternalFrame, File, Frame, Pathname, Signature},
+	encryption::{ContentCipher, EncryptionKey, KeyStanza},
 	integrity::Digest,
-	ondemand::OnDemand,
 	trailer::Trailer,
 };
 
+use self::{
+	dictionary::Dictionary,
+	error::{ErrorKind, Result},
+};
+
 #[cfg_attr(feature = "expose-internals", visibility::make(pub))]
 #[doc(inline)]
 pub(crate) use self::zstd_iterator::ZstdFrameIterator;
 
+#[cfg(feature = "tokio")]
+#[doc(inline)]
+pub use self::async_decoder::AsyncDecoder;
+#[doc(inline)]
+pub use self::content_iterator::ContentIterator;
+#[doc(inline)]
+pub use self::edition_chain::EditionLink;
+#[doc(inline)]
+pub use self::file_handle::FileHandle;
+#[doc(inline)]
+pub use self::file_reader::FileReader;
 #[doc(inline)]
 pub use self::frame_iterator::FrameIterator;
+#[doc(inline)]
+pub use self::range_iterator::RangeIterator;
 
+#[cfg(feature = "tokio")]
+mod async_decoder;
+mod content_iterator;
+mod dictionary;
 mod directory;
+mod edition_chain;
 pub mod error;
+mod file_handle;
+mod file_reader;
 mod frame_iterator;
+mod locate;
 mod open;
+mod range_iterator;
 mod zstd_iterator;
 
 /// Decoder context.
@@ -37,15 +67,47 @@ pub struct Decoder<R> {
 	file_length: u64,
 	trailer: Trailer,
 
+	// obtained from keys frame, and given by user
+	key_stanzas: Vec<KeyStanza>,
+	cipher: Option<ContentCipher>,
+
 	// obtained from directory
 	editions: BTreeMap<NonZeroU16, Edition>,
 	files: Vec<File>,
 	frames: HashMap<Digest, Frame>,
 	files_by_name: BTreeMap<Pathname, Vec<usize>>,
 	files_by_digest: HashMap<Digest, Vec<usize>>,
+	externals: HashMap<Digest, External>,
+	external_frames: HashMap<Digest, ExternalFrame>,
+	signatures: Vec<Signature>,
+	dictionaries: HashMap<Digest, Dictionary>,
+
+	// given by user, for thin zarcs
+	bases: Vec<Decoder<R>>,
 }
 
-impl<R: OnDemand> Decoder<R> {
+impl<R> Decoder<R> {
+	/// Whether the Zarc is encrypted.
+	///
+	/// Encrypted Zarcs must be opened with [`open_with_key()`](Decoder::open_with_key) to be read.
+	pub fn is_encrypted(&self) -> bool {
+		!self.key_stanzas.is_empty()
+	}
+
+	/// Whether an encryption key can open this Zarc.
+	///
+	/// This is always false if the Zarc isn't encrypted.
+	pub fn opens_with(&self, key: &EncryptionKey) -> bool {
+		self.key_stanzas
+			.iter()
+			.any(|stanza| ContentCipher::unwrap_from(stanza, key).is_some())
+	}
+
+	/// The content key, if the Zarc is encrypted and was opened with a key.
+	pub(crate) fn cipher(&self) -> Option<&ContentCipher> {
+		self.cipher.as_ref()
+	}
+
 	/// Length of the file in bytes.
 	pub fn file_length(&self) -> u64 {
 		self.file_length
@@ -87,7 +149,51 @@ impl<R: OnDemand> Decoder<R> {
 			.map(|v| v.iter().filter_map(|i| self.files.get(*i)).collect())
 	}
 
+	/// Get the latest file entry that has a particular (path)name.
+	///
+	/// This is the entry from the latest edition, or the last one in the directory if there are
+	/// several in that edition. Returns `None` if there's no entry with this name, or if the latest
+	/// one is a tombstone, i.e. the file was deleted.
+	pub fn latest_file(&self, name: impl Into<Pathname>) -> Option<&File> {
+		self.files_by_name
+			.get(&name.into())
+			.and_then(|indices| self.latest_of(indices))
+	}
+
+	/// Iterate through the latest file entry of every (path)name.
+	///
+	/// This is the current view of the Zarc: superseded entries and deleted files are skipped.
+	/// Files are sorted by name, so directories come before their contents.
+	pub fn latest_files(&self) -> impl Iterator<Item = &File> {
+		self.files_by_name
+			.values()
+			.filter_map(|indices| self.latest_of(indices))
+	}
+
+	/// Iterate through the latest file entry of every (path)name under a directory, at any depth.
+	///
+	/// Like [`latest_files()`](Decoder::latest_files), this is sorted by name, so the entries for
+	/// a name come right before the entries under it.
+	pub(crate) fn latest_files_under(&self, dir: Pathname) -> impl Iterator<Item = &File> {
+		self.files_by_name
+			.range((Bound::Excluded(dir.clone()), Bound::Unbounded))
+			.take_while(move |(name, _)| name.0.starts_with(&dir.0))
+			.filter_map(|(_, indices)| self.latest_of(indices))
+	}
+
+	fn latest_of(&self, indices: &[usize]) -> Option<&File> {
+		indices
+			.iter()
+			.filter_map(|i| self.files.get(*i))
+			.max_by_key(|file| file.edition)
+			.filter(|file| !file.is_tombstone())
+	}
+
 	/// Get files that reference a frame from its digest.
+	///
+	/// This finds files by the digest of their whole content, and by the digests of the frames
+	/// their content is in: for chunked files, that's each chunk, and for files in a solid frame,
+	/// the solid frame.
 	pub fn files_by_digest(&self, digest: &Digest) -> Option<Vec<&File>> {
 		self.files_by_digest
 			.get(digest)
@@ -104,4 +210,162 @@ impl<R: OnDemand> Decoder<R> {
 	pub fn frame(&self, digest: &Digest) -> Option<&Frame> {
 		self.frames.get(digest)
 	}
+
+	/// Iterate through the references to external Zarcs.
+	///
+	/// If there are any, this is a thin Zarc: some of its content is in these other Zarcs, which
+	/// need to be [added as bases](Decoder::add_base) to read it.
+	pub fn externals(&self) -> impl Iterator<Item = &External> {
+		self.externals.values()
+	}
+
+	/// Iterate through the references to external Zarcs which haven't been added as bases.
+	pub fn missing_bases(&self) -> impl Iterator<Item = &External> {
+		self.externals.values().filter(|external| {
+			!self
+				.bases
+				.iter()
+				.any(|base| base.trailer.digest == external.digest)
+		})
+	}
+
+	/// Iterate through the bases which haven't been added, but are needed to read a file.
+	///
+	/// This includes the bases of frames that the file's frames are compressed with or against.
+	pub fn missing_bases_for<'file>(
+		&'file self,
+		file: &'file File,
+	) -> impl Iterator<Item = &'file External> {
+		let mut needed = HashSet::new();
+		let mut seen = HashSet::new();
+		let mut digests: Vec<&Digest> = file.frame_digests().collect();
+		while let Some(digest) = digests.pop() {
+			if !seen.insert(digest) {
+				continue;
+			}
+
+			if let Some(frame) = self.frames.get(digest) {
+				digests.extend(
+					frame
+						.dictionary
+						.iter()
+						.chain(frame.reference.iter())
+						.chain(frame.outboard.iter()),
+				);
+			} else if let Some(frame) = self.external_frames.get(digest) {
+				needed.insert(&frame.external);
+			}
+		}
+
+		self.missing_bases()
+			.filter(move |external| needed.contains(&external.digest))
+	}
+
+	/// Iterate through the frames which are stored in external Zarcs.
+	pub fn external_frames(&self) -> impl Iterator<Item = &ExternalFrame> {
+		self.external_frames.values()
+	}
+
+	/// Get external frame metadata by digest.
+	pub fn external_frame(&self, digest: &Digest) -> Option<&ExternalFrame> {
+		self.external_frames.get(digest)
+	}
+
+	/// Add a base Zarc, to read content that this thin Zarc references but doesn't contain.
+	///
+	/// The base must have had its directory read, and its directory digest must match one of the
+	/// [externals](Decoder::externals) of this Zarc. Reading the directory verifies the digest, so
+	/// this guarantees that the base is the one this Zarc was made against.
+	pub fn add_base(&mut self, base: Decoder<R>) -> Result<()> {
+		if base.editions.is_empty() {
+			return Err(ErrorKind::ReadOrderViolation("base directory must be read first").into());
+		}
+
+		if !self.externals.contains_key(&base.trailer.digest) {
+			return Err(ErrorKind::UnknownBase.into());
+		}
+
+		self.bases.push(base);
+		Ok(())
+	}
+
+	/// Iterate through the signatures, of all editions.
+	///
+	/// These haven't been checked: use [`signers()`](Decoder::signers) to get who validly signed
+	/// this Zarc, or [`Signature::signer()`] to check individual signatures.
+	pub fn signatures(&self) -> impl Iterator<Item = &Signature> {
+		self.signatures.iter()
+	}
+
+	/// Get the keys which validly signed this Zarc.
+	///
+	/// These are the signatures over the current directory digest, which authenticate all of the
+	/// Zarc's content. Signatures from previous editions, over previous digests, aren't included.
+	pub fn signers(&self) -> Vec<VerifyingKey> {
+		self.signatures
+			.iter()
+			.filter(|signature| signature.digest == self.trailer.digest)
+			.filter_map(Signature::signer)
+			.collect()
+	}
+
+	/// Get the keys which validly signed the Zarc as of an edition.
+	///
+	/// These are signatures added in that edition, over the directory digest the Zarc had then.
+	pub fn edition_signers(&self, number: impl TryInto<NonZeroU16>) -> Vec<VerifyingKey> {
+		let Ok(number) = number.try_into() else {
+			return Vec::new();
+		};
+
+		self.signatures
+			.iter()
+			.filter(|signature| signature.edition == number && self.signs_edition(signature))
+			.filter_map(Signature::signer)
+			.collect()
+	}
+
+	/// Check whether the Zarc was validly signed by a key.
+	pub fn is_signed_by(&self, key: &VerifyingKey) -> bool {
+		self.signers().contains(key)
+	}
+}
+
+#[test]
+fn thin_zarc_reads_from_base() {
+	use crate::{
+		encode::Encoder,
+		test_util::{error_kind, name, open, zarc, zarc_bytes},
+	};
+
+	let base = zarc_bytes(&[("base.txt", b"shared content")]);
+	let mut bytes = Vec::new();
+	let mut thin = Encoder::new(&mut bytes).expect("new encoder");
+	thin.add_base(&open(base.clone()), None).expect("add base");
+	let digest = thin.add_data_frame(b"shared content").expect("add frame");
+	let mut file = thin.build_file(name("thin.txt"));
+	file.digest(digest);
+	thin.add_file_entry(file).expect("add file");
+	thin.finalise().expect("finalise");
+
+	let mut thin = open(bytes);
+	assert_eq!(thin.frames().count(), 0, "content is only in the base");
+	let file = thin
+		.latest_file(name("thin.txt"))
+		.expect("thin.txt")
+		.clone();
+	assert_eq!(thin.missing_bases_for(&file).count(), 1);
+	let err = thin.read_at(&file, 0, 64).expect_err("base is missing");
+	assert_eq!(error_kind(&err), Some(ErrorKind::MissingBase));
+
+	let other = zarc(&[("base.txt", b"other content")]);
+	let err = thin.add_base(other).expect_err("wrong base");
+	assert_eq!(error_kind(&err), Some(ErrorKind::UnknownBase));
+	assert_eq!(thin.missing_bases().count(), 1);
+
+	thin.add_base(open(base)).expect("add base");
+	assert_eq!(thin.missing_bases().count(), 0);
+	assert_eq!(
+		thin.read_at(&file, 0, 64).expect("read thin.txt"),
+		b"shared content"
+	);
 }
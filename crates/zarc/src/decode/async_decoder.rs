@@ -0,0 +1,393 @@
+use std::{
+	collections::HashMap,
+	future::Future,
+	io::{Cursor, SeekFrom},
+	ops::Deref,
+	pin::Pin,
+};
+
+use async_stream::try_stream;
+use futures_core::Stream;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::{debug, trace};
+
+use crate::{
+	directory::File,
+	encode::MAX_DELTA_DEPTH,
+	encryption::{EncryptionKey, KEYS_FRAME_NIBBLE},
+	header::FILE_MAGIC,
+	integrity::Digest,
+	ondemand::AsyncOnDemand,
+};
+
+use super::{
+	dictionary::Dictionary,
+	directory::ParsedDirectory,
+	error::{self, ErrorKind, Result, SimpleError},
+	open::{
+		parse_header, parse_keys, parse_skippable_frame, parse_trailer, KEYS_FRAME_MAGIC,
+		TRAILER_SEARCH_LENGTH,
+	},
+	Decoder,
+};
+
+/// A content frame being read.
+type ContentFuture<'a> = Pin<Box<dyn Future<Output = Result<Option<Vec<u8>>>> + Send + 'a>>;
+
+/// Async decoder context, for tokio.
+///
+/// This reads Zarcs from an [`AsyncOnDemand`] source without blocking. Frames are read into memory
+/// before being decompressed, so content is returned a frame at a time, after it's been verified.
+///
+/// Once the directory has been read, the Zarc can be queried as with the [`Decoder`] it derefs to.
+/// Reading content must go through the methods of this type, as those of the `Decoder` need a
+/// blocking source.
+#[derive(Debug)]
+pub struct AsyncDecoder<R> {
+	decoder: Decoder<R>,
+}
+
+impl<R> Deref for AsyncDecoder<R> {
+	type Target = Decoder<R>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.decoder
+	}
+}
+
+impl<R: AsyncOnDemand> AsyncDecoder<R> {
+	/// Open a Zarc for reading.
+	///
+	/// This checks the [header][crate::header], reads the [trailer][crate::trailer], and verifies
+	/// the integrity of the trailer, like [`Decoder::open()`].
+	///
+	/// If the Zarc is [encrypted](Decoder::is_encrypted), this succeeds, but reading the directory
+	/// will fail: use [`open_with_key()`](AsyncDecoder::open_with_key) instead.
+	pub async fn open(reader: R) -> Result<Self> {
+		let mut source = reader.open().await?;
+
+		let mut header = vec![0; FILE_MAGIC.len()];
+		source.read_exact(&mut header).await?;
+		let frame = parse_skippable_frame(&mut Cursor::new(header), 0x0)?;
+		let version = parse_header(frame.data)?;
+
+		let mut magic = [0; 4];
+		source.read_exact(&mut magic).await?;
+		let key_stanzas = if magic == KEYS_FRAME_MAGIC {
+			let length = source.read_u32_le().await?;
+			let mut frame = Vec::with_capacity(length as usize + 8);
+			frame.extend_from_slice(&magic);
+			frame.extend_from_slice(&length.to_le_bytes());
+			(&mut source)
+				.take(length as _)
+				.read_to_end(&mut frame)
+				.await?;
+			let frame = parse_skippable_frame(&mut Cursor::new(frame), KEYS_FRAME_NIBBLE)?;
+			parse_keys(&frame.data)?
+		} else {
+			trace!(magic=%format!("{magic:02x?}"), "no keys frame, zarc is not encrypted");
+			Vec::new()
+		};
+
+		let file_length = source.seek(SeekFrom::End(0)).await?;
+		let ending_length = file_length.min(TRAILER_SEARCH_LENGTH);
+		trace!(%file_length, reading_bytes=%ending_length, "reading end of file");
+		source.seek(SeekFrom::End(-(ending_length as i64))).await?;
+		let mut ending = Vec::with_capacity(ending_length as _);
+		source.read_to_end(&mut ending).await?;
+		let trailer = parse_trailer(&ending, file_length)?;
+
+		Ok(Self {
+			decoder: Decoder::from_parts(reader, version, trailer, file_length, key_stanzas),
+		})
+	}
+
+	/// Open an encrypted Zarc for reading.
+	///
+	/// This is [`open()`](AsyncDecoder::open), but also decrypts the Zarc's content key with the
+	/// given key, failing if the key doesn't open this Zarc. If the Zarc isn't encrypted, the key
+	/// is ignored.
+	pub async fn open_with_key(reader: R, key: &EncryptionKey) -> Result<Self> {
+		let mut zarc = Self::open(reader).await?;
+		zarc.decoder.unlock(key)?;
+		Ok(zarc)
+	}
+
+	/// Read the directory.
+	///
+	/// This reads the entire directory into memory and verifies its digest, then loads the
+	/// dictionaries it references.
+	pub async fn read_directory(&mut self) -> Result<()> {
+		let (offset, length) = self.decoder.directory_span();
+		let frame = self.decoder.fetch(offset, length).await?;
+		let directory = {
+			let zstd = self.decoder.create_dctx(None)?;
+			let mut frame = self
+				.decoder
+				.read_zstandard_frame_from(frame, offset, zstd)?;
+			ParsedDirectory::read(&mut frame)?
+		};
+
+		self.decoder.set_directory(directory)?;
+		self.load_dictionaries().await
+	}
+
+	/// Read all dictionaries used in this Zarc.
+	async fn load_dictionaries(&mut self) -> Result<()> {
+		let mut dictionaries = HashMap::new();
+		for digest in self
+			.decoder
+			.frames
+			.values()
+			.filter_map(|frame| frame.dictionary.as_ref())
+		{
+			if dictionaries.contains_key(digest) {
+				continue;
+			}
+
+			let Some(dictionary) = self.decoder.fetch_content_frame(digest).await? else {
+				return Err(ErrorKind::DirectoryIntegrity("missing dictionary frame").into());
+			};
+
+			debug!(digest=%format!("{digest:02x?}"), bytes=%dictionary.len(), "loaded dictionary");
+			dictionaries.insert(digest.clone(), Dictionary::new(&dictionary));
+		}
+
+		self.decoder.dictionaries = dictionaries;
+		Ok(())
+	}
+
+	/// Add a base Zarc, to read content that this thin Zarc references but doesn't contain.
+	///
+	/// See [`Decoder::add_base()`].
+	pub fn add_base(&mut self, base: AsyncDecoder<R>) -> Result<()> {
+		self.decoder.add_base(base.decoder)
+	}
+
+	/// Read and decompress a content frame by digest.
+	///
+	/// The frame is decompressed entirely and its digest is checked before it's returned.
+	///
+	/// If the frame is stored in an external Zarc, it's read from there, and this errors if that
+	/// Zarc hasn't been [added as a base](AsyncDecoder::add_base).
+	pub async fn read_content_frame(&self, digest: &Digest) -> Result<Option<Vec<u8>>> {
+		self.decoder.fetch_content_frame(digest).await
+	}
+
+	/// Decompress the content of a file.
+	///
+	/// This returns a stream of chunks of bytes, one per content frame of the file, which are
+	/// each verified before they're yielded. The digest of the whole content is also checked
+	/// against the file's at the end.
+	///
+	/// Each frame is read and decompressed whole before it's yielded, so this holds up to a frame's
+	/// compressed and decompressed content in memory at once. Chunked files have small frames, but
+	/// a file stored in a single frame is buffered entirely: for large files like that, prefer the
+	/// blocking [`Decoder::file_reader()`], which streams through frames.
+	///
+	/// Returns `None` if the file has no content.
+	pub fn read_file(
+		&self,
+		file: &File,
+	) -> Result<Option<impl Stream<Item = Result<Vec<u8>>> + Send + '_>> {
+		let (Some(digest), Some(segments)) = (file.digest.clone(), self.decoder.segments(file)?)
+		else {
+			return Ok(None);
+		};
+
+		Ok(Some(try_stream! {
+			let mut hasher = blake3::Hasher::new();
+			for segment in segments {
+				// try_stream! expands `?` to a block, which can't be followed by `else`
+				let content = self.decoder.fetch_content_frame(&segment.frame).await?;
+				let Some(mut content) = content else {
+					Err(ErrorKind::DirectoryIntegrity("file references missing frame"))?;
+					unreachable!();
+				};
+
+				if segment.offset > 0 || segment.length < content.len() as u64 {
+					let start = (segment.offset as usize).min(content.len());
+					let end = (start + segment.length as usize).min(content.len());
+					content = content[start..end].to_vec();
+				}
+
+				hasher.update(&content);
+				yield content;
+			}
+
+			if Digest(hasher.finalize().as_bytes().to_vec()) != digest {
+				Err(SimpleError::new(ErrorKind::ContentIntegrity)
+					.with_message("file content doesn't match its digest"))?;
+			}
+		}))
+	}
+}
+
+impl<R: AsyncOnDemand> Decoder<R> {
+	/// Read bytes from the source.
+	async fn fetch(&self, offset: u64, length: u64) -> Result<Vec<u8>> {
+		let mut reader = self.reader.open().await?;
+		reader.seek(SeekFrom::Start(offset)).await?;
+		let mut data = Vec::with_capacity(length as _);
+		reader.take(length).read_to_end(&mut data).await?;
+		if data.len() as u64 != length {
+			return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+		}
+
+		Ok(data)
+	}
+
+	/// Read, decompress, and verify a content frame entirely.
+	fn fetch_content_frame<'a>(&'a self, digest: &'a Digest) -> ContentFuture<'a> {
+		self.fetch_content_frame_at(digest, 0)
+	}
+
+	/// Read, decompress, and verify a content frame which is `depth` references deep.
+	///
+	/// This is boxed as it recurses into bases and reference frames.
+	fn fetch_content_frame_at<'a>(&'a self, digest: &'a Digest, depth: usize) -> ContentFuture<'a> {
+		Box::pin(async move {
+			trace!(digest=%format!("{digest:02x?}"), depth, "fetch content frame");
+			if depth > MAX_DELTA_DEPTH {
+				return Err(ErrorKind::DirectoryIntegrity("reference chain too deep").into());
+			}
+
+			let Some(entry) = self.frames.get(digest) else {
+				let Some(external) = self.external_frames.get(digest) else {
+					return Ok(None);
+				};
+
+				return self
+					.base(&external.external)?
+					.fetch_content_frame_at(digest, depth)
+					.await;
+			};
+
+			let prefix = if let Some(reference) = &entry.reference {
+				let Some(prefix) = self.fetch_content_frame_at(reference, depth + 1).await? else {
+					return Err(ErrorKind::DirectoryIntegrity("missing reference frame").into());
+				};
+				Some(prefix)
+			} else {
+				None
+			};
+
+			let frame = self.fetch(entry.offset, entry.length).await?;
+			let zstd = if let Some(prefix) = &prefix {
+				let mut zstd = self.create_dctx(None)?;
+				zstd.load_dictionary(prefix).map_err(error::zstd)?;
+				zstd
+			} else {
+				self.create_dctx(entry.dictionary.as_ref())?
+			};
+
+			let mut content = Vec::with_capacity(entry.uncompressed as _);
+			for bytes in self.read_zstandard_frame_from(frame, entry.offset, zstd)? {
+				content.extend(bytes?);
+			}
+
+			if Digest(blake3::hash(&content).as_bytes().to_vec()) != *digest {
+				return Err(SimpleError::new(ErrorKind::ContentIntegrity)
+					.with_message("frame content doesn't match its digest")
+					.into());
+			}
+
+			Ok(Some(content))
+		})
+	}
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn reads_encoder_output() {
+	use std::{future::poll_fn, pin::pin, sync::Arc};
+
+	use crate::{
+		encode::Encoder,
+		test_util::{error_kind, name, random, varied},
+	};
+
+	async fn read_all(zarc: &AsyncDecoder<Arc<[u8]>>, file: &File) -> Result<Option<Vec<u8>>> {
+		let Some(stream) = zarc.read_file(file)? else {
+			return Ok(None);
+		};
+
+		let mut stream = pin!(stream);
+		let mut content = Vec::new();
+		while let Some(bytes) = poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+			content.extend(bytes?);
+		}
+		Ok(Some(content))
+	}
+
+	let chunked = varied(300_000);
+	let stored = random(1000);
+
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	let (digest, chunks) = zarc.add_chunked_data(&chunked).expect("add chunks");
+	assert!(chunks.len() > 1);
+	let mut file = zarc.build_file(name("chunked.bin"));
+	file.chunks(digest, chunks);
+	zarc.add_file_entry(file).expect("add file");
+	let digest = zarc.add_data_frame(&stored).expect("add frame");
+	let mut file = zarc.build_file(name("stored.bin"));
+	file.digest(digest);
+	zarc.add_file_entry(file).expect("add file");
+	for (path, content) in [("one.txt", &b"first"[..]), ("two.txt", b"second")] {
+		let file = zarc.build_file(name(path));
+		zarc.add_solid_file(file, content).expect("add solid");
+	}
+	let mut dir = zarc.build_file(name("dir"));
+	dir.directory();
+	zarc.add_file_entry(dir).expect("add dir");
+	zarc.finalise().expect("finalise");
+
+	let open = |bytes: Vec<u8>| async move {
+		let mut zarc = AsyncDecoder::open(Arc::<[u8]>::from(bytes))
+			.await
+			.expect("open");
+		zarc.read_directory().await.expect("read directory");
+		zarc
+	};
+
+	let zarc = open(bytes.clone()).await;
+	for (path, content) in [
+		("chunked.bin", &chunked[..]),
+		("stored.bin", &stored),
+		("one.txt", b"first"),
+		("two.txt", b"second"),
+	] {
+		let file = zarc.latest_file(name(path)).expect("file");
+		let read = read_all(&zarc, file).await.expect("read file");
+		assert_eq!(read.as_deref(), Some(content), "{path}");
+	}
+	let dir = zarc.latest_file(name("dir")).expect("dir");
+	assert_eq!(read_all(&zarc, dir).await.expect("read dir"), None);
+
+	// the content is fine, but it's not what the file says it is
+	let mut wrong = zarc.latest_file(name("one.txt")).expect("file").clone();
+	wrong.digest = zarc
+		.latest_file(name("two.txt"))
+		.expect("file")
+		.digest
+		.clone();
+	let err = read_all(&zarc, &wrong)
+		.await
+		.expect_err("file digest mismatch");
+	assert_eq!(error_kind(&err), Some(ErrorKind::ContentIntegrity));
+
+	// the frame doesn't decompress to its digest
+	let offset = bytes
+		.windows(stored.len())
+		.position(|window| window == stored)
+		.expect("stored bytes");
+	let mut tampered = bytes;
+	tampered[offset + 500] ^= 0xFF;
+	let zarc = open(tampered).await;
+	let file = zarc.latest_file(name("stored.bin")).expect("file");
+	let err = read_all(&zarc, file)
+		.await
+		.expect_err("frame digest mismatch");
+	assert_eq!(error_kind(&err), Some(ErrorKind::ContentIntegrity));
+}
@@ -0,0 +1,221 @@
+use std::{fmt, ops::Range, vec::IntoIter};
+
+use crate::{directory::File, integrity::Digest, ondemand::OnDemand};
+
+use super::{
+	error::{ErrorKind, Result},
+	Decoder, FrameIterator,
+};
+
+impl<R: OnDemand> Decoder<R> {
+	/// Decompress the content of a file.
+	///
+	/// This returns an iterator of chunks of bytes, which reads through each of the file's content
+	/// frames in turn. If the file's content is [chunked](File::chunks), that's the concatenation
+	/// of all the chunks; if it's in a [solid frame](File::solid), that's the file's slice of the
+	/// frame; otherwise it's the same as [`read_content_frame()`](Decoder::read_content_frame)
+	/// with the file's digest.
+	///
+	/// Returns `None` if the file has no content.
+	pub fn read_file(&self, file: &File) -> Result<Option<ContentIterator<'_, R>>> {
+		let Some(digest) = &file.digest else {
+			return Ok(None);
+		};
+
+		let frames: Vec<Digest> = file.frame_digests().cloned().collect();
+		let uncompressed_size = self.content_size(file).unwrap_or_default();
+
+		Ok(Some(ContentIterator {
+			decoder: self,
+			frames: frames.into_iter(),
+			current: None,
+			hasher: blake3::Hasher::new(),
+			digest: digest.clone(),
+			uncompressed_size,
+			uncompressed_read: 0,
+			window: ContentWindow::new(file),
+			done: false,
+		}))
+	}
+
+	/// Get the uncompressed size of a file's content.
+	///
+	/// This is the sum of the sizes of the file's content frames, including those stored in
+	/// external Zarcs, or the length of its slice of a solid frame. Returns `None` if the file has
+	/// no content, or if a frame is unknown.
+	pub fn content_size(&self, file: &File) -> Option<u64> {
+		file.digest.as_ref()?;
+		if let Some(solid) = &file.solid {
+			return Some(solid.length);
+		}
+
+		file.frame_digests()
+			.map(|digest| {
+				self.frames
+					.get(digest)
+					.map(|frame| frame.uncompressed)
+					.or_else(|| {
+						self.external_frames
+							.get(digest)
+							.map(|frame| frame.uncompressed)
+					})
+			})
+			.sum()
+	}
+}
+
+/// Iterator over a file's content.
+///
+/// This is returned by [`Decoder::read_file()`][super::Decoder::read_file].
+///
+/// Each call to the iterator decompresses some data and returns it, going through each content
+/// frame in order, until the content is exhausted. It also computes the digest of the whole
+/// content as it goes, so you can check it against the file's digest.
+pub struct ContentIterator<'decoder, R: OnDemand> {
+	decoder: &'decoder Decoder<R>,
+	frames: IntoIter<Digest>,
+	current: Option<FrameIterator<'decoder, R::Reader>>,
+	hasher: blake3::Hasher,
+	digest: Digest,
+	uncompressed_size: u64,
+	uncompressed_read: u64,
+	window: ContentWindow,
+	done: bool,
+}
+
+impl<R: OnDemand> fmt::Debug for ContentIterator<'_, R> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("ContentIterator")
+			.field("frames", &self.frames)
+			.field("hasher", &self.hasher)
+			.field("digest", &self.digest)
+			.field("uncompressed_size", &self.uncompressed_size)
+			.field("uncompressed_read", &self.uncompressed_read)
+			.field("window", &self.window)
+			.field("done", &self.done)
+			.finish_non_exhaustive()
+	}
+}
+
+impl<R: OnDemand> ContentIterator<'_, R> {
+	/// Return the uncompressed size of the content.
+	pub fn uncompressed_size(&self) -> u64 {
+		self.uncompressed_size
+	}
+
+	/// How many (uncompressed) bytes are left to go.
+	pub fn bytes_left(&self) -> u64 {
+		self.uncompressed_size
+			.saturating_sub(self.uncompressed_read)
+	}
+
+	/// Return the digest of the content.
+	///
+	/// Returns None if the iterator isn't yet done.
+	pub fn digest(&self) -> Option<Digest> {
+		if self.done {
+			Some(Digest(self.hasher.finalize().as_bytes().to_vec()))
+		} else {
+			None
+		}
+	}
+
+	/// Check the digest of the content against the file's digest.
+	///
+	/// Returns None if the iterator isn't yet done.
+	pub fn verify(&self) -> Option<bool> {
+		self.digest().map(|d| d == self.digest)
+	}
+}
+
+impl<R: OnDemand> Iterator for ContentIterator<'_, R> {
+	type Item = Result<Vec<u8>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if self.window.is_exhausted() {
+				self.current = None;
+				self.done = true;
+				return None;
+			}
+
+			if let Some(frame) = &mut self.current {
+				match frame.next() {
+					Some(Ok(mut data)) => {
+						let range = self.window.clip(data.len());
+						data.truncate(range.end);
+						data.drain(..range.start);
+						if data.is_empty() {
+							continue;
+						}
+
+						self.uncompressed_read += data.len() as u64;
+						self.hasher.update(&data);
+						return Some(Ok(data));
+					}
+					Some(Err(err)) => return Some(Err(err)),
+					None => {
+						self.current = None;
+					}
+				}
+			}
+
+			let Some(digest) = self.frames.next() else {
+				self.done = true;
+				return None;
+			};
+
+			match self.decoder.read_content_frame(&digest) {
+				Ok(Some(frame)) => {
+					self.current = Some(frame);
+				}
+				Ok(None) => {
+					return Some(Err(ErrorKind::DirectoryIntegrity(
+						"file references missing frame",
+					)
+					.into()))
+				}
+				Err(err) => return Some(Err(err)),
+			}
+		}
+	}
+}
+
+/// The part of the decompressed frames that is a file's content.
+///
+/// For files in a solid frame, that's some bytes to skip at the start, and some to read after
+/// that; for other files, it's everything.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ContentWindow {
+	skip: u64,
+	remaining: Option<u64>,
+}
+
+impl ContentWindow {
+	pub(crate) fn new(file: &File) -> Self {
+		Self {
+			skip: file.solid.as_ref().map_or(0, |solid| solid.offset),
+			remaining: file.solid.as_ref().map(|solid| solid.length),
+		}
+	}
+
+	/// Whether all of the content has been read.
+	pub(crate) fn is_exhausted(&self) -> bool {
+		self.remaining == Some(0)
+	}
+
+	/// Take the next `length` decompressed bytes, and return the range of them that's content.
+	pub(crate) fn clip(&mut self, length: usize) -> Range<usize> {
+		let length = length as u64;
+		let start = self.skip.min(length);
+		self.skip -= start;
+
+		let mut end = length;
+		if let Some(remaining) = &mut self.remaining {
+			end = start + (*remaining).min(length - start);
+			*remaining -= end - start;
+		}
+
+		(start as usize)..(end as usize)
+	}
+}
@@ -0,0 +1,116 @@
+use std::{collections::HashMap, fmt};
+
+use tracing::{debug, instrument};
+use zstd_safe::{DCtx, DDict};
+
+use crate::{integrity::Digest, ondemand::OnDemand};
+
+use super::{
+	error::{self, ErrorKind, Result, SimpleError},
+	Decoder,
+};
+
+/// A zstd dictionary, loaded from its frame.
+pub(crate) struct Dictionary(DDict<'static>);
+
+impl fmt::Debug for Dictionary {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("Dictionary")
+			.field(&self.0.get_dict_id())
+			.finish()
+	}
+}
+
+impl Dictionary {
+	/// Prepare a dictionary from its content.
+	pub(crate) fn new(content: &[u8]) -> Self {
+		Self(DDict::create(content))
+	}
+}
+
+impl<R: OnDemand> Decoder<R> {
+	/// Load the dictionaries that content frames were compressed with.
+	///
+	/// This is called after reading the directory.
+	#[instrument(level = "debug", skip(self))]
+	pub(crate) fn load_dictionaries(&mut self) -> Result<()> {
+		let mut dictionaries = HashMap::new();
+		for digest in self
+			.frames
+			.values()
+			.filter_map(|frame| frame.dictionary.as_ref())
+		{
+			if dictionaries.contains_key(digest) {
+				continue;
+			}
+
+			let Some(mut frame) = self.read_content_frame(digest)? else {
+				return Err(ErrorKind::DirectoryIntegrity("missing dictionary frame").into());
+			};
+
+			let mut dictionary = Vec::with_capacity(frame.uncompressed_size() as _);
+			for bytes in &mut frame {
+				dictionary.extend(bytes?);
+			}
+			if !frame.verify().unwrap_or(false) {
+				return Err(ErrorKind::DirectoryIntegrity("dictionary digest").into());
+			}
+
+			debug!(digest=%format!("{digest:02x?}"), bytes=%dictionary.len(), "loaded dictionary");
+			dictionaries.insert(digest.clone(), Dictionary::new(&dictionary));
+		}
+
+		self.dictionaries = dictionaries;
+		Ok(())
+	}
+
+	/// Create a zstd decompression context for a frame compressed against a reference frame.
+	///
+	/// This decompresses the reference frame entirely, so it can be used as prefix. The reference
+	/// is `depth` references deep from the frame that was asked for.
+	#[instrument(level = "debug", skip(self))]
+	pub(crate) fn create_delta_dctx(&self, reference: &Digest, depth: usize) -> Result<DCtx<'_>> {
+		let Some(mut frame) = self.read_content_frame_at(reference, depth)? else {
+			return Err(ErrorKind::DirectoryIntegrity("missing reference frame").into());
+		};
+
+		let mut prefix = Vec::with_capacity(frame.uncompressed_size() as _);
+		for bytes in &mut frame {
+			prefix.extend(bytes?);
+		}
+		if !frame.verify().unwrap_or(false) {
+			return Err(ErrorKind::DirectoryIntegrity("reference frame digest").into());
+		}
+		debug!(bytes=%prefix.len(), "decompressed reference");
+
+		// the prefix is owned here, so load it instead of referencing it: the encoder never uses
+		// content that looks like a dictionary as reference, so this loads it as raw content
+		let mut zstd = self.create_dctx(None)?;
+		zstd.load_dictionary(&prefix).map_err(error::zstd)?;
+		Ok(zstd)
+	}
+}
+
+impl<R> Decoder<R> {
+	/// Create a zstd decompression context, with a dictionary if needed.
+	pub(crate) fn create_dctx(&self, dictionary: Option<&Digest>) -> Result<DCtx<'_>> {
+		let mut zstd = DCtx::try_create().ok_or(ErrorKind::ZstdInit)?;
+
+		if let Some(digest) = dictionary {
+			let Some(Dictionary(ddict)) = self.dictionaries.get(digest) else {
+				return Err(
+					SimpleError::new(ErrorKind::DirectoryIntegrity("missing dictionary"))
+						.with_message(format!(
+						"frame was compressed with a dictionary that's not in the zarc: {:02x?}",
+						digest.as_slice()
+					))
+						.into(),
+				);
+			};
+
+			zstd.ref_ddict(ddict).map_err(error::zstd)?;
+		}
+
+		Ok(zstd)
+	}
+}
@@ -1,19 +1,28 @@
-use std::mem::take;
+use std::{
+	collections::{BTreeMap, HashMap},
+	io::{Read, Seek},
+	num::NonZeroU16,
+};
 
 use blake3::Hasher;
 use deku::DekuContainerRead;
-use ozarc::framing::{ZstandardBlockHeader, ZstandardFrameHeader};
+use ozarc::framing::{ZstandardBlockHeader, ZstandardFrameHeader, SKIPPABLE_FRAME_OVERHEAD};
 use tracing::{debug, instrument, trace, warn};
+use zstd_safe::DCtx;
 
 use crate::{
-	directory::{Element, ElementFrame},
+	directory::{
+		Edition, Element, ElementFrame, ElementKind, External, ExternalFrame, File, Frame,
+		Pathname, Signature,
+	},
+	encode::MAX_DELTA_DEPTH,
 	integrity::Digest,
 	ondemand::OnDemand,
 };
 
 use super::{
 	error::{ErrorKind, Result, SimpleError},
-	Decoder,
+	Decoder, ZstdFrameIterator,
 };
 
 impl<R: OnDemand> Decoder<R> {
@@ -27,7 +36,9 @@ impl<R: OnDemand> Decoder<R> {
 	/// [if present as per this header](ozarc::framing::ZstandardFrameDescriptor.checksum).
 	#[cfg_attr(feature = "expose-internals", visibility::make(pub))]
 	#[instrument(level = "debug", skip(reader))]
-	fn read_zstandard_frame_header(reader: &mut R::Reader) -> Result<ZstandardFrameHeader> {
+	pub(crate) fn read_zstandard_frame_header(
+		reader: &mut R::Reader,
+	) -> Result<ZstandardFrameHeader> {
 		let (bits_read, header) =
 			ZstandardFrameHeader::from_reader((reader, 0)).map_err(SimpleError::from_deku)?;
 		debug!(%bits_read, ?header, "read zstandard frame header");
@@ -40,7 +51,9 @@ impl<R: OnDemand> Decoder<R> {
 	/// the start of the block's payload. The block header is returned.
 	#[cfg_attr(feature = "expose-internals", visibility::make(pub))]
 	#[instrument(level = "debug", skip(reader))]
-	fn read_zstandard_block_header(reader: &mut R::Reader) -> Result<ZstandardBlockHeader> {
+	pub(crate) fn read_zstandard_block_header(
+		reader: &mut R::Reader,
+	) -> Result<ZstandardBlockHeader> {
 		let (bits_read, header) =
 			ZstandardBlockHeader::from_reader((reader, 0)).map_err(SimpleError::from_deku)?;
 		debug!(%bits_read, ?header, "read zstandard block header");
@@ -53,24 +66,138 @@ impl<R: OnDemand> Decoder<R> {
 	/// through the available file records and extract them on demand.
 	#[instrument(level = "debug", skip(self))]
 	pub fn read_directory(&mut self) -> Result<()> {
-		let mut hasher = Hasher::new();
-		let mut editions = take(&mut self.editions);
-		let mut frames = take(&mut self.frames);
-		let mut files = take(&mut self.files);
-		let mut files_by_name = take(&mut self.files_by_name);
-		let mut files_by_digest = take(&mut self.files_by_digest);
+		let (offset, length) = self.directory_span();
 
 		// start a new decompression session
-		let frame = self.read_zstandard_frame(self.trailer.directory_offset as _)?;
-		for data in frame {
-			let data = data?;
-			hasher.update(&data);
+		let mut frame = self.read_zstandard_frame(offset, length, None)?;
+		let directory = ParsedDirectory::read(&mut frame)?;
+		drop(frame);
+
+		self.set_directory(directory)?;
+		self.load_dictionaries()?;
+		Ok(())
+	}
+}
+
+impl<R> Decoder<R> {
+	/// The offset and framed length of the directory.
+	pub(crate) fn directory_span(&self) -> (u64, u64) {
+		// the directory goes up to the trailer
+		let length = self
+			.file_length
+			.saturating_sub(self.trailer.directory_offset as u64)
+			.saturating_sub((SKIPPABLE_FRAME_OVERHEAD + self.trailer.len()) as u64);
+		(self.trailer.directory_offset as u64, length)
+	}
+
+	/// Replace the decoder's directory state with a parsed directory, and verify its digest.
+	pub(crate) fn set_directory(&mut self, directory: ParsedDirectory) -> Result<()> {
+		let ParsedDirectory {
+			digest,
+			editions,
+			frames,
+			files,
+			files_by_name,
+			files_by_digest,
+			externals,
+			external_frames,
+			signatures,
+		} = directory;
+
+		self.editions = editions;
+		self.frames = frames;
+		self.files = files;
+		self.files_by_name = files_by_name;
+		self.files_by_digest = files_by_digest;
+		self.externals = externals;
+		self.external_frames = external_frames;
+		self.signatures = signatures;
+
+		trace!("finished reading directory, verify digest");
+		if self.trailer.digest != digest {
+			return Err(ErrorKind::DirectoryIntegrity("digest").into());
+		}
+
+		self.check_references()
+	}
+
+	/// Check that chains of reference frames end, and aren't too deep.
+	///
+	/// Chains which continue into bases are checked as they're read.
+	fn check_references(&self) -> Result<()> {
+		for frame in self.frames.values() {
+			let mut chain = vec![&frame.digest];
+			let mut current = frame;
+			while let Some(reference) = &current.reference {
+				if chain.contains(&reference) {
+					return Err(
+						ErrorKind::DirectoryIntegrity("reference frames form a cycle").into(),
+					);
+				}
+				if chain.len() > MAX_DELTA_DEPTH {
+					return Err(ErrorKind::DirectoryIntegrity("reference chain too deep").into());
+				}
+
+				let Some(next) = self.frames.get(reference) else {
+					break;
+				};
+				chain.push(reference);
+				current = next;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// The elements of a directory, read from its frame.
+#[derive(Debug)]
+pub(crate) struct ParsedDirectory {
+	digest: Digest,
+	editions: BTreeMap<NonZeroU16, Edition>,
+	frames: HashMap<Digest, Frame>,
+	files: Vec<File>,
+	files_by_name: BTreeMap<Pathname, Vec<usize>>,
+	files_by_digest: HashMap<Digest, Vec<usize>>,
+	externals: HashMap<Digest, External>,
+	external_frames: HashMap<Digest, ExternalFrame>,
+	signatures: Vec<Signature>,
+}
+
+impl ParsedDirectory {
+	/// Decompress and parse the directory frame.
+	pub(crate) fn read<F: Read + Seek>(frame: &mut ZstdFrameIterator<'_, F>) -> Result<Self> {
+		let mut hasher = Hasher::new();
+		let mut editions = BTreeMap::new();
+		let mut frames = HashMap::new();
+		let mut files = Vec::new();
+		let mut files_by_name: BTreeMap<_, Vec<_>> = BTreeMap::new();
+		let mut files_by_digest: HashMap<_, Vec<_>> = HashMap::new();
+		let mut externals = HashMap::new();
+		let mut external_frames = HashMap::new();
+		let mut signatures = Vec::new();
+
+		// elements may straddle chunks of decompressed data, so keep what's left of each chunk
+		let mut data = Vec::new();
+		let mut chunk = vec![0; DCtx::out_size()];
+		loop {
+			let read = frame.read_into(&mut chunk)?;
+			if read == 0 {
+				break;
+			}
+			data.extend_from_slice(&chunk[..read]);
 
 			let mut bytes = &data[..];
-			loop {
-				let ((rest, _), element) =
-					ElementFrame::from_bytes((&bytes, 0)).map_err(SimpleError::from_deku)?;
-				bytes = rest;
+			while let Some(length) = element_length(bytes) {
+				let ((rest, _), element) = ElementFrame::from_bytes((&bytes[..length], 0))
+					.map_err(SimpleError::from_deku)?;
+				debug_assert!(rest.is_empty());
+
+				// signatures are over the digest, so they can't be part of it
+				if element.kind != ElementKind::Signature {
+					hasher.update(&bytes[..length]);
+				}
+				bytes = &bytes[length..];
 
 				trace!(?element, "read element");
 				let Some(element) = element.element()? else {
@@ -87,34 +214,176 @@ impl<R: OnDemand> Decoder<R> {
 					}
 					Element::File(file) => {
 						let name = file.name.clone();
-						let digest = file.digest.clone();
+						let digests: Vec<Digest> = file
+							.digest
+							.iter()
+							.chain(file.frame_digests())
+							.cloned()
+							.collect();
 						files.push(*file);
 						let index = files.len() - 1;
 						files_by_name.entry(name).or_default().push(index);
-						if let Some(digest) = digest {
-							files_by_digest.entry(digest).or_default().push(index);
+						for digest in digests {
+							let indices = files_by_digest.entry(digest).or_default();
+							// the content digest is also the frame digest of single-frame files
+							if indices.last() != Some(&index) {
+								indices.push(index);
+							}
 						}
 					}
-				}
-
-				if bytes.is_empty() {
-					trace!("done with this chunk of data");
-					break;
+					Element::External(external) => {
+						externals.insert(external.digest.clone(), *external);
+					}
+					Element::ExternalFrame(frame) => {
+						external_frames.insert(frame.digest.clone(), *frame);
+					}
+					Element::Signature(signature) => {
+						signatures.push(*signature);
+					}
 				}
 			}
+
+			let consumed = data.len() - bytes.len();
+			data.drain(..consumed);
+			trace!(leftover=%data.len(), "done with this chunk of data");
 		}
 
-		self.editions = editions;
-		self.frames = frames;
-		self.files = files;
-		self.files_by_name = files_by_name;
-		self.files_by_digest = files_by_digest;
+		if !data.is_empty() {
+			return Err(SimpleError::new(ErrorKind::Parse)
+				.with_message(format!(
+					"directory ends in the middle of an element ({} bytes left over)",
+					data.len()
+				))
+				.into());
+		}
 
-		trace!("finished reading directory, verify digest");
-		if self.trailer.digest != Digest(hasher.finalize().as_bytes().to_vec()) {
-			return Err(ErrorKind::DirectoryIntegrity("digest").into());
+		Ok(Self {
+			digest: Digest(hasher.finalize().as_bytes().to_vec()),
+			editions,
+			frames,
+			files,
+			files_by_name,
+			files_by_digest,
+			externals,
+			external_frames,
+			signatures,
+		})
+	}
+}
+
+/// Length of the element at the start of some bytes, if it's entirely there.
+fn element_length(bytes: &[u8]) -> Option<usize> {
+	// kind, length, padding
+	let header = bytes.get(..4)?;
+	let length = 4 + usize::from(u16::from_le_bytes([header[1], header[2]]));
+	(bytes.len() >= length).then_some(length)
+}
+
+#[test]
+fn files_by_chunk_digest() {
+	use crate::{
+		encode::Encoder,
+		test_util::{name, open, varied},
+	};
+
+	let content = varied(300_000);
+
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	let (digest, chunks) = zarc.add_chunked_data(&content).expect("add chunks");
+	assert!(chunks.len() > 1);
+	let mut file = zarc.build_file(name("chunked.bin"));
+	file.chunks(digest.clone(), chunks.clone());
+	zarc.add_file_entry(file).expect("add file");
+	zarc.finalise().expect("finalise");
+
+	let zarc = open(bytes);
+	for frame in std::iter::once(&digest).chain(&chunks) {
+		let files = zarc.files_by_digest(frame).expect("indexed");
+		assert_eq!(files.len(), 1);
+		assert_eq!(files[0].digest.as_ref(), Some(&digest));
+	}
+}
+
+#[test]
+fn reference_chains_are_bounded() {
+	use std::sync::Arc;
+
+	use crate::{
+		encode::Encoder,
+		test_util::{error_kind, name, open, random},
+	};
+
+	// each version is the previous one with a byte changed
+	let mut versions = vec![random(50_000)];
+	for n in 0..=MAX_DELTA_DEPTH {
+		let mut next = versions[n].clone();
+		next[n * 1000] ^= 0xff;
+		versions.push(next);
+	}
+
+	let write = |versions: &[Vec<u8>]| {
+		let mut bytes = Vec::new();
+		let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+		let mut reference = zarc.add_data_frame(&versions[0]).expect("add frame");
+		for (n, pair) in versions.windows(2).enumerate() {
+			let digest = zarc
+				.add_delta_frame(&pair[1], &reference, &pair[0])
+				.expect("add delta");
+			let mut file = zarc.build_file(name(&format!("v{}", n + 1)));
+			file.digest(digest.clone());
+			zarc.add_file_entry(file).expect("add file");
+			reference = digest;
 		}
+		zarc.finalise().expect("finalise");
+		bytes
+	};
 
-		Ok(())
+	let deepest = versions.len() - 2;
+	let mut zarc = open(write(&versions[..=deepest]));
+	let file = zarc
+		.latest_file(name(&format!("v{deepest}")))
+		.expect("file");
+	let frame = zarc
+		.frame(file.digest.as_ref().expect("digest"))
+		.expect("frame");
+	assert!(frame.reference.is_some());
+	assert!(
+		frame.length < frame.uncompressed / 10,
+		"compressed as a delta"
+	);
+	assert_eq!(
+		zarc.read_at(file, 0, versions[deepest].len())
+			.expect("read file"),
+		versions[deepest]
+	);
+
+	let mut too_deep = Decoder::open(Arc::<[u8]>::from(write(&versions))).expect("open");
+	let err = too_deep
+		.read_directory()
+		.expect_err("reference chain is too deep");
+	assert_eq!(
+		error_kind(&err),
+		Some(ErrorKind::DirectoryIntegrity("reference chain too deep"))
+	);
+
+	let digest = |n: usize| Digest(blake3::hash(&versions[n]).as_bytes().to_vec());
+	let file = zarc.latest_file(name("v1")).expect("v1").clone();
+	// v1 against itself, then v1 and v0 against each other
+	for cycle in [&[(1, 1)][..], &[(1, 0), (0, 1)]] {
+		for (from, to) in cycle {
+			zarc.frames
+				.get_mut(&digest(*from))
+				.expect("frame")
+				.reference = Some(digest(*to));
+		}
+		let err = zarc.check_references().expect_err("cycle");
+		assert_eq!(
+			error_kind(&err),
+			Some(ErrorKind::DirectoryIntegrity(
+				"reference frames form a cycle"
+			))
+		);
+		assert!(zarc.read_at(&file, 0, 64).is_err());
 	}
 }
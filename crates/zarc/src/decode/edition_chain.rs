@@ -0,0 +1,251 @@
+use std::{collections::BTreeMap, num::NonZeroU16};
+
+use blake3::Hasher;
+use deku::DekuContainerWrite;
+use tracing::{debug, instrument};
+
+use crate::{
+	directory::{ElementFrame, File, Pathname, Signature},
+	encode::DirectoryElements,
+	integrity::Digest,
+	ondemand::OnDemand,
+};
+
+use super::{
+	error::{ErrorKind, Result, SimpleError},
+	Decoder,
+};
+
+/// How an edition links to the previous one.
+///
+/// This is returned by [`Decoder::verify_edition_chain()`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum EditionLink {
+	/// This is the first edition, there's nothing to link to.
+	First,
+
+	/// The edition records the digest of the previous edition's directory, and it matches.
+	Verified,
+
+	/// The edition doesn't record the digest of the previous edition's directory.
+	///
+	/// This is the case for Zarcs written before editions were chained, and for merged editions.
+	Unlinked,
+
+	/// The edition records the digest of the previous edition's directory, but it doesn't match:
+	/// the previous editions were tampered with.
+	///
+	/// Writers only record the digest if they can reproduce it, so this isn't caused by entries
+	/// with keys this version doesn't know, unless the Zarc was appended to by a writer that
+	/// doesn't check.
+	Broken,
+}
+
+impl<R: OnDemand> Decoder<R> {
+	/// Check the chain of editions.
+	///
+	/// Each edition records the directory digest of the previous edition. As appending to a Zarc
+	/// overwrites the directory, the previous directories are recomputed from the entries of the
+	/// current directory which belong to earlier editions, and checked against the recorded
+	/// digests. This shows if history was rewritten when appending.
+	///
+	/// The directory must have been read. Returns how each edition links to the previous one.
+	#[instrument(level = "debug", skip(self))]
+	pub fn verify_edition_chain(&self) -> Result<BTreeMap<NonZeroU16, EditionLink>> {
+		if self.editions.is_empty() {
+			return Err(ErrorKind::ReadOrderViolation("directory must be read first").into());
+		}
+
+		let mut links = BTreeMap::new();
+		let mut previous = None;
+		for edition in self.editions.values() {
+			let link = match (previous, &edition.previous_digest) {
+				(None, _) => EditionLink::First,
+				(Some(_), None) => EditionLink::Unlinked,
+				(Some(number), Some(digest)) => {
+					if self.edition_directory_digest(number)? == *digest {
+						EditionLink::Verified
+					} else {
+						EditionLink::Broken
+					}
+				}
+			};
+			debug!(edition=%edition.number, ?link, "checked edition link");
+
+			links.insert(edition.number, link);
+			previous = Some(edition.number);
+		}
+
+		Ok(links)
+	}
+}
+
+impl<R> Decoder<R> {
+	/// Check that a signature is over the directory digest of the edition it was added in.
+	///
+	/// For the latest edition, that's the current directory digest. For previous editions, the
+	/// digest is recomputed like in [`verify_edition_chain()`](Decoder::verify_edition_chain), so
+	/// this is false if the entries of that edition were tampered with since, or if they can't be
+	/// reproduced. This doesn't check the signature itself, see [`Signature::signer()`].
+	pub fn signs_edition(&self, signature: &Signature) -> bool {
+		if signature.digest == self.trailer.digest {
+			return self.editions.keys().next_back() == Some(&signature.edition);
+		}
+
+		self.editions.contains_key(&signature.edition)
+			&& self
+				.edition_directory_digest(signature.edition)
+				.is_ok_and(|digest| digest == signature.digest)
+	}
+
+	/// Recompute the digest of the directory as it was when an edition was written.
+	///
+	/// This re-encodes the entries as decoded, so it only gives the original digest if they encode
+	/// to the same bytes again. Keys this version doesn't know are dropped when decoding, so a
+	/// directory which has some can't be reproduced.
+	pub(crate) fn edition_directory_digest(&self, number: NonZeroU16) -> Result<Digest> {
+		let mut files: BTreeMap<Pathname, Vec<File>> = BTreeMap::new();
+		for file in self.files.iter().filter(|file| file.edition <= number) {
+			files
+				.entry(file.name.clone())
+				.or_default()
+				.push(file.clone());
+		}
+
+		let elements = DirectoryElements {
+			editions: self
+				.editions
+				.range(..=number)
+				.map(|(_, edition)| edition.clone())
+				.collect(),
+			externals: self
+				.externals
+				.values()
+				.filter(|external| external.edition <= number)
+				.cloned()
+				.collect(),
+			files,
+			frames: self
+				.frames
+				.iter()
+				.filter(|(_, frame)| frame.edition <= number)
+				.map(|(digest, frame)| (digest.clone(), frame.clone()))
+				.collect(),
+			external_frames: self
+				.external_frames
+				.iter()
+				.filter(|(_, frame)| frame.edition <= number)
+				.map(|(digest, frame)| (digest.clone(), frame.clone()))
+				.collect(),
+		};
+
+		let mut hasher = Hasher::new();
+		for element in elements.into_elements() {
+			let frame = ElementFrame::create(&element).map_err(|err| {
+				SimpleError::new(ErrorKind::Parse).with_message(format!("element too large: {err}"))
+			})?;
+			hasher.update(&frame.to_bytes().map_err(SimpleError::from_deku)?);
+		}
+
+		Ok(Digest(hasher.finalize().as_bytes().to_vec()))
+	}
+}
+
+#[test]
+fn appended_editions_verify() {
+	use crate::{
+		encode::Encoder,
+		test_util::{name, open, zarc_bytes},
+	};
+
+	let mut bytes = zarc_bytes(&[("first.txt", b"first")]);
+
+	for (edition, content) in [("second.txt", &b"second"[..]), ("third.txt", b"third")] {
+		let existing = open(bytes.clone());
+		bytes.truncate(usize::try_from(existing.trailer().directory_offset).expect("offset"));
+		let mut zarc = Encoder::append(&mut bytes, &existing).expect("append");
+		let file = zarc.build_file(name(edition));
+		zarc.add_solid_file(file, content).expect("add solid");
+		let mut dir = zarc.build_file(name("dir"));
+		dir.directory();
+		zarc.add_file_entry(dir).expect("add dir");
+		zarc.finalise().expect("finalise");
+	}
+
+	let links = open(bytes.clone()).verify_edition_chain().expect("verify");
+	assert_eq!(
+		links.into_values().collect::<Vec<_>>(),
+		[
+			EditionLink::First,
+			EditionLink::Verified,
+			EditionLink::Verified
+		]
+	);
+
+	// entries that don't encode as they were, like those with keys this version doesn't know,
+	// leave the next edition unlinked rather than broken
+	let mut existing = open(bytes.clone());
+	existing.files[0].mode = Some(0o600);
+	bytes.truncate(usize::try_from(existing.trailer().directory_offset).expect("offset"));
+	Encoder::append(&mut bytes, &existing)
+		.expect("append")
+		.finalise()
+		.expect("finalise");
+
+	let links = open(bytes).verify_edition_chain().expect("verify");
+	assert_eq!(links.values().last(), Some(&EditionLink::Unlinked));
+}
+
+#[test]
+fn signatures_of_previous_editions() {
+	use crate::{
+		encode::Encoder,
+		integrity::SigningKey,
+		test_util::{name, open},
+	};
+
+	let first_key = SigningKey::from_bytes(&[1; 32]);
+	let second_key = SigningKey::from_bytes(&[2; 32]);
+	let wrong_key = SigningKey::from_bytes(&[3; 32]);
+
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	zarc.sign_with(first_key.clone());
+	let file = zarc.build_file(name("first.txt"));
+	zarc.add_solid_file(file, b"first").expect("add solid");
+	zarc.finalise().expect("finalise");
+
+	let signed = open(bytes.clone());
+	assert!(signed.is_signed_by(&first_key.verifying_key()));
+	assert!(!signed.is_signed_by(&wrong_key.verifying_key()));
+	assert!(signed.signatures().all(|sig| signed.signs_edition(sig)));
+
+	bytes.truncate(usize::try_from(signed.trailer().directory_offset).expect("offset"));
+	let mut zarc = Encoder::append(&mut bytes, &signed).expect("append");
+	zarc.sign_with(second_key.clone());
+	let file = zarc.build_file(name("second.txt"));
+	zarc.add_solid_file(file, b"second").expect("add solid");
+	zarc.finalise().expect("finalise");
+
+	// the first signature is kept, and is over the first edition, not the current one
+	let mut appended = open(bytes);
+	assert_eq!(appended.signatures().count(), 2);
+	assert!(appended.signatures().all(|sig| appended.signs_edition(sig)));
+	assert_eq!(appended.signers(), [second_key.verifying_key()]);
+	assert_eq!(appended.edition_signers(1), [first_key.verifying_key()]);
+
+	// a signature over another directory isn't taken for one of a previous edition
+	let mut moved = appended.signatures[0].clone();
+	moved.edition = NonZeroU16::new(2).expect("non-zero");
+	assert!(moved.signer().is_some());
+	assert!(!appended.signs_edition(&moved));
+
+	// once the first edition's entries are changed, its signature no longer matches them
+	appended.files[0].mode = Some(0o600);
+	let first = appended
+		.signatures()
+		.find(|sig| sig.edition.get() == 1)
+		.expect("signature");
+	assert!(!appended.signs_edition(first));
+	assert!(appended.edition_signers(1).is_empty());
+}
@@ -152,6 +152,21 @@ pub enum ErrorKind {
 	/// The directory's integrity is compromised.
 	DirectoryIntegrity(&'static str),
 
+	/// Content doesn't match its digest.
+	ContentIntegrity,
+
+	/// Content is in an external Zarc which hasn't been added as a base.
+	MissingBase,
+
+	/// A Zarc was added as a base but isn't referenced by this one.
+	UnknownBase,
+
+	/// The Zarc is encrypted, and no key was given to open it.
+	Encrypted,
+
+	/// Decryption failed: the key doesn't open this Zarc, or the data was tampered with.
+	Decryption,
+
 	/// Parse error.
 	Parse,
 }
@@ -176,6 +191,11 @@ impl ErrorKind {
 			ErrorKind::DirectoryIntegrity(what) => {
 				Cow::Owned(format!("directory integrity compromised: {what}"))
 			}
+			ErrorKind::ContentIntegrity => Cow::Borrowed("content integrity compromised"),
+			ErrorKind::MissingBase => Cow::Borrowed("missing base zarc"),
+			ErrorKind::UnknownBase => Cow::Borrowed("not a base of this zarc"),
+			ErrorKind::Encrypted => Cow::Borrowed("zarc is encrypted, a key is needed"),
+			ErrorKind::Decryption => Cow::Borrowed("decryption failed"),
 			ErrorKind::Parse => Cow::Borrowed("parse error"),
 		}
 	}
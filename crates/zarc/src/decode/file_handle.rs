@@ -0,0 +1,354 @@
+use std::{
+	fmt,
+	io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult, Seek, SeekFrom},
+};
+
+use crate::{directory::File, integrity::Digest, ondemand::OnDemand};
+
+use super::{
+	error::{ErrorKind, Result},
+	Decoder, RangeIterator,
+};
+
+/// Where a part of a file's content is.
+#[derive(Clone, Debug)]
+pub(crate) struct Segment {
+	/// Offset of this part within the file's content.
+	pub start: u64,
+
+	/// Frame holding this part.
+	pub frame: Digest,
+
+	/// Offset of this part within the frame's uncompressed content.
+	pub offset: u64,
+
+	/// Length of this part.
+	pub length: u64,
+}
+
+impl<R: OnDemand> Decoder<R> {
+	/// Open a file's content for random access.
+	///
+	/// The handle implements [`Read`] and [`Seek`], and is verified as it's read like with
+	/// [`read_range()`](Decoder::read_range). Seeking within a frame still requires decompressing
+	/// that frame from its start, so large files should be packed in several frames to make
+	/// seeking fast, e.g. with [`Encoder::add_seekable_data()`](crate::encode::Encoder::add_seekable_data).
+	///
+	/// Returns `None` if the file has no content.
+	pub fn open_file(&self, file: &File) -> Result<Option<FileHandle<'_, R>>> {
+		Ok(self
+			.segments(file)?
+			.map(|segments| self.file_handle(segments)))
+	}
+
+	/// Make a handle over content made of these segments.
+	pub(crate) fn file_handle(&self, segments: Vec<Segment>) -> FileHandle<'_, R> {
+		FileHandle {
+			decoder: self,
+			length: segments
+				.last()
+				.map_or(0, |segment| segment.start + segment.length),
+			segments,
+			position: 0,
+			current: None,
+			buffer: Vec::new(),
+			consumed: 0,
+		}
+	}
+
+	/// Read part of a file's content.
+	///
+	/// This reads `len` bytes starting at `offset` within the file's content, or fewer if the
+	/// content ends before that. Only the frames holding that part of the content are read, and
+	/// they're verified like with [`read_range()`](Decoder::read_range).
+	pub fn read_at(&self, file: &File, offset: u64, len: usize) -> Result<Vec<u8>> {
+		let end = offset.saturating_add(len as u64);
+		let mut data = Vec::new();
+		for segment in self.segments(file)?.unwrap_or_default() {
+			let segment_end = segment.start + segment.length;
+			if segment_end <= offset || segment.start >= end {
+				continue;
+			}
+
+			let start = segment.offset + offset.saturating_sub(segment.start);
+			let stop = segment.offset + (end.min(segment_end) - segment.start);
+			let Some(range) = self.read_range(&segment.frame, start..stop)? else {
+				return Err(ErrorKind::DirectoryIntegrity("file references missing frame").into());
+			};
+
+			for chunk in range {
+				data.extend(chunk?);
+			}
+		}
+
+		Ok(data)
+	}
+}
+
+impl<R> Decoder<R> {
+	/// Work out where each part of a file's content is.
+	pub(crate) fn segments(&self, file: &File) -> Result<Option<Vec<Segment>>> {
+		if file.digest.is_none() {
+			return Ok(None);
+		}
+
+		if let Some(solid) = &file.solid {
+			return Ok(Some(vec![Segment {
+				start: 0,
+				frame: solid.frame.clone(),
+				offset: solid.offset,
+				length: solid.length,
+			}]));
+		}
+
+		let mut start = 0;
+		let mut segments = Vec::new();
+		for digest in file.frame_digests() {
+			let length = self
+				.frames
+				.get(digest)
+				.map(|frame| frame.uncompressed)
+				.or_else(|| {
+					self.external_frames
+						.get(digest)
+						.map(|frame| frame.uncompressed)
+				})
+				.ok_or(ErrorKind::DirectoryIntegrity(
+					"file references missing frame",
+				))?;
+
+			segments.push(Segment {
+				start,
+				frame: digest.clone(),
+				offset: 0,
+				length,
+			});
+			start += length;
+		}
+
+		Ok(Some(segments))
+	}
+}
+
+/// Random access to a file's content.
+///
+/// This is returned by [`Decoder::open_file()`][super::Decoder::open_file].
+///
+/// Reading decompresses and verifies content as needed, and keeps going from where it left off,
+/// so sequential reads are as fast as with [`Decoder::read_file()`][super::Decoder::read_file].
+/// Seeking elsewhere starts decompressing again from the start of the frame holding that part.
+///
+/// Errors from the decoder are returned as I/O errors wrapping the decoder error.
+pub struct FileHandle<'decoder, R: OnDemand> {
+	decoder: &'decoder Decoder<R>,
+	segments: Vec<Segment>,
+	length: u64,
+	position: u64,
+
+	// reading from the current frame, after the buffer
+	current: Option<RangeIterator<'decoder, R::Reader>>,
+
+	// verified content at the current position, from `consumed` onwards
+	buffer: Vec<u8>,
+	consumed: usize,
+}
+
+impl<R: OnDemand> fmt::Debug for FileHandle<'_, R> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("FileHandle")
+			.field("segments", &self.segments)
+			.field("length", &self.length)
+			.field("position", &self.position)
+			.field("buffered", &(self.buffer.len() - self.consumed))
+			.finish_non_exhaustive()
+	}
+}
+
+impl<R: OnDemand> FileHandle<'_, R> {
+	/// Return the size of the content.
+	pub fn size(&self) -> u64 {
+		self.length
+	}
+
+	fn open_segment(&mut self) -> IoResult<()> {
+		let Some(segment) = self.segments.iter().find(|segment| {
+			segment.start <= self.position && self.position < segment.start + segment.length
+		}) else {
+			return Err(IoError::new(
+				IoErrorKind::UnexpectedEof,
+				"position is within the content but not within any frame",
+			));
+		};
+
+		let start = segment.offset + (self.position - segment.start);
+		let range = self
+			.decoder
+			.read_range(&segment.frame, start..(segment.offset + segment.length))
+			.map_err(IoError::other)?
+			.ok_or_else(|| IoError::other("file references missing frame"))?;
+		self.current = Some(range);
+		Ok(())
+	}
+}
+
+impl<R: OnDemand> Read for FileHandle<'_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+		loop {
+			let buffered = &self.buffer[self.consumed..];
+			if !buffered.is_empty() {
+				let n = buffered.len().min(buf.len());
+				buf[..n].copy_from_slice(&buffered[..n]);
+				self.consumed += n;
+				self.position += n as u64;
+				return Ok(n);
+			}
+
+			if self.position >= self.length || buf.is_empty() {
+				return Ok(0);
+			}
+
+			if self.current.is_none() {
+				self.open_segment()?;
+			}
+
+			#[allow(clippy::unwrap_used)] // UNWRAP: opened just above
+			match self.current.as_mut().unwrap().next() {
+				Some(Ok(data)) => {
+					self.buffer = data;
+					self.consumed = 0;
+				}
+				Some(Err(err)) => {
+					self.current = None;
+					return Err(IoError::other(err));
+				}
+				None => {
+					self.current = None;
+				}
+			}
+		}
+	}
+}
+
+impl<R: OnDemand> Seek for FileHandle<'_, R> {
+	fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+		let position = match pos {
+			SeekFrom::Start(offset) => Some(offset),
+			SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+			SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+		}
+		.ok_or_else(|| {
+			IoError::new(
+				IoErrorKind::InvalidInput,
+				"invalid seek to a negative or overflowing position",
+			)
+		})?;
+
+		let buffered = (self.buffer.len() - self.consumed) as u64;
+		if position >= self.position && position - self.position <= buffered {
+			// within what's already been read
+			self.consumed += (position - self.position) as usize;
+		} else {
+			self.current = None;
+			self.buffer.clear();
+			self.consumed = 0;
+		}
+
+		self.position = position;
+		Ok(position)
+	}
+}
+
+#[test]
+fn reads_at_any_offset() {
+	use crate::{
+		encode::Encoder,
+		integrity::OUTBOARD_GROUP_SIZE,
+		test_util::{name, open, varied},
+	};
+
+	const FRAME_SIZE: usize = 40_000;
+	let content = varied(3 * FRAME_SIZE + 1234);
+
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	zarc.set_outboard_threshold(Some(0));
+	let (digest, chunks) = zarc
+		.add_seekable_data(&content, FRAME_SIZE)
+		.expect("add frames");
+	assert_eq!(chunks.len(), 4);
+	let mut file = zarc.build_file(name("seekable.bin"));
+	file.chunks(digest, chunks);
+	zarc.add_file_entry(file).expect("add file");
+	let file = zarc.build_file(name("solid.txt"));
+	zarc.add_solid_file(file, &content[..5000])
+		.expect("add solid");
+	zarc.finalise().expect("finalise");
+	let zarc = open(bytes);
+
+	let len = content.len() as u64;
+	let frame = FRAME_SIZE as u64;
+	let group = OUTBOARD_GROUP_SIZE as u64;
+	let offsets = [
+		0,
+		1,
+		group - 1,
+		group,
+		frame - 1,
+		frame,
+		frame + 1,
+		2 * frame + group,
+		len - 1,
+		len,
+		len + 10,
+	];
+
+	for (path, content) in [
+		("seekable.bin", &content[..]),
+		("solid.txt", &content[..5000]),
+	] {
+		let file = zarc.latest_file(name(path)).expect("file");
+		let mut handle = zarc.open_file(file).expect("open").expect("content");
+		assert_eq!(handle.size(), content.len() as u64);
+
+		let expected = |offset: u64, length: usize| {
+			let start = (offset as usize).min(content.len());
+			&content[start..(start + length).min(content.len())]
+		};
+
+		for offset in offsets {
+			// within a frame, across the next boundary, and to the end
+			for length in [1, 100, FRAME_SIZE + 10, content.len()] {
+				assert_eq!(
+					zarc.read_at(file, offset, length).expect("read at"),
+					expected(offset, length),
+					"{path}: read_at({offset}, {length})"
+				);
+			}
+
+			handle.seek(SeekFrom::Start(offset)).expect("seek");
+			let mut buf = [0; 100];
+			let mut read = 0;
+			while read < buf.len() {
+				match handle.read(&mut buf[read..]).expect("read") {
+					0 => break,
+					n => read += n,
+				}
+			}
+			assert_eq!(
+				&buf[..read],
+				expected(offset, 100),
+				"{path}: handle at {offset}"
+			);
+		}
+
+		// backwards from the end, within what's buffered and before it
+		handle.seek(SeekFrom::End(-10)).expect("seek");
+		handle.seek(SeekFrom::Current(-5)).expect("seek");
+		let mut rest = Vec::new();
+		handle.read_to_end(&mut rest).expect("read to end");
+		assert_eq!(rest, &content[(content.len() - 15)..]);
+		assert!(handle
+			.seek(SeekFrom::Current(-(content.len() as i64) - 1))
+			.is_err());
+	}
+}
@@ -0,0 +1,227 @@
+use std::{
+	fmt,
+	io::{BufRead, Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult},
+	vec::IntoIter,
+};
+
+use zstd_safe::DCtx;
+
+use crate::{directory::File, integrity::Digest, ondemand::OnDemand};
+
+use super::{
+	content_iterator::ContentWindow,
+	error::{Error, ErrorKind, Result, SimpleError},
+	Decoder, ZstdFrameIterator,
+};
+
+impl<R: OnDemand> Decoder<R> {
+	/// Read the content of a file with [`Read`] and [`BufRead`].
+	///
+	/// This reads the same content as [`read_file()`](Decoder::read_file), but reuses its buffers
+	/// instead of allocating for each chunk, and can be given to anything that takes a reader.
+	///
+	/// The content's digest is checked at the end: if it doesn't match, reading returns an error
+	/// of kind [`InvalidData`](std::io::ErrorKind::InvalidData) instead of the end of the content,
+	/// which wraps a decoder error of kind [`ContentIntegrity`](ErrorKind::ContentIntegrity).
+	/// Other decoder errors are returned as I/O errors of kind [`Other`](std::io::ErrorKind::Other).
+	///
+	/// Returns `None` if the file has no content.
+	pub fn file_reader(&self, file: &File) -> Result<Option<FileReader<'_, R>>> {
+		let Some(digest) = &file.digest else {
+			return Ok(None);
+		};
+
+		let frames: Vec<Digest> = file.frame_digests().cloned().collect();
+		Ok(Some(FileReader {
+			decoder: self,
+			frames: frames.into_iter(),
+			current: None,
+			hasher: blake3::Hasher::new(),
+			digest: digest.clone(),
+			output: Vec::new(),
+			consumed: 0,
+			filled: 0,
+			window: ContentWindow::new(file),
+			verified: None,
+		}))
+	}
+}
+
+/// Reader over a file's content.
+///
+/// This is returned by [`Decoder::file_reader()`][super::Decoder::file_reader].
+///
+/// It decompresses content as it's read, going through each content frame in order, and checks
+/// the digest of the whole content once it's all been read.
+pub struct FileReader<'decoder, R: OnDemand> {
+	decoder: &'decoder Decoder<R>,
+	frames: IntoIter<Digest>,
+	current: Option<ZstdFrameIterator<'decoder, R::Reader>>,
+	hasher: blake3::Hasher,
+	digest: Digest,
+
+	// reused for every step: decompressed output is in output[consumed..filled]
+	output: Vec<u8>,
+	consumed: usize,
+	filled: usize,
+
+	window: ContentWindow,
+	verified: Option<bool>,
+}
+
+impl<R: OnDemand> fmt::Debug for FileReader<'_, R> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("FileReader")
+			.field("frames", &self.frames)
+			.field("hasher", &self.hasher)
+			.field("digest", &self.digest)
+			.field("buffered", &(self.filled - self.consumed))
+			.field("window", &self.window)
+			.field("verified", &self.verified)
+			.finish_non_exhaustive()
+	}
+}
+
+impl<R: OnDemand> FileReader<'_, R> {
+	/// Check the digest of the content against the file's digest.
+	///
+	/// Returns None if the content hasn't been entirely read yet.
+	pub fn verify(&self) -> Option<bool> {
+		self.verified
+	}
+
+	fn integrity_error() -> IoError {
+		IoError::new(
+			IoErrorKind::InvalidData,
+			Error::from(
+				SimpleError::new(ErrorKind::ContentIntegrity)
+					.with_message("file content doesn't match its digest"),
+			),
+		)
+	}
+
+	/// Decompress the next step, or move to the next frame, or finish.
+	fn step(&mut self) -> Result<()> {
+		self.consumed = 0;
+		self.filled = 0;
+
+		if self.window.is_exhausted() {
+			self.current = None;
+			self.frames = Vec::new().into_iter();
+		}
+
+		if let Some(framer) = &mut self.current {
+			if self.output.is_empty() {
+				self.output = vec![0; DCtx::out_size()];
+			}
+
+			let read = framer.read_into(&mut self.output)?;
+			if read == 0 {
+				self.current = None;
+				return Ok(());
+			}
+
+			let range = self.window.clip(read);
+			self.consumed = range.start;
+			self.filled = range.end;
+
+			self.hasher.update(&self.output[self.consumed..self.filled]);
+			return Ok(());
+		}
+
+		let Some(digest) = self.frames.next() else {
+			let digest = Digest(self.hasher.finalize().as_bytes().to_vec());
+			self.verified = Some(digest == self.digest);
+			return Ok(());
+		};
+
+		let Some(frame) = self.decoder.read_content_frame(&digest)? else {
+			return Err(ErrorKind::DirectoryIntegrity("file references missing frame").into());
+		};
+		self.current = Some(frame.into_framer());
+		Ok(())
+	}
+}
+
+impl<R: OnDemand> BufRead for FileReader<'_, R> {
+	fn fill_buf(&mut self) -> IoResult<&[u8]> {
+		while self.consumed == self.filled {
+			match self.verified {
+				Some(true) => return Ok(&[]),
+				Some(false) => return Err(Self::integrity_error()),
+				None => self.step().map_err(|err| match err {
+					Error::Io(err) => err,
+					err => IoError::other(err),
+				})?,
+			}
+		}
+
+		Ok(&self.output[self.consumed..self.filled])
+	}
+
+	fn consume(&mut self, amt: usize) {
+		self.consumed = (self.consumed + amt).min(self.filled);
+	}
+}
+
+impl<R: OnDemand> Read for FileReader<'_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+		let available = self.fill_buf()?;
+		let n = available.len().min(buf.len());
+		buf[..n].copy_from_slice(&available[..n]);
+		self.consume(n);
+		Ok(n)
+	}
+}
+
+#[test]
+fn reads_files_with_read_and_bufread() {
+	use crate::{
+		encode::Encoder,
+		test_util::{error_kind, name, open, varied},
+	};
+
+	let content = varied(300_000);
+	let lines = b"first line\nsecond line\nthird";
+
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	let (digest, chunks) = zarc.add_chunked_data(&content).expect("add chunks");
+	assert!(chunks.len() > 1);
+	let mut file = zarc.build_file(name("chunked.bin"));
+	file.chunks(digest, chunks);
+	zarc.add_file_entry(file).expect("add file");
+	for (path, content) in [("before.txt", &b"before"[..]), ("lines.txt", lines)] {
+		let file = zarc.build_file(name(path));
+		zarc.add_solid_file(file, content).expect("add solid");
+	}
+	zarc.finalise().expect("finalise");
+	let zarc = open(bytes);
+
+	let chunked = zarc.latest_file(name("chunked.bin")).expect("file");
+	let mut reader = zarc.file_reader(chunked).expect("reader").expect("content");
+	let mut read = Vec::new();
+	reader.read_to_end(&mut read).expect("read");
+	assert_eq!(read, content);
+	assert_eq!(reader.verify(), Some(true));
+
+	// the slice of the solid frame which is this file, line by line
+	let solid = zarc.latest_file(name("lines.txt")).expect("file");
+	let reader = zarc.file_reader(solid).expect("reader").expect("content");
+	let read: Vec<String> = reader.lines().collect::<IoResult<_>>().expect("lines");
+	assert_eq!(read, ["first line", "second line", "third"]);
+
+	let mut wrong = solid.clone();
+	wrong.digest = chunked.digest.clone();
+	let mut reader = zarc.file_reader(&wrong).expect("reader").expect("content");
+	let err = reader
+		.read_to_end(&mut Vec::new())
+		.expect_err("digest mismatch");
+	assert_eq!(err.kind(), IoErrorKind::InvalidData);
+	let err = err
+		.into_inner()
+		.and_then(|err| err.downcast::<Error>().ok())
+		.expect("decoder error");
+	assert_eq!(error_kind(&err), Some(ErrorKind::ContentIntegrity));
+	assert_eq!(reader.verify(), Some(false));
+}
@@ -1,30 +1,106 @@
 //! Decoder types and functions.
 
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, Take};
 
-use crate::{integrity::Digest, ondemand::OnDemand};
+use crate::{encode::MAX_DELTA_DEPTH, integrity::Digest, ondemand::OnDemand};
 
-use super::{error::Result, Decoder, ZstdFrameIterator};
+use super::{
+	error::{ErrorKind, Result, SimpleError},
+	Decoder, ZstdFrameIterator,
+};
 
 impl<R: OnDemand> Decoder<R> {
 	/// Decompress a content frame by digest.
 	///
 	/// This returns an iterator of chunks of bytes. Each call to the iterator decompresses some
 	/// data and returns it, until the frame is exhausted.
+	///
+	/// If the frame is stored in an external Zarc, it's read from there, and this errors if that
+	/// Zarc hasn't been [added as a base](Decoder::add_base).
 	pub fn read_content_frame(
 		&self,
 		digest: &Digest,
 	) -> Result<Option<FrameIterator<'_, R::Reader>>> {
+		self.read_content_frame_at(digest, 0)
+	}
+
+	/// Decompress a content frame which is `depth` references deep.
+	pub(crate) fn read_content_frame_at(
+		&self,
+		digest: &Digest,
+		depth: usize,
+	) -> Result<Option<FrameIterator<'_, R::Reader>>> {
+		if depth > MAX_DELTA_DEPTH {
+			return Err(ErrorKind::DirectoryIntegrity("reference chain too deep").into());
+		}
+
 		let Some(entry) = self.frames.get(digest) else {
-			return Ok(None);
+			let Some(external) = self.external_frames.get(digest) else {
+				return Ok(None);
+			};
+
+			return self
+				.base(&external.external)?
+				.read_content_frame_at(digest, depth);
+		};
+
+		let framer = if let Some(reference) = &entry.reference {
+			self.read_zstandard_frame_with(
+				entry.offset,
+				entry.length,
+				self.create_delta_dctx(reference, depth + 1)?,
+			)?
+		} else {
+			self.read_zstandard_frame(entry.offset, entry.length, entry.dictionary.as_ref())?
 		};
 
 		Ok(Some(FrameIterator::new(
-			self.read_zstandard_frame(entry.offset)?,
+			framer,
 			digest.clone(),
 			entry.uncompressed,
 		)))
 	}
+
+	/// Read a content frame's raw bytes by digest.
+	///
+	/// This opens a new reader at the frame, and returns a reader limited to the frame's
+	/// length: it yields exactly one entire Zstandard frame, still compressed. This is useful to copy
+	/// frames to another Zarc, see [`Encoder::add_raw_frame()`](crate::encode::Encoder::add_raw_frame).
+	///
+	/// Frames of encrypted Zarcs can't be read raw, as they'd need to be decrypted anyway.
+	pub fn read_raw_content_frame(&self, digest: &Digest) -> Result<Option<Take<R::Reader>>> {
+		if self.is_encrypted() {
+			return Err(SimpleError::new(ErrorKind::Encrypted)
+				.with_message("can't read raw frames from an encrypted zarc")
+				.into());
+		}
+
+		let Some(entry) = self.frames.get(digest) else {
+			return Ok(None);
+		};
+
+		let reader = self
+			.reader
+			.open_range(entry.offset..(entry.offset + entry.length))?;
+		Ok(Some(reader.take(entry.length)))
+	}
+}
+
+impl<R> Decoder<R> {
+	/// Get the base Zarc with this digest.
+	pub(crate) fn base(&self, digest: &Digest) -> Result<&Self> {
+		self.bases
+			.iter()
+			.find(|base| &base.trailer.digest == digest)
+			.ok_or_else(|| {
+				SimpleError::new(ErrorKind::MissingBase)
+					.with_message(format!(
+						"missing base: content is in a zarc with digest {:02x?}, which wasn't provided",
+						digest.as_slice()
+					))
+					.into()
+			})
+	}
 }
 
 /// Iterator over a Zarc content frame's chunks.
@@ -86,6 +162,11 @@ impl<'zstd, R> FrameIterator<'zstd, R> {
 	pub fn verify(&self) -> Option<bool> {
 		self.digest().map(|d| d == self.digest)
 	}
+
+	/// Get the underlying decompressor, for reading the frame without hashing it.
+	pub(crate) fn into_framer(self) -> ZstdFrameIterator<'zstd, R> {
+		self.framer
+	}
 }
 
 impl<'zstd, R: Read + Seek> Iterator for FrameIterator<'zstd, R> {
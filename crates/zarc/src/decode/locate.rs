@@ -0,0 +1,213 @@
+use std::{
+	io::{Read, Seek, SeekFrom},
+	ops::Range,
+};
+
+use deku::DekuContainerRead;
+use ozarc::framing::{ZstandardBlockType, SKIPPABLE_FRAME_MAGIC, ZSTANDARD_FRAME_MAGIC};
+use tracing::{debug, instrument, trace};
+
+use crate::{
+	constants::ZARC_MAGIC,
+	header::FILE_MAGIC,
+	ondemand::{OnDemand, SubRange},
+	trailer::{Epilogue, EPILOGUE_LENGTH},
+};
+
+use super::{
+	error::{ErrorKind, Result, SimpleError},
+	Decoder,
+};
+
+/// How much to read at once when looking for the header.
+const SCAN_BUFFER: usize = 1024 * 1024;
+
+impl<R: OnDemand> Decoder<R> {
+	/// Find a Zarc at the end of a larger source.
+	///
+	/// The source must end with the Zarc, like an archive appended to an executable. This checks
+	/// that there's a Zarc trailer at the end, then looks for the Zarc header from the start of the
+	/// source. As the header's bytes could appear elsewhere, each candidate is checked by walking
+	/// the frames from there: the Zarc starts at the first header from which the frames lead
+	/// exactly to the directory.
+	///
+	/// Returns the range of the source which is the Zarc, to open with a
+	/// [`SubRange`](crate::ondemand::SubRange).
+	#[instrument(level = "debug", skip(source))]
+	pub fn locate(source: &R) -> Result<Range<u64>> {
+		let mut reader = source.open()?;
+		let length = reader.seek(SeekFrom::End(0))?;
+		if length < (FILE_MAGIC.len() + EPILOGUE_LENGTH) as u64 {
+			return Err(not_found("source is too short"));
+		}
+
+		reader.seek(SeekFrom::End(-(EPILOGUE_LENGTH as i64)))?;
+		let mut ending = [0; EPILOGUE_LENGTH];
+		reader.read_exact(&mut ending)?;
+		let (_, epilogue) = Epilogue::from_bytes((&ending, 0))
+			.map_err(|_| not_found("source doesn't end with a zarc trailer"))?;
+		if epilogue.magic != ZARC_MAGIC {
+			return Err(not_found("source doesn't end with a zarc trailer"));
+		}
+		debug!(?epilogue, "found zarc trailer");
+
+		// a negative offset is from the end, so the same wherever the zarc starts
+		let directory = |start: u64| -> Option<u64> {
+			if epilogue.directory_offset < 0 {
+				length.checked_add_signed(epilogue.directory_offset)
+			} else {
+				start.checked_add(epilogue.directory_offset as u64)
+			}
+		};
+
+		let mut buffer = vec![0; SCAN_BUFFER];
+		let mut position = 0;
+		while position < length {
+			reader.seek(SeekFrom::Start(position))?;
+			let mut filled = 0;
+			while filled < buffer.len() {
+				let bytes = reader.read(&mut buffer[filled..])?;
+				if bytes == 0 {
+					break;
+				}
+				filled += bytes;
+			}
+
+			for (index, window) in buffer[..filled].windows(FILE_MAGIC.len()).enumerate() {
+				if window != FILE_MAGIC {
+					continue;
+				}
+
+				let start = position + index as u64;
+				let Some(directory) = directory(start).filter(|&dir| dir > start) else {
+					continue;
+				};
+
+				trace!(%start, %directory, "found zarc header, check frames");
+				if Self::frames_lead_to(&mut reader, start, directory)? {
+					let version = Self::read_header(source, start)?;
+					debug!(%start, %version, "found zarc");
+					return Ok(start..length);
+				}
+			}
+
+			if filled < buffer.len() {
+				break;
+			}
+
+			// overlap so headers straddling buffers are found
+			position += (filled - FILE_MAGIC.len() + 1) as u64;
+		}
+
+		Err(not_found("no zarc header leads to the directory"))
+	}
+
+	/// Walk frames from `start`, and check that they end exactly at `end`.
+	fn frames_lead_to(reader: &mut R::Reader, start: u64, end: u64) -> Result<bool> {
+		let mut position = start;
+		while position < end {
+			reader.seek(SeekFrom::Start(position))?;
+			let mut magic = [0; 4];
+			if reader.read_exact(&mut magic).is_err() {
+				return Ok(false);
+			}
+
+			if magic[0] & 0xF0 == 0x50 && magic[1..] == *SKIPPABLE_FRAME_MAGIC {
+				let mut size = [0; 4];
+				if reader.read_exact(&mut size).is_err() {
+					return Ok(false);
+				}
+				position += 8 + u64::from(u32::from_le_bytes(size));
+			} else if magic == ZSTANDARD_FRAME_MAGIC {
+				reader.seek(SeekFrom::Start(position))?;
+				let Ok(header) = Self::read_zstandard_frame_header(reader) else {
+					return Ok(false);
+				};
+
+				loop {
+					let Ok(block) = Self::read_zstandard_block_header(reader) else {
+						return Ok(false);
+					};
+					if block.block_type == ZstandardBlockType::Reserved {
+						return Ok(false);
+					}
+
+					reader.seek(SeekFrom::Current(i64::from(block.actual_size())))?;
+					if block.last {
+						break;
+					}
+				}
+
+				if header.frame_descriptor.checksum {
+					reader.seek(SeekFrom::Current(4))?;
+				}
+				position = reader.stream_position()?;
+			} else {
+				trace!(%position, magic=%format!("{magic:02x?}"), "not a frame");
+				return Ok(false);
+			}
+		}
+
+		Ok(position == end)
+	}
+}
+
+impl<T: OnDemand> Decoder<SubRange<T>> {
+	/// Open a Zarc at the end of a larger source.
+	///
+	/// This [locates](Decoder::locate) the Zarc, then [opens](Decoder::open) the range of the
+	/// source which it occupies. Offsets within the Zarc are then relative to its header.
+	pub fn open_embedded(source: T) -> Result<Self> {
+		let range = Decoder::<T>::locate(&source)?;
+		Self::open(SubRange::new(source, range.start, range.end - range.start))
+	}
+}
+
+fn not_found(message: &'static str) -> super::error::Error {
+	SimpleError::new(ErrorKind::Parse)
+		.with_message(format!("zarc not found: {message}"))
+		.into()
+}
+
+#[test]
+fn finds_embedded_zarc() {
+	use std::sync::Arc;
+
+	use crate::test_util::{name, random, zarc_bytes};
+
+	let zarc = zarc_bytes(&[("file.txt", b"embedded")]);
+	let with_false_header = [&random(5000)[..], &FILE_MAGIC, &random(100)].concat();
+	let straddling = [&random(SCAN_BUFFER - 3)[..], &FILE_MAGIC, &random(100)].concat();
+	for leading in [Vec::new(), random(12_345), with_false_header, straddling] {
+		let source: Arc<[u8]> = [&leading[..], &zarc].concat().into();
+		let range = Decoder::locate(&source).expect("locate");
+		assert_eq!(range, (leading.len() as u64)..(source.len() as u64));
+
+		let mut embedded = Decoder::open_embedded(source).expect("open embedded");
+		embedded.read_directory().expect("read directory");
+		let file = embedded.latest_file(name("file.txt")).expect("file");
+		assert_eq!(
+			embedded.read_at(file, 0, 8).expect("read file"),
+			b"embedded"
+		);
+	}
+}
+
+#[test]
+fn locate_needs_a_zarc() {
+	use std::sync::Arc;
+
+	use crate::test_util::{error_kind, random, zarc_bytes};
+
+	let plain: Arc<[u8]> = random(5000).into();
+	let err = Decoder::locate(&plain).expect_err("no trailer");
+	assert_eq!(error_kind(&err), Some(ErrorKind::Parse));
+
+	// a trailer with no header to lead to it
+	let zarc = zarc_bytes(&[("file.txt", b"embedded")]);
+	let headless: Arc<[u8]> = [&random(5000)[..], &zarc[FILE_MAGIC.len()..]]
+		.concat()
+		.into();
+	let err = Decoder::locate(&headless).expect_err("no header");
+	assert_eq!(error_kind(&err), Some(ErrorKind::Parse));
+}
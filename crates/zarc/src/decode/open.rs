@@ -8,7 +8,8 @@ use ozarc::framing::SkippableFrame;
 use tracing::{debug, instrument, trace, warn};
 
 use crate::{
-	header::Header,
+	encryption::{ContentCipher, EncryptionKey, KeyStanza, KEYS_FRAME_NIBBLE},
+	header::{Header, FILE_MAGIC},
 	ondemand::OnDemand,
 	trailer::{Epilogue, Trailer, EPILOGUE_LENGTH},
 };
@@ -18,52 +19,57 @@ use super::{
 	Decoder,
 };
 
+/// Magic of the Keys frame, which follows the header in encrypted Zarcs.
+pub(crate) const KEYS_FRAME_MAGIC: [u8; 4] = [0x50 + KEYS_FRAME_NIBBLE, 0x2A, 0x4D, 0x18];
+
 impl<R: OnDemand> Decoder<R> {
 	/// Read a Skippable frame, checking its nibble.
 	///
 	/// Reads and returns the entire frame's payload, and thus seeks to the end of the frame.
 	#[cfg_attr(feature = "expose-internals", visibility::make(pub))]
 	#[instrument(level = "debug", skip(reader))]
-	fn read_skippable_frame(reader: &mut R::Reader, nibble: u8) -> Result<SkippableFrame> {
-		let (bits_read, frame) =
-			SkippableFrame::from_reader((reader, 0)).map_err(SimpleError::from_deku)?;
-		debug!(%bits_read, frame=format!("{frame:02x?}"), nibble=%format!("0x{:X}", frame.nibble()), "read skippable frame");
-
-		if frame.nibble() != nibble {
-			return Err(ErrorKind::InvalidNibble {
-				expected: nibble,
-				actual: frame.nibble(),
-			}
-			.into());
-		}
-
-		Ok(frame)
+	pub(crate) fn read_skippable_frame(
+		reader: &mut R::Reader,
+		nibble: u8,
+	) -> Result<SkippableFrame> {
+		parse_skippable_frame(reader, nibble)
 	}
 
 	/// Read a Zarc header.
 	///
+	/// The header is at `offset` in the source: that's zero for a standalone Zarc, but it can be
+	/// further in for a Zarc embedded in a larger file, see [`locate()`](Decoder::locate).
+	///
 	/// Returns the file version in the header.
 	#[cfg_attr(feature = "expose-internals", visibility::make(pub))]
 	#[instrument(level = "debug", skip(ondemand))]
-	fn read_header(ondemand: &R) -> Result<NonZeroU8> {
+	pub(crate) fn read_header(ondemand: &R, offset: u64) -> Result<NonZeroU8> {
 		let mut reader = ondemand.open()?;
+		reader.seek(SeekFrom::Start(offset))?;
 		let frame = Self::read_skippable_frame(&mut reader, 0x0)?;
+		parse_header(frame.data)
+	}
 
-		let mut content = Cursor::new(frame.data);
-		let (bits_read, header) =
-			Header::from_reader((&mut content, 0)).map_err(SimpleError::from_deku)?;
-		debug!(%bits_read, header=format!("{header:02x?}"), "read zarc header");
+	/// Read the Zarc Keys frame, if there's one.
+	///
+	/// This opens a new reader and seeks to just after the header. If the frame there isn't a
+	/// Keys frame, the Zarc isn't encrypted, and this returns an empty list.
+	#[cfg_attr(feature = "expose-internals", visibility::make(pub))]
+	#[instrument(level = "debug", skip(ondemand))]
+	fn read_keys(ondemand: &R) -> Result<Vec<KeyStanza>> {
+		let mut reader = ondemand.open()?;
+		reader.seek(SeekFrom::Start(FILE_MAGIC.len() as _))?;
 
-		debug_assert_ne!(crate::constants::ZARC_VERSION, 0);
-		debug_assert_ne!(header.version, 0);
-		if header.version != crate::constants::ZARC_VERSION {
-			return Err(ErrorKind::UnsupportedZarcVersion(header.version).into());
+		let mut magic = [0; 4];
+		reader.read_exact(&mut magic)?;
+		if magic != KEYS_FRAME_MAGIC {
+			trace!(magic=%format!("{magic:02x?}"), "no keys frame, zarc is not encrypted");
+			return Ok(Vec::new());
 		}
 
-		Ok(unsafe {
-			// SAFETY: the version is valid and zarc versions start at 1
-			NonZeroU8::new_unchecked(header.version)
-		})
+		reader.seek(SeekFrom::Start(FILE_MAGIC.len() as _))?;
+		let frame = Self::read_skippable_frame(&mut reader, KEYS_FRAME_NIBBLE)?;
+		parse_keys(&frame.data)
 	}
 
 	/// Read the Zarc Trailer.
@@ -79,7 +85,7 @@ impl<R: OnDemand> Decoder<R> {
 		// seek to the end to figure out how long this file is
 		reader.seek(SeekFrom::End(0))?;
 		let file_length = reader.stream_position()?;
-		let ending_length = file_length.min(1024);
+		let ending_length = file_length.min(TRAILER_SEARCH_LENGTH);
 		trace!(%file_length, reading_bytes=%ending_length, "reading end of file");
 
 		// read up to 1KB from the end of the file
@@ -89,46 +95,7 @@ impl<R: OnDemand> Decoder<R> {
 		trace!(%bytes, data=%format!("{bytes:02x?}"), "read end of file");
 		debug_assert_eq!(bytes, ending_length as _);
 
-		// read the epilogue out of the end of the ending
-		let ((rest, remaining_bits), epilogue) =
-			Epilogue::from_bytes((&ending[(bytes - EPILOGUE_LENGTH)..], 0))
-				.map_err(SimpleError::from_deku)?;
-		debug!(?epilogue, "read zarc trailer epilogue");
-
-		if remaining_bits > 0 {
-			trace!(%remaining_bits, ?rest, "some data remaining");
-			return Err(SimpleError::new(ErrorKind::Parse)
-				.with_message(format!(
-					"parse error: too much data ({remaining_bits} bits) {rest:02x?}"
-				))
-				.into());
-		}
-
-		// check we have enough data
-		let trailer_length = epilogue.full_length();
-		if bytes < trailer_length {
-			todo!("read more bytes");
-		}
-
-		// complete reading the trailer
-		// UNWRAP: we know we have enough data, we just checked
-		let mut trailer = epilogue.complete(&ending).expect("not enough data");
-		debug!(bytes=%trailer.len(), trailer=format!("{trailer:02x?}"), "read zarc trailer");
-
-		// compare the check byte
-		let check_byte = trailer.compute_check();
-		if check_byte != epilogue.check {
-			return Err(SimpleError::new(ErrorKind::Parse)
-				.with_message(format!(
-				"parse error: trailer check byte doesn't match (expected 0x{:02X}, got 0x{check_byte:02X})",
-				epilogue.check
-			))
-				.into());
-		}
-
-		trailer.make_offset_positive(file_length);
-		debug!(offset=%trailer.directory_offset, "reified directory offset");
-
+		let trailer = parse_trailer(&ending, file_length)?;
 		Ok((trailer, file_length))
 	}
 
@@ -138,22 +105,218 @@ impl<R: OnDemand> Decoder<R> {
 	/// the integrity of the trailer.
 	///
 	/// You'll then need to read the directory and extract some files!
+	///
+	/// If the Zarc is [encrypted](Decoder::is_encrypted), this succeeds, but reading the directory
+	/// will fail: use [`open_with_key()`](Decoder::open_with_key) instead.
 	pub fn open(reader: R) -> Result<Self> {
-		let version = Self::read_header(&reader)?;
+		let version = Self::read_header(&reader, 0)?;
 		let (trailer, file_length) = Self::read_trailer(&reader)?;
+		let key_stanzas = Self::read_keys(&reader)?;
+		Ok(Self::from_parts(
+			reader,
+			version,
+			trailer,
+			file_length,
+			key_stanzas,
+		))
+	}
+
+	/// Open an encrypted Zarc for reading.
+	///
+	/// This is [`open()`](Decoder::open), but also decrypts the Zarc's content key with the given
+	/// key, failing if the key doesn't open this Zarc. If the Zarc isn't encrypted, the key is
+	/// ignored.
+	pub fn open_with_key(reader: R, key: &EncryptionKey) -> Result<Self> {
+		let mut zarc = Self::open(reader)?;
+		zarc.unlock(key)?;
+		Ok(zarc)
+	}
+}
+
+impl<R> Decoder<R> {
+	/// Make a decoder out of what was read when opening a Zarc.
+	pub(crate) fn from_parts(
+		reader: R,
+		version: NonZeroU8,
+		trailer: Trailer,
+		file_length: u64,
+		key_stanzas: Vec<KeyStanza>,
+	) -> Self {
 		if version.get() != trailer.version {
 			warn!(header=%version, trailer=%trailer.version, "zarc version mismatch in header and trailer");
 		}
 
-		Ok(Self {
+		Self {
 			reader,
 			file_length,
 			trailer,
+			key_stanzas,
+			cipher: None,
 			editions: Default::default(),
 			files: Default::default(),
 			frames: Default::default(),
 			files_by_name: Default::default(),
 			files_by_digest: Default::default(),
-		})
+			externals: Default::default(),
+			external_frames: Default::default(),
+			signatures: Default::default(),
+			dictionaries: Default::default(),
+			bases: Default::default(),
+		}
 	}
+
+	/// Decrypt the content key with the given key.
+	///
+	/// Does nothing if the Zarc isn't encrypted.
+	pub(crate) fn unlock(&mut self, key: &EncryptionKey) -> Result<()> {
+		if !self.is_encrypted() {
+			warn!("zarc is not encrypted, ignoring key");
+			return Ok(());
+		}
+
+		self.cipher = self
+			.key_stanzas
+			.iter()
+			.find_map(|stanza| ContentCipher::unwrap_from(stanza, key));
+		if self.cipher.is_none() {
+			return Err(SimpleError::new(ErrorKind::Decryption)
+				.with_message("the key doesn't open this zarc")
+				.into());
+		}
+
+		Ok(())
+	}
+}
+
+/// How much of the end of a Zarc is read to find the trailer.
+pub(crate) const TRAILER_SEARCH_LENGTH: u64 = 1024;
+
+/// Parse a Skippable frame, checking its nibble.
+pub(crate) fn parse_skippable_frame(
+	reader: &mut (impl Read + Seek),
+	nibble: u8,
+) -> Result<SkippableFrame> {
+	let (bits_read, frame) =
+		SkippableFrame::from_reader((reader, 0)).map_err(SimpleError::from_deku)?;
+	debug!(%bits_read, frame=format!("{frame:02x?}"), nibble=%format!("0x{:X}", frame.nibble()), "read skippable frame");
+
+	if frame.nibble() != nibble {
+		return Err(ErrorKind::InvalidNibble {
+			expected: nibble,
+			actual: frame.nibble(),
+		}
+		.into());
+	}
+
+	Ok(frame)
+}
+
+/// Parse the payload of the header frame, and check the version is supported.
+pub(crate) fn parse_header(data: Vec<u8>) -> Result<NonZeroU8> {
+	let mut content = Cursor::new(data);
+	let (bits_read, header) =
+		Header::from_reader((&mut content, 0)).map_err(SimpleError::from_deku)?;
+	debug!(%bits_read, header=format!("{header:02x?}"), "read zarc header");
+
+	debug_assert_ne!(crate::constants::ZARC_VERSION, 0);
+	debug_assert_ne!(header.version, 0);
+	if header.version != crate::constants::ZARC_VERSION {
+		return Err(ErrorKind::UnsupportedZarcVersion(header.version).into());
+	}
+
+	Ok(unsafe {
+		// SAFETY: the version is valid and zarc versions start at 1
+		NonZeroU8::new_unchecked(header.version)
+	})
+}
+
+/// Parse the payload of the Keys frame.
+pub(crate) fn parse_keys(data: &[u8]) -> Result<Vec<KeyStanza>> {
+	let stanzas: Vec<KeyStanza> = minicbor::decode(data)?;
+	debug!(stanzas=%stanzas.len(), "read zarc keys");
+
+	if stanzas.is_empty() {
+		return Err(SimpleError::new(ErrorKind::Parse)
+			.with_message("keys frame has no key stanzas")
+			.into());
+	}
+
+	Ok(stanzas)
+}
+
+/// Parse the trailer from the end of a Zarc.
+///
+/// The `ending` must be the last bytes of the Zarc, up to [`TRAILER_SEARCH_LENGTH`]. The directory
+/// offset is made positive.
+pub(crate) fn parse_trailer(ending: &[u8], file_length: u64) -> Result<Trailer> {
+	let bytes = ending.len();
+	if bytes < EPILOGUE_LENGTH {
+		return Err(SimpleError::new(ErrorKind::Parse)
+			.with_message("parse error: too short to have a trailer")
+			.into());
+	}
+
+	// read the epilogue out of the end of the ending
+	let ((rest, remaining_bits), epilogue) =
+		Epilogue::from_bytes((&ending[(bytes - EPILOGUE_LENGTH)..], 0))
+			.map_err(SimpleError::from_deku)?;
+	debug!(?epilogue, "read zarc trailer epilogue");
+
+	if remaining_bits > 0 {
+		trace!(%remaining_bits, ?rest, "some data remaining");
+		return Err(SimpleError::new(ErrorKind::Parse)
+			.with_message(format!(
+				"parse error: too much data ({remaining_bits} bits) {rest:02x?}"
+			))
+			.into());
+	}
+
+	// check we have enough data
+	let trailer_length = epilogue.full_length();
+	if bytes < trailer_length {
+		// the ending is only shorter than the search length when that's the whole file
+		return Err(SimpleError::new(ErrorKind::Parse)
+			.with_message(format!(
+				"parse error: too short for its trailer ({bytes} bytes, needs {trailer_length})"
+			))
+			.into());
+	}
+
+	// complete reading the trailer
+	// UNWRAP: we know we have enough data, we just checked
+	let mut trailer = epilogue.complete(ending).expect("not enough data");
+	debug!(bytes=%trailer.len(), trailer=format!("{trailer:02x?}"), "read zarc trailer");
+
+	// compare the check byte
+	let check_byte = trailer.compute_check();
+	if check_byte != epilogue.check {
+		return Err(SimpleError::new(ErrorKind::Parse)
+			.with_message(format!(
+			"parse error: trailer check byte doesn't match (expected 0x{:02X}, got 0x{check_byte:02X})",
+			epilogue.check
+		))
+			.into());
+	}
+
+	trailer.make_offset_positive(file_length);
+	debug!(offset=%trailer.directory_offset, "reified directory offset");
+
+	Ok(trailer)
+}
+
+#[test]
+fn short_trailer_is_an_error() {
+	use crate::test_util::{error_kind, zarc_bytes};
+
+	let bytes = zarc_bytes(&[("file.txt", b"content")]);
+	let (_, epilogue) = Epilogue::from_bytes((&bytes[(bytes.len() - EPILOGUE_LENGTH)..], 0))
+		.expect("parse epilogue");
+
+	// a source which holds the epilogue but not the whole trailer
+	let ending = &bytes[(bytes.len() - epilogue.full_length() + 1)..];
+	let err = parse_trailer(ending, ending.len() as _).expect_err("trailer is cut short");
+	assert_eq!(error_kind(&err), Some(ErrorKind::Parse));
+
+	let ending = &bytes[(bytes.len() - epilogue.full_length())..];
+	parse_trailer(ending, ending.len() as _).expect("whole trailer");
 }
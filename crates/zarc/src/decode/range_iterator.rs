@@ -0,0 +1,180 @@
+use std::{
+	io::{Read, Seek},
+	ops::Range,
+};
+
+use tracing::{debug, instrument};
+
+use crate::{
+	integrity::{Digest, Outboard, OUTBOARD_GROUP_SIZE},
+	ondemand::OnDemand,
+};
+
+use super::{
+	error::{ErrorKind, Result, SimpleError},
+	Decoder, FrameIterator,
+};
+
+impl<R: OnDemand> Decoder<R> {
+	/// Read a range of a content frame by digest, verifying it as it goes.
+	///
+	/// This returns an iterator of chunks of bytes within the `range` (which is clamped to the
+	/// frame's uncompressed size). Unlike [`read_content_frame()`](Decoder::read_content_frame),
+	/// every chunk is checked before it's returned, so the consumer never sees unverified bytes.
+	///
+	/// If the frame has an [outboard](crate::integrity::Outboard), each 16 KiB group of content is
+	/// checked on its own as it's decompressed. Otherwise, the entire frame has to be decompressed
+	/// and checked before the first chunk is returned, which takes memory.
+	///
+	/// The frame still has to be decompressed from its start up to the end of the range.
+	#[instrument(level = "debug", skip(self))]
+	pub fn read_range(
+		&self,
+		digest: &Digest,
+		range: Range<u64>,
+	) -> Result<Option<RangeIterator<'_, R::Reader>>> {
+		let Some(entry) = self.frames.get(digest) else {
+			let Some(external) = self.external_frames.get(digest) else {
+				return Ok(None);
+			};
+
+			return self.base(&external.external)?.read_range(digest, range);
+		};
+
+		let outboard = match &entry.outboard {
+			None => None,
+			Some(outboard) => {
+				let Some(mut frame) = self.read_content_frame(outboard)? else {
+					return Err(ErrorKind::DirectoryIntegrity("outboard frame is missing").into());
+				};
+
+				let mut bytes = Vec::new();
+				for chunk in &mut frame {
+					bytes.extend(chunk?);
+				}
+
+				if frame.verify() != Some(true) {
+					return Err(SimpleError::new(ErrorKind::ContentIntegrity)
+						.with_message("outboard frame doesn't match its digest")
+						.into());
+				}
+
+				let outboard = Outboard::verified(&bytes, entry.uncompressed, digest).ok_or(
+					ErrorKind::DirectoryIntegrity("outboard isn't for this frame"),
+				)?;
+				debug!("loaded outboard");
+				Some(outboard)
+			}
+		};
+
+		let Some(frame) = self.read_content_frame(digest)? else {
+			return Ok(None);
+		};
+
+		let end = range.end.min(entry.uncompressed);
+		Ok(Some(RangeIterator {
+			frame,
+			outboard,
+			range: range.start.min(end)..end,
+			buffer: Vec::new(),
+			position: 0,
+			group: 0,
+			exhausted: false,
+			done: false,
+		}))
+	}
+}
+
+/// Iterator over a verified range of a Zarc content frame.
+///
+/// This is returned by [`Decoder::read_range()`][super::Decoder::read_range].
+///
+/// Each call to the iterator decompresses some data, checks it, and returns the part of it which
+/// is within the range, until the range is exhausted. If the content doesn't match, it returns an
+/// error with [`ErrorKind::ContentIntegrity`] and stops.
+#[derive(Debug)]
+pub struct RangeIterator<'zstd, R> {
+	frame: FrameIterator<'zstd, R>,
+	outboard: Option<Outboard>,
+	range: Range<u64>,
+	buffer: Vec<u8>,
+	position: u64,
+	group: usize,
+	exhausted: bool,
+	done: bool,
+}
+
+impl<'zstd, R> RangeIterator<'zstd, R> {
+	/// Return the range of the frame this iterates over.
+	pub fn range(&self) -> Range<u64> {
+		self.range.clone()
+	}
+
+	/// Whether the content is checked group by group, with an outboard.
+	pub fn has_outboard(&self) -> bool {
+		self.outboard.is_some()
+	}
+
+	fn fail(&mut self, message: &'static str) -> Option<Result<Vec<u8>>> {
+		self.done = true;
+		Some(Err(SimpleError::new(ErrorKind::ContentIntegrity)
+			.with_message(message)
+			.into()))
+	}
+}
+
+impl<'zstd, R: Read + Seek> Iterator for RangeIterator<'zstd, R> {
+	type Item = Result<Vec<u8>>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let group_size = if self.outboard.is_some() {
+			OUTBOARD_GROUP_SIZE
+		} else {
+			usize::MAX
+		};
+
+		while !self.done && self.position < self.range.end {
+			while !self.exhausted && self.buffer.len() < group_size {
+				match self.frame.next() {
+					Some(Ok(data)) => self.buffer.extend(data),
+					Some(Err(err)) => {
+						self.done = true;
+						return Some(Err(err));
+					}
+					None => self.exhausted = true,
+				}
+			}
+
+			if self.buffer.is_empty() {
+				return self.fail("frame is shorter than its recorded size");
+			}
+
+			let length = self.buffer.len().min(group_size);
+			let start = self.position;
+			let end = start + length as u64;
+			let mut group: Vec<u8> = self.buffer.drain(..length).collect();
+			let index = self.group;
+			self.position = end;
+			self.group += 1;
+
+			// groups before the range need to be decompressed, but not checked
+			if end <= self.range.start {
+				continue;
+			}
+
+			let valid = match &self.outboard {
+				Some(outboard) => outboard.check_group(index, &group),
+				None => self.frame.verify() == Some(true),
+			};
+			if !valid {
+				return self.fail("frame doesn't match its digest");
+			}
+
+			group.truncate((self.range.end - start).min(length as u64) as usize);
+			group.drain(..(self.range.start.saturating_sub(start) as usize));
+			return Some(Ok(group));
+		}
+
+		None
+	}
+}
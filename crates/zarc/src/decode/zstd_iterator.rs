@@ -1,15 +1,22 @@
+#[cfg(feature = "tokio")]
+use std::io::Cursor;
 use std::{
 	fmt,
-	io::{Read, Seek, SeekFrom},
+	io::{self, Read, Seek},
 };
 
+use ozarc::framing::{SKIPPABLE_FRAME_MAGIC, SKIPPABLE_FRAME_OVERHEAD};
 use tracing::{debug, instrument, trace};
 use zstd_safe::{DCtx, InBuffer, OutBuffer};
 
-use crate::ondemand::OnDemand;
+use crate::{
+	encryption::{DecryptionFailed, Decryptor, CONTENT_AAD, DIRECTORY_AAD, ENCRYPTED_FRAME_NIBBLE},
+	integrity::Digest,
+	ondemand::OnDemand,
+};
 
 use super::{
-	error::{self, ErrorKind, Result},
+	error::{self, Error, ErrorKind, Result, SimpleError},
 	Decoder,
 };
 
@@ -18,21 +25,163 @@ impl<R: OnDemand> Decoder<R> {
 	///
 	/// This opens a new reader, seeks to the position given, and returns an iterator of chunks of
 	/// bytes. Each call to the iterator decompresses some data and returns it, until the frame is
-	/// exhausted.
+	/// exhausted. No more than `length` bytes are read from the Zarc, which is the framed size of
+	/// the frame.
+	///
+	/// If the frame was compressed with a dictionary, give its digest; the dictionary must have
+	/// been loaded from the directory.
 	#[cfg_attr(feature = "expose-internals", visibility::make(pub))]
 	#[instrument(level = "debug", skip(self))]
 	pub(crate) fn read_zstandard_frame(
 		&self,
 		offset: u64,
+		length: u64,
+		dictionary: Option<&Digest>,
 	) -> Result<ZstdFrameIterator<'_, R::Reader>> {
-		let mut reader = self.reader.open()?;
-		let zstd = DCtx::try_create().ok_or(ErrorKind::ZstdInit)?;
-		// TODO method to create zstd context with the parameters saved against Decoder
+		self.read_zstandard_frame_with(offset, length, self.create_dctx(dictionary)?)
+	}
+
+	/// Read a Zstandard frame with a given decompression context.
+	///
+	/// If the source is [in memory](OnDemand::as_slice) and the Zarc isn't encrypted, this
+	/// decompresses straight from memory.
+	pub(crate) fn read_zstandard_frame_with<'zstd>(
+		&'zstd self,
+		offset: u64,
+		length: u64,
+		zstd: DCtx<'zstd>,
+	) -> Result<ZstdFrameIterator<'zstd, R::Reader>> {
+		if let (Some(source), false) = (self.reader.as_slice(), self.is_encrypted()) {
+			let frame = usize::try_from(offset)
+				.ok()
+				.zip(usize::try_from(length).ok())
+				.and_then(|(start, length)| source.get(start..(start.checked_add(length)?)))
+				.ok_or_else(|| {
+					SimpleError::new(ErrorKind::Parse).with_message(format!(
+						"frame at offset {offset} of length {length} is beyond the end of the zarc"
+					))
+				})?;
+
+			debug!(%offset, %length, "decompress frame from memory");
+			return Ok(ZstdFrameIterator::new(
+				FrameSource::Slice(frame),
+				0,
+				zstd,
+				offset,
+			));
+		}
+
+		debug!(%offset, %length, "open reader at frame");
+		let reader = self.reader.open_range(offset..(offset + length))?;
 
-		debug!(%offset, "seek to frame");
-		reader.seek(SeekFrom::Start(offset))?;
+		if !self.is_encrypted() {
+			return Ok(ZstdFrameIterator::new(
+				FrameSource::Reader(reader),
+				length,
+				zstd,
+				offset,
+			));
+		}
 
-		Ok(ZstdFrameIterator::new(reader, zstd, offset))
+		let decryptor = self.decrypt_frame(reader, offset)?;
+		let length = decryptor.plaintext_length();
+		Ok(ZstdFrameIterator::new(
+			FrameSource::Decrypting(decryptor),
+			length,
+			zstd,
+			offset,
+		))
+	}
+}
+
+impl<R> Decoder<R> {
+	/// Decrypt the encrypted frame at `offset`, which the reader is at the start of.
+	///
+	/// Returns a reader of the plaintext Zstandard frame, which is decrypted a segment at a time
+	/// as it's read.
+	pub(crate) fn decrypt_frame<F: Read>(
+		&self,
+		mut reader: F,
+		offset: u64,
+	) -> Result<Decryptor<F>> {
+		let Some(cipher) = &self.cipher else {
+			return Err(ErrorKind::Encrypted.into());
+		};
+
+		let mut header = [0; SKIPPABLE_FRAME_OVERHEAD];
+		reader.read_exact(&mut header)?;
+		let (magic, size) = header.split_at(4);
+		if magic[0] & 0xF0 != 0x50 || magic[1..] != *SKIPPABLE_FRAME_MAGIC {
+			return Err(SimpleError::new(ErrorKind::Parse)
+				.with_message(format!(
+					"frame at offset {offset} is not an encrypted frame"
+				))
+				.into());
+		}
+		if magic[0] & 0x0F != ENCRYPTED_FRAME_NIBBLE {
+			return Err(ErrorKind::InvalidNibble {
+				expected: ENCRYPTED_FRAME_NIBBLE,
+				actual: magic[0] & 0x0F,
+			}
+			.into());
+		}
+
+		let mut size_bytes = [0; 4];
+		size_bytes.copy_from_slice(size);
+		let size = u32::from_le_bytes(size_bytes) as u64;
+
+		let aad = if offset == self.trailer.directory_offset as u64 {
+			DIRECTORY_AAD
+		} else {
+			CONTENT_AAD
+		};
+		let decryptor = cipher
+			.decryptor(aad, reader, size)
+			.map_err(|err| decryption_error(err, offset))?;
+		trace!(sealed=%size, plaintext=%decryptor.plaintext_length(), "decrypting frame");
+		Ok(decryptor)
+	}
+
+	/// Decompress a frame which was read into memory, decrypting it first if needed.
+	#[cfg(feature = "tokio")]
+	pub(crate) fn read_zstandard_frame_from<'zstd>(
+		&self,
+		frame: Vec<u8>,
+		offset: u64,
+		zstd: DCtx<'zstd>,
+	) -> Result<ZstdFrameIterator<'zstd, Cursor<Vec<u8>>>> {
+		if self.is_encrypted() {
+			let decryptor = self.decrypt_frame(Cursor::new(frame), offset)?;
+			let length = decryptor.plaintext_length();
+			return Ok(ZstdFrameIterator::new(
+				FrameSource::Decrypting(decryptor),
+				length,
+				zstd,
+				offset,
+			));
+		}
+
+		let length = frame.len() as u64;
+		Ok(ZstdFrameIterator::new(
+			FrameSource::Owned(Cursor::new(frame)),
+			length,
+			zstd,
+			offset,
+		))
+	}
+}
+
+/// Convert an error reading an encrypted frame, keeping decryption failures distinct.
+fn decryption_error(err: io::Error, offset: u64) -> Error {
+	if err
+		.get_ref()
+		.is_some_and(|inner| inner.is::<DecryptionFailed>())
+	{
+		SimpleError::new(ErrorKind::Decryption)
+			.with_message(format!("decryption failed for frame at offset {offset}"))
+			.into()
+	} else {
+		err.into()
 	}
 }
 
@@ -41,12 +190,28 @@ impl<R: OnDemand> Decoder<R> {
 /// This is returned by [`Decoder::read_zstandard_frame()`][super::Decoder::read_zstandard_frame].
 ///
 /// Each call to the iterator decompresses some data and returns it, until the frame is exhausted.
-/// It also computes the frame's digest as it goes, so you can check it against the one you used to
-/// request the frame.
+/// For decompressing without allocating, use [`read_into()`](ZstdFrameIterator::read_into)
+/// instead, with the same buffer every time.
+///
+/// Compressed data is read from the Zarc into a buffer which is kept for the life of the iterator,
+/// and no more than the frame's length is ever read.
 pub struct ZstdFrameIterator<'zstd, R> {
-	reader: R,
+	reader: FrameSource<'zstd, R>,
 	zstd: DCtx<'zstd>,
 	start_offset: u64,
+
+	// compressed bytes of the frame not yet read from the reader
+	unread: u64,
+
+	// compressed bytes read but not yet given to zstd are input[input_pos..input_len], or
+	// source[input_pos..input_len] when decompressing from memory
+	input: Vec<u8>,
+	input_pos: usize,
+	input_len: usize,
+
+	// decompressed by the iterator, before being copied out
+	output: Vec<u8>,
+
 	done: bool,
 }
 
@@ -56,11 +221,42 @@ impl<R: fmt::Debug> fmt::Debug for ZstdFrameIterator<'_, R> {
 			.field("reader", &self.reader)
 			.field("zstd", &"zstd-safe decompression context")
 			.field("start_offset", &self.start_offset)
+			.field("unread", &self.unread)
+			.field("input_buffered", &(self.input_len - self.input_pos))
 			.field("done", &self.done)
 			.finish()
 	}
 }
 
+/// Where a frame's compressed bytes are read from.
+#[derive(Debug)]
+pub(crate) enum FrameSource<'data, R> {
+	/// Straight from the Zarc.
+	Reader(R),
+
+	/// From a frame read into memory.
+	#[cfg(feature = "tokio")]
+	Owned(Cursor<Vec<u8>>),
+
+	/// From an encrypted frame, decrypting it as it's read.
+	Decrypting(Decryptor<R>),
+
+	/// The entire frame, from a Zarc in memory.
+	Slice(&'data [u8]),
+}
+
+impl<R: Read> Read for FrameSource<'_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		match self {
+			Self::Reader(reader) => reader.read(buf),
+			#[cfg(feature = "tokio")]
+			Self::Owned(cursor) => cursor.read(buf),
+			Self::Decrypting(decryptor) => decryptor.read(buf),
+			Self::Slice(slice) => slice.read(buf),
+		}
+	}
+}
+
 impl<'zstd, R> ZstdFrameIterator<'zstd, R> {
 	/// Return `true` if the iterator is done, without advancing it.
 	pub fn is_done(&self) -> bool {
@@ -69,61 +265,58 @@ impl<'zstd, R> ZstdFrameIterator<'zstd, R> {
 }
 
 impl<'zstd, R: Read + Seek> ZstdFrameIterator<'zstd, R> {
-	pub(crate) fn new(reader: R, zstd: DCtx<'zstd>, start_offset: u64) -> Self {
+	pub(crate) fn new(
+		reader: FrameSource<'zstd, R>,
+		length: u64,
+		zstd: DCtx<'zstd>,
+		start_offset: u64,
+	) -> Self {
+		// frames in memory don't need to be read into the input buffer
+		let input_len = match reader {
+			FrameSource::Slice(slice) => slice.len(),
+			_ => 0,
+		};
+
 		Self {
 			reader,
 			zstd,
 			start_offset,
+			unread: length,
+			input: Vec::new(),
+			input_pos: 0,
+			input_len,
+			output: Vec::new(),
 			done: false,
 		}
 	}
 
-	/// Perform one step of a stream decompression.
+	/// Decompress some data into a buffer.
 	///
-	/// This cursor is left at wherever the decompression stopped, which may be in the middle of a
-	/// block or frame; the next call to this method will continue from there.
+	/// This continues from wherever the previous call stopped, which may be in the middle of a
+	/// block, reading more of the frame as needed. The buffer should be non-empty; a buffer of
+	/// [`DCtx::out_size()`] bytes is most efficient.
 	///
-	/// Returns the data that was decompressed and a boolean to indicate if the frame is done.
-	#[instrument(level = "trace", skip(self))]
-	fn decompress_step(&mut self) -> Result<(Vec<u8>, bool)> {
-		let input_size = DCtx::in_size().max(1024);
-		let mut input_buf = vec![0; input_size];
-		let bytes = self.reader.read(&mut input_buf)?;
-		trace!(desired=%input_size, obtained=%bytes, "read from reader to give to zstd");
-		let mut input = InBuffer {
-			src: &input_buf[..bytes],
-			pos: 0,
-		};
+	/// Returns how many bytes were written to the start of the buffer, or zero once the frame is
+	/// done.
+	#[instrument(level = "trace", skip(self, buf))]
+	pub fn read_into(&mut self, buf: &mut [u8]) -> Result<usize> {
+		while !self.done && !buf.is_empty() {
+			// when the frame has been entirely read, zstd may still have output to flush
+			if self.input_pos == self.input_len && self.unread > 0 {
+				self.fill_input()?;
+			}
+
+			let src = match &self.reader {
+				FrameSource::Slice(slice) => slice,
+				_ => &self.input[..],
+			};
+			let mut input = InBuffer {
+				src: &src[self.input_pos..self.input_len],
+				pos: 0,
+			};
+			let mut output = OutBuffer::around(&mut *buf);
 
-		let output_size = DCtx::out_size().max(1024);
-		let mut output_buf: Vec<u8> = Vec::with_capacity(output_size);
-		trace!(bytes=%output_size, "allocated zstd output buffer");
-		let mut output = OutBuffer::around(&mut output_buf);
-
-		trace!("decompressing");
-		let mut input_hint = self
-			.zstd
-			.decompress_stream(&mut output, &mut input)
-			.map_err(error::zstd)?;
-		trace!(
-			%input_hint,
-			frame_done=%input_hint == 0,
-			input_pos=%input.pos,
-			input_size=%input.src.len(),
-			output_pos=%output.pos(),
-			output_size=%output.capacity(),
-			"decompressed"
-		);
-
-		while output.pos() == output.capacity() {
-			trace!("zstd wants more output space");
-			let new_output_size = DCtx::out_size().max(1024);
-			output_buf.reserve(output_size + new_output_size);
-			trace!(total=%output_buf.capacity(), "allocated larger zstd output buffer");
-			output = OutBuffer::around(&mut output_buf);
-
-			trace!("decompressing again without changing input");
-			input_hint = self
+			let input_hint = self
 				.zstd
 				.decompress_stream(&mut output, &mut input)
 				.map_err(error::zstd)?;
@@ -136,20 +329,61 @@ impl<'zstd, R: Read + Seek> ZstdFrameIterator<'zstd, R> {
 				output_size=%output.capacity(),
 				"decompressed"
 			);
+
+			self.input_pos += input.pos;
+			if input_hint == 0 {
+				self.done = true;
+			}
+
+			let written = output.pos();
+			if written > 0 {
+				return Ok(written);
+			}
+
+			if !self.done && self.input_pos == self.input_len && self.unread == 0 {
+				return Err(self.truncated());
+			}
 		}
 
-		let output_written = output.as_slice().len();
-		trace!(bytes = output_written, "zstd has finished with the input");
+		Ok(0)
+	}
 
-		#[allow(clippy::drop_non_drop)]
-		drop(output); // to release the mutable borrow on output_buf
+	/// Read more of the frame into the input buffer.
+	///
+	/// The input buffer is allocated on first use, and never reads beyond the frame's length.
+	fn fill_input(&mut self) -> Result<()> {
+		if self.input.is_empty() {
+			let input_size = DCtx::in_size().max(1024);
+			trace!(bytes=%input_size, "allocate zstd input buffer");
+			self.input = vec![0; input_size];
+		}
+
+		let desired = usize::try_from(self.unread)
+			.unwrap_or(usize::MAX)
+			.min(self.input.len());
+		let bytes = self
+			.reader
+			.read(&mut self.input[..desired])
+			.map_err(|err| decryption_error(err, self.start_offset))?;
+		trace!(%desired, obtained=%bytes, "read from reader to give to zstd");
 
-		if output_written != output_buf.len() {
-			trace!("shrink output buffer to actual written size");
-			output_buf.truncate(output_written);
+		if bytes == 0 {
+			return Err(self.truncated());
 		}
 
-		Ok((output_buf, input_hint == 0))
+		self.unread -= bytes as u64;
+		self.input_pos = 0;
+		self.input_len = bytes;
+		Ok(())
+	}
+
+	fn truncated(&self) -> Error {
+		SimpleError::new(ErrorKind::Parse)
+			.with_message(format!(
+				"frame at offset {} ends before zstd is done with it",
+				self.start_offset
+			))
+			.into()
 	}
 }
 
@@ -161,15 +395,54 @@ impl<'zstd, R: Read + Seek> Iterator for ZstdFrameIterator<'zstd, R> {
 			return None;
 		}
 
-		let (data, done) = match self.decompress_step() {
-			Ok(ok) => ok,
-			Err(err) => return Some(Err(err)),
+		if self.output.is_empty() {
+			let output_size = DCtx::out_size().max(1024);
+			trace!(bytes=%output_size, "allocate zstd output buffer");
+			self.output = vec![0; output_size];
+		}
+
+		let mut output = std::mem::take(&mut self.output);
+		let result = match self.read_into(&mut output) {
+			Ok(0) => None,
+			Ok(bytes) => Some(Ok(output[..bytes].to_vec())),
+			Err(err) => {
+				self.done = true;
+				Some(Err(err))
+			}
 		};
+		self.output = output;
+		result
+	}
+}
 
-		if done {
-			self.done = true;
-		}
+#[test]
+fn read_into_matches_iterator() {
+	use crate::test_util::{varied, zarc};
+
+	let content = varied(600_000);
+	let zarc = zarc(&[("file.bin", &content)]);
+	let digest = zarc.frames().next().expect("frame").digest.clone();
+	let framer = || {
+		zarc.read_content_frame(&digest)
+			.expect("read frame")
+			.expect("frame")
+			.into_framer()
+	};
 
-		Some(Ok(data))
+	let chunks = framer().collect::<Result<Vec<_>>>().expect("decompress");
+	assert!(chunks.len() > 1);
+	assert_eq!(chunks.concat(), content);
+
+	for size in [1000, DCtx::out_size()] {
+		let mut framer = framer();
+		let mut buf = vec![0; size];
+		let mut read = Vec::new();
+		loop {
+			match framer.read_into(&mut buf).expect("decompress") {
+				0 => break,
+				n => read.extend_from_slice(&buf[..n]),
+			}
+		}
+		assert_eq!(read, content, "buffer of {size} bytes");
 	}
 }
@@ -5,12 +5,16 @@ pub use self::edition::*;
 #[doc(inline)]
 pub use self::elements::*;
 #[doc(inline)]
+pub use self::external::*;
+#[doc(inline)]
 pub use self::file::*;
 #[doc(inline)]
 pub use self::frame::*;
 #[doc(inline)]
 pub use self::posix_owner::*;
 #[doc(inline)]
+pub use self::signature::*;
+#[doc(inline)]
 pub use self::specials::*;
 #[doc(inline)]
 pub use self::strings::*;
@@ -19,9 +23,11 @@ pub use self::timestamps::*;
 
 mod edition;
 mod elements;
+mod external;
 mod file;
 mod frame;
 mod posix_owner;
+mod signature;
 mod specials;
 mod strings;
 mod timestamps;
@@ -1,9 +1,9 @@
-use std::{collections::HashMap, num::NonZeroU16};
+use std::{collections::BTreeMap, num::NonZeroU16};
 
 use minicbor::{Decode, Encode};
 
 use super::{strings::AttributeValue, timestamps::Timestamp};
-use crate::integrity::DigestType;
+use crate::integrity::{Digest, DigestType};
 
 /// Metadata about a (previous) version of the Zarc Directory
 ///
@@ -25,10 +25,18 @@ pub struct Edition {
 	#[n(2)]
 	pub digest_type: DigestType,
 
+	/// Directory digest of the previous edition.
+	///
+	/// This chains editions together, so that history can't be rewritten when appending without
+	/// it showing: see [`Decoder::verify_edition_chain()`](crate::decode::Decoder::verify_edition_chain).
+	/// It's absent for the first edition, and for editions merged from other Zarcs.
+	#[n(3)]
+	pub previous_digest: Option<Digest>,
+
 	/// User Metadata of that version.
 	///
-	/// You can write a Some(empty HashMap), but you'll save two bytes if you write a None instead.
+	/// You can write a Some(empty map), but you'll save two bytes if you write a None instead.
 	/// This is pretty cheap here, but adds up for the similar fields in [`files`](FilemapEntry).
 	#[n(10)]
-	pub user_metadata: Option<HashMap<String, AttributeValue>>,
+	pub user_metadata: Option<BTreeMap<String, AttributeValue>>,
 }
@@ -2,7 +2,13 @@ use std::num::TryFromIntError;
 
 use deku::prelude::*;
 
-use super::{edition::Edition, file::File, frame::Frame};
+use super::{
+	edition::Edition,
+	external::{External, ExternalFrame},
+	file::File,
+	frame::Frame,
+	signature::Signature,
+};
 
 /// Zarc Directory Element framing
 ///
@@ -47,6 +53,15 @@ impl ElementFrame {
 			}
 			ElementKind::File => minicbor::decode(&self.payload).map(|e| Some(Element::File(e))),
 			ElementKind::Frame => minicbor::decode(&self.payload).map(|e| Some(Element::Frame(e))),
+			ElementKind::External => {
+				minicbor::decode(&self.payload).map(|e| Some(Element::External(e)))
+			}
+			ElementKind::ExternalFrame => {
+				minicbor::decode(&self.payload).map(|e| Some(Element::ExternalFrame(e)))
+			}
+			ElementKind::Signature => {
+				minicbor::decode(&self.payload).map(|e| Some(Element::Signature(e)))
+			}
 			ElementKind::Unknown(_) => Ok(None),
 		}
 	}
@@ -68,6 +83,18 @@ pub enum ElementKind {
 	#[deku(id = "3")]
 	Frame,
 
+	/// [External]
+	#[deku(id = "4")]
+	External,
+
+	/// [ExternalFrame]
+	#[deku(id = "5")]
+	ExternalFrame,
+
+	/// [Signature]
+	#[deku(id = "6")]
+	Signature,
+
 	/// Unknown element kind.
 	#[deku(id_pat = "_")]
 	Unknown(u8),
@@ -82,6 +109,12 @@ pub enum Element {
 	File(Box<File>),
 	/// [Frame]
 	Frame(Box<Frame>),
+	/// [External]
+	External(Box<External>),
+	/// [ExternalFrame]
+	ExternalFrame(Box<ExternalFrame>),
+	/// [Signature]
+	Signature(Box<Signature>),
 }
 
 impl Element {
@@ -91,6 +124,9 @@ impl Element {
 			Element::Edition(_) => ElementKind::Edition,
 			Element::File(_) => ElementKind::File,
 			Element::Frame(_) => ElementKind::Frame,
+			Element::External(_) => ElementKind::External,
+			Element::ExternalFrame(_) => ElementKind::ExternalFrame,
+			Element::Signature(_) => ElementKind::Signature,
 		}
 	}
 
@@ -101,6 +137,9 @@ impl Element {
 			Element::Edition(edition) => minicbor::to_vec(edition),
 			Element::File(file) => minicbor::to_vec(file),
 			Element::Frame(frame) => minicbor::to_vec(frame),
+			Element::External(external) => minicbor::to_vec(external),
+			Element::ExternalFrame(frame) => minicbor::to_vec(frame),
+			Element::Signature(signature) => minicbor::to_vec(signature),
 		}
 		.unwrap()
 	}
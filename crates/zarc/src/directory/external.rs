@@ -0,0 +1,54 @@
+use std::num::NonZeroU16;
+
+use minicbor::{Decode, Encode};
+
+use super::strings::CborString;
+use crate::integrity::Digest;
+
+/// Zarc Directory External Zarc Entry
+///
+/// A reference to another Zarc which holds some of the content of this one.
+///
+/// [Spec](https://github.com/passcod/zarc/blob/main/SPEC.md#kind-4-externals)
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+#[cbor(map)]
+pub struct External {
+	/// Edition which added this reference.
+	#[n(0)]
+	pub edition: NonZeroU16,
+
+	/// Directory digest of the other Zarc.
+	///
+	/// This is the digest in its trailer.
+	#[n(1)]
+	pub digest: Digest,
+
+	/// Name of the other Zarc, as a hint for finding it.
+	#[n(2)]
+	pub name: Option<CborString>,
+}
+
+/// Zarc Directory External Frame Entry
+///
+/// A frame of content that's stored in an [external Zarc](External).
+///
+/// [Spec](https://github.com/passcod/zarc/blob/main/SPEC.md#kind-5-external-frames)
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+#[cbor(map)]
+pub struct ExternalFrame {
+	/// Edition which added this frame.
+	#[n(0)]
+	pub edition: NonZeroU16,
+
+	/// Directory digest of the Zarc which contains the frame.
+	#[n(1)]
+	pub external: Digest,
+
+	/// Hash of the frame.
+	#[n(2)]
+	pub digest: Digest,
+
+	/// Uncompressed content size in bytes.
+	#[n(4)]
+	pub uncompressed: u64,
+}
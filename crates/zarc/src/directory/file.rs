@@ -1,11 +1,16 @@
-use std::{collections::HashMap, num::NonZeroU16};
+use std::{
+	collections::BTreeMap,
+	num::NonZeroU16,
+	path::{Component, Path},
+};
 
 use minicbor::{Decode, Encode};
 
 use super::{
+	frame::SolidSlice,
 	posix_owner::PosixOwner,
-	specials::SpecialFile,
-	strings::{AttributeValue, Pathname},
+	specials::{LinkTarget, SpecialFile},
+	strings::{AttributeValue, CborString, Pathname},
 	timestamps::Timestamps,
 };
 use crate::integrity::Digest;
@@ -25,6 +30,9 @@ pub struct File {
 	pub name: Pathname,
 
 	/// Hash of a frame of content.
+	///
+	/// If the content is [chunked](File::chunks) or in a [solid frame](File::solid), this is the
+	/// hash of the whole content instead.
 	#[n(2)]
 	pub digest: Option<Digest>,
 
@@ -48,17 +56,27 @@ pub struct File {
 	#[n(7)]
 	pub special: Option<SpecialFile>,
 
+	/// Hashes of the frames of content, in order.
+	///
+	/// When present, the content is the concatenation of these frames.
+	#[n(8)]
+	pub chunks: Option<Vec<Digest>>,
+
+	/// Where the content is within a solid frame.
+	#[n(9)]
+	pub solid: Option<SolidSlice>,
+
 	/// User metadata.
 	#[n(10)]
-	pub user_metadata: Option<HashMap<String, AttributeValue>>,
+	pub user_metadata: Option<BTreeMap<String, AttributeValue>>,
 
 	/// File attributes.
 	#[n(11)]
-	pub attributes: Option<HashMap<String, AttributeValue>>,
+	pub attributes: Option<BTreeMap<String, AttributeValue>>,
 
 	/// Extended attributes.
 	#[n(12)]
-	pub extended_attributes: Option<HashMap<String, AttributeValue>>,
+	pub extended_attributes: Option<BTreeMap<String, AttributeValue>>,
 }
 
 impl File {
@@ -67,6 +85,28 @@ impl File {
 		self.digest.is_some() && self.special.is_none()
 	}
 
+	/// Returns `true` if the content is split in several frames.
+	pub fn is_chunked(&self) -> bool {
+		self.chunks.is_some()
+	}
+
+	/// Returns `true` if the content is part of a solid frame.
+	pub fn is_solid(&self) -> bool {
+		self.solid.is_some()
+	}
+
+	/// Iterate through the digests of the frames of content, in order.
+	///
+	/// That's the [chunks](File::chunks) if the content is chunked, the [solid frame](File::solid)
+	/// if it's in one, the [digest](File::digest) otherwise, or nothing if the file has no content.
+	pub fn frame_digests(&self) -> impl Iterator<Item = &Digest> {
+		match (&self.chunks, &self.solid) {
+			(Some(chunks), _) => chunks.iter().chain(None),
+			(None, Some(solid)) => [].iter().chain(Some(&solid.frame)),
+			(None, None) => [].iter().chain(self.digest.as_ref()),
+		}
+	}
+
 	/// Returns `true` if this is a directory.
 	///
 	/// See also [`SpecialFile::is_dir`].
@@ -96,4 +136,62 @@ impl File {
 			.as_ref()
 			.map_or(false, SpecialFile::is_hardlink)
 	}
+
+	/// Returns `true` if this is a tombstone.
+	///
+	/// See also [`SpecialFile::is_tombstone`].
+	pub fn is_tombstone(&self) -> bool {
+		self.special
+			.as_ref()
+			.map_or(false, SpecialFile::is_tombstone)
+	}
+
+	/// Resolve the target of a link to a pathname within the Zarc.
+	///
+	/// Symlink targets are relative to the parent of the link, hardlink targets to the root of the
+	/// Zarc. This is purely lexical: it doesn't check that the target exists.
+	///
+	/// Returns `None` if this isn't a link, or if the target is absolute or goes outside the Zarc.
+	pub fn link_target_pathname(&self) -> Option<Pathname> {
+		let special = self.special.as_ref()?;
+		let target = special.link_target.as_ref()?;
+
+		let mut components = if special.is_symlink() {
+			let mut parent = self.name.0.clone();
+			parent.pop();
+			parent
+		} else if special.is_hardlink() {
+			Vec::new()
+		} else {
+			return None;
+		};
+
+		match target {
+			LinkTarget::Components(target) => {
+				for component in target {
+					match component {
+						CborString::Text(text) if text == "." => {}
+						CborString::Text(text) if text == ".." => {
+							components.pop()?;
+						}
+						component => components.push(component.clone()),
+					}
+				}
+			}
+			LinkTarget::FullPath(target) => {
+				for component in Path::new(&target.to_os_string()).components() {
+					match component {
+						Component::Normal(component) => components.push(component.into()),
+						Component::CurDir => {}
+						Component::ParentDir => {
+							components.pop()?;
+						}
+						Component::RootDir | Component::Prefix(_) => return None,
+					}
+				}
+			}
+		}
+
+		Some(Pathname(components))
+	}
 }
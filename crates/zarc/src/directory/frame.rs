@@ -29,4 +29,38 @@ pub struct Frame {
 	/// Uncompressed content size in bytes.
 	#[n(4)]
 	pub uncompressed: u64,
+
+	/// Hash of the frame holding the dictionary this frame was compressed with.
+	#[n(5)]
+	pub dictionary: Option<Digest>,
+
+	/// Hash of the frame whose content this frame was compressed against, as a prefix.
+	#[n(6)]
+	pub reference: Option<Digest>,
+
+	/// Hash of the frame holding the BLAKE3 [outboard](crate::integrity::Outboard) of this frame's
+	/// content, for verifying parts of it.
+	#[n(7)]
+	pub outboard: Option<Digest>,
+}
+
+/// Slice of a solid frame.
+///
+/// A solid frame holds the content of several files, concatenated; each file references its part.
+///
+/// [Spec](https://github.com/passcod/zarc/blob/main/SPEC.md#key-9-solid-slice)
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Encode, Decode)]
+#[cbor(array)]
+pub struct SolidSlice {
+	/// Hash of the frame.
+	#[n(0)]
+	pub frame: Digest,
+
+	/// Offset of the content within the uncompressed frame, in bytes.
+	#[n(1)]
+	pub offset: u64,
+
+	/// Length of the content in bytes.
+	#[n(2)]
+	pub length: u64,
 }
@@ -0,0 +1,67 @@
+use std::num::NonZeroU16;
+
+use ed25519_dalek::{Signature as Ed25519Signature, VerifyingKey};
+use minicbor::{Decode, Encode};
+
+use crate::integrity::Digest;
+
+/// Zarc Directory Signature Entry
+///
+/// A signature over a directory digest, which authenticates the whole Zarc: the directory commits
+/// to every frame by digest. Signatures are not included in the directory digest themselves.
+///
+/// [Spec](https://github.com/passcod/zarc/blob/main/SPEC.md#kind-6-signatures)
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+#[cbor(map)]
+pub struct Signature {
+	/// Edition which added this signature.
+	#[n(0)]
+	pub edition: NonZeroU16,
+
+	/// Signature algorithm.
+	#[n(1)]
+	pub scheme: SignatureScheme,
+
+	/// Public key of the signer.
+	#[n(2)]
+	#[cbor(with = "minicbor::bytes")]
+	pub public_key: Vec<u8>,
+
+	/// Directory digest that was signed.
+	///
+	/// For signatures added in the latest edition, this is the digest in the trailer.
+	#[n(3)]
+	pub digest: Digest,
+
+	/// Signature over the `digest`.
+	#[n(4)]
+	#[cbor(with = "minicbor::bytes")]
+	pub signature: Vec<u8>,
+}
+
+impl Signature {
+	/// Check the signature, and return the signer's key if it's valid.
+	///
+	/// This only checks that the signature is valid for its `digest`; compare that to the
+	/// directory digest to check that it's a signature of this Zarc.
+	pub fn signer(&self) -> Option<VerifyingKey> {
+		match self.scheme {
+			SignatureScheme::Ed25519 => {
+				let key =
+					VerifyingKey::from_bytes(self.public_key.as_slice().try_into().ok()?).ok()?;
+				let signature = Ed25519Signature::from_slice(&self.signature).ok()?;
+				key.verify_strict(&self.digest, &signature).ok()?;
+				Some(key)
+			}
+		}
+	}
+}
+
+/// Available signature algorithms.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Encode, Decode)]
+#[cbor(index_only)]
+pub enum SignatureScheme {
+	/// Ed25519, as specified in RFC 8032, with strict verification.
+	#[n(1)]
+	Ed25519 = 1,
+}
@@ -49,6 +49,13 @@ impl SpecialFile {
 	pub fn is_hardlink(&self) -> bool {
 		self.kind.map_or(false, SpecialFileKind::is_hardlink)
 	}
+
+	/// Returns `true` if this is a tombstone.
+	///
+	/// See also [`SpecialFileKind::is_tombstone`].
+	pub fn is_tombstone(&self) -> bool {
+		self.kind.map_or(false, SpecialFileKind::is_tombstone)
+	}
 }
 
 /// Special File kinds.
@@ -98,6 +105,12 @@ pub enum SpecialFileKind {
 	/// External hardlink.
 	#[n(22)]
 	ExternalHardlink = 22,
+
+	/// Tombstone.
+	///
+	/// The file was deleted in this entry's edition.
+	#[n(30)]
+	Tombstone = 30,
 }
 
 impl SpecialFileKind {
@@ -135,6 +148,11 @@ impl SpecialFileKind {
 			Self::Hardlink | Self::InternalHardlink | Self::ExternalHardlink
 		)
 	}
+
+	/// Returns `true` if this is a tombstone.
+	pub fn is_tombstone(self) -> bool {
+		matches!(self, Self::Tombstone)
+	}
 }
 
 /// Target of link (for [`SpecialFile`])
@@ -191,8 +209,7 @@ impl<C> Encode<C> for LinkTarget {
 impl<'b, C> Decode<'b, C> for LinkTarget {
 	fn decode(d: &mut Decoder<'b>, ctx: &mut C) -> Result<Self, minicbor::decode::Error> {
 		match d.datatype()? {
-			Type::Array => todo!(),
-			Type::ArrayIndef => todo!(),
+			Type::Array | Type::ArrayIndef => Vec::decode(d, ctx).map(Self::Components),
 			_ => CborString::decode(d, ctx).map(Self::FullPath),
 		}
 	}
@@ -1,5 +1,5 @@
 use std::{
-	ffi::OsStr,
+	ffi::{OsStr, OsString},
 	path::{Component, Path, PathBuf},
 };
 
@@ -35,22 +35,7 @@ impl Pathname {
 	pub fn to_path(&self) -> PathBuf {
 		let mut path = PathBuf::new();
 		for comp in &self.0 {
-			match comp {
-				CborString::Text(text) => {
-					path.push(text);
-				}
-				CborString::Binary(bytes) => {
-					#[cfg(unix)]
-					{
-						use std::os::unix::ffi::OsStrExt;
-						path.push(OsStr::from_bytes(bytes));
-					}
-					#[cfg(not(unix))]
-					{
-						path.push(String::from_utf8_lossy(bytes).to_string());
-					}
-				}
-			}
+			path.push(comp.to_os_string());
 		}
 
 		path
@@ -75,6 +60,26 @@ impl CborString {
 			Err(err) => Self::Binary(err.into_bytes()),
 		}
 	}
+
+	/// Converts to a (platform-specific) OS string.
+	///
+	/// On non-unix, binary strings are converted lossily.
+	pub fn to_os_string(&self) -> OsString {
+		match self {
+			Self::Text(text) => text.into(),
+			Self::Binary(bytes) => {
+				#[cfg(unix)]
+				{
+					use std::os::unix::ffi::OsStrExt;
+					OsStr::from_bytes(bytes).to_owned()
+				}
+				#[cfg(not(unix))]
+				{
+					String::from_utf8_lossy(bytes).into_owned().into()
+				}
+			}
+		}
+	}
 }
 
 impl From<&OsStr> for CborString {
@@ -5,35 +5,82 @@ use std::{
 	fmt,
 	io::{Error, Result, Write},
 	num::NonZeroU16,
+	ops::{Deref, DerefMut},
 };
 
-use tracing::{instrument, trace};
+use tracing::{instrument, trace, warn};
 use zstd_safe::CCtx;
 pub use zstd_safe::{CParameter as ZstdParameter, Strategy as ZstdStrategy};
 
 use crate::{
-	directory::{File, Frame, Pathname},
+	decode::Decoder,
+	directory::{
+		CborString, Edition, External, ExternalFrame, File, Frame, Pathname, Signature, Timestamp,
+	},
+	encryption::{ContentCipher, EncryptionKey, KEYS_FRAME_NIBBLE},
 	header::FILE_MAGIC,
-	integrity::Digest,
+	integrity::{Digest, SigningKey},
 	map_zstd_error,
+	ondemand::OnDemand,
 };
 
+#[cfg(feature = "tokio")]
+#[doc(inline)]
+pub use self::async_encoder::AsyncEncoder;
+#[doc(inline)]
+pub use self::chunking::ChunkSizes;
+pub(crate) use self::delta::MAX_DELTA_DEPTH;
+#[doc(inline)]
+pub use self::dictionary::train_dictionary;
+pub(crate) use self::directory::DirectoryElements;
+#[doc(inline)]
+pub use self::merge::MergeConflict;
+#[doc(inline)]
+pub use self::policy::{detect_mime_type, AutoCompression, Compression, CompressionPolicy};
+use self::solid::SolidBlock;
+
 mod add_file;
+#[cfg(feature = "tokio")]
+mod async_encoder;
+mod chunking;
 mod content_frame;
+mod delta;
+mod dictionary;
 mod directory;
 mod lowlevel_frames;
+mod merge;
+mod outboard;
+mod policy;
+mod solid;
 
 /// Zarc encoder context.
 pub struct Encoder<'writer, W: Write> {
-	writer: &'writer mut W,
+	writer: Output<'writer, W>,
 	zstd: CCtx<'writer>,
+	parameters: Vec<ZstdParameter>,
 	edition: NonZeroU16,
+	editions: Vec<Edition>,
+	previous_digest: Option<Digest>,
+	written_at: Option<Timestamp>,
 	files: Vec<Option<File>>,
 	frames: HashMap<Digest, Frame>,
 	files_by_name: BTreeMap<Pathname, Vec<usize>>,
+	files_by_basename: HashMap<CborString, Vec<usize>>,
 	files_by_digest: HashMap<Digest, Vec<usize>>,
+	externals: Vec<External>,
+	external_frames: HashMap<Digest, ExternalFrame>,
+	signatures: Vec<Signature>,
+	signing_keys: Vec<SigningKey>,
+	cipher: Option<ContentCipher>,
 	offset: usize,
 	compress: bool,
+	policy: Option<Box<dyn CompressionPolicy>>,
+	policy_zstd: Option<(Vec<ZstdParameter>, CCtx<'writer>)>,
+	dictionary: Option<(Digest, Vec<u8>)>,
+	chunk_sizes: ChunkSizes,
+	solid: SolidBlock,
+	outboard_threshold: Option<usize>,
+	offset_from_start: bool,
 }
 
 impl<W: Write + fmt::Debug> fmt::Debug for Encoder<'_, W> {
@@ -41,56 +88,361 @@ impl<W: Write + fmt::Debug> fmt::Debug for Encoder<'_, W> {
 		f.debug_struct("Encoder")
 			.field("writer", &self.writer)
 			.field("zstd", &"zstd-safe compression context")
+			.field("parameters", &self.parameters)
 			.field("edition", &self.edition)
+			.field("editions", &self.editions)
+			.field("previous_digest", &self.previous_digest)
+			.field("written_at", &self.written_at)
 			.field("files", &self.files)
 			.field("frames", &self.frames)
 			.field("files_by_name", &self.files_by_name)
+			.field("files_by_basename", &self.files_by_basename)
 			.field("files_by_digest", &self.files_by_digest)
+			.field("externals", &self.externals)
+			.field("external_frames", &self.external_frames)
+			.field("signatures", &self.signatures)
+			.field(
+				"signing_keys",
+				&self
+					.signing_keys
+					.iter()
+					.map(SigningKey::verifying_key)
+					.collect::<Vec<_>>(),
+			)
+			.field("cipher", &self.cipher)
 			.field("offset", &self.offset)
 			.field("compress", &self.compress)
+			.field(
+				"policy",
+				&self.policy.as_ref().map(|_| "compression policy"),
+			)
+			.field(
+				"policy_zstd",
+				&self.policy_zstd.as_ref().map(|(parameters, _)| parameters),
+			)
+			.field(
+				"dictionary",
+				&self.dictionary.as_ref().map(|(digest, _)| digest),
+			)
+			.field("chunk_sizes", &self.chunk_sizes)
+			.field("solid", &self.solid)
+			.field("outboard_threshold", &self.outboard_threshold)
+			.field("offset_from_start", &self.offset_from_start)
 			.finish()
 	}
 }
 
+/// Where the encoder writes to.
+#[derive(Debug)]
+pub(crate) enum Output<'writer, W> {
+	/// A writer given by the user.
+	Borrowed(&'writer mut W),
+
+	/// A writer owned by the encoder, like the buffer of the [`AsyncEncoder`].
+	#[cfg(feature = "tokio")]
+	Owned(W),
+}
+
+impl<W> Deref for Output<'_, W> {
+	type Target = W;
+
+	fn deref(&self) -> &Self::Target {
+		match self {
+			Self::Borrowed(writer) => writer,
+			#[cfg(feature = "tokio")]
+			Self::Owned(writer) => writer,
+		}
+	}
+}
+
+impl<W> DerefMut for Output<'_, W> {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		match self {
+			Self::Borrowed(writer) => writer,
+			#[cfg(feature = "tokio")]
+			Self::Owned(writer) => writer,
+		}
+	}
+}
+
 impl<'writer, W: Write> Encoder<'writer, W> {
 	/// Create a new encoder and write the header.
 	#[instrument(level = "trace", skip(writer))]
 	pub fn new(writer: &'writer mut W) -> Result<Self> {
-		trace!("create zstd context");
-		let mut zstd =
-			CCtx::try_create().ok_or_else(|| Error::other("failed allocating zstd context"))?;
-		zstd.init(0).map_err(map_zstd_error)?;
+		Self::with_output(Output::Borrowed(writer))
+	}
+
+	pub(crate) fn with_output(mut writer: Output<'writer, W>) -> Result<Self> {
+		let zstd = Self::create_zstd()?;
 
 		trace!("write zarc magic");
-		let offset = writer.write(&FILE_MAGIC)?;
+		writer.write_all(&FILE_MAGIC)?;
+		let offset = FILE_MAGIC.len();
 
 		Ok(Self {
 			writer,
 			zstd,
+			parameters: Vec::new(),
 			edition: unsafe { NonZeroU16::new_unchecked(1) },
+			editions: Vec::new(),
+			previous_digest: None,
+			written_at: None,
 			files: Vec::new(),
 			frames: HashMap::new(),
 			files_by_name: BTreeMap::new(),
+			files_by_basename: HashMap::new(),
 			files_by_digest: HashMap::new(),
+			externals: Vec::new(),
+			external_frames: HashMap::new(),
+			signatures: Vec::new(),
+			signing_keys: Vec::new(),
+			cipher: None,
 			offset,
 			compress: true,
+			policy: None,
+			policy_zstd: None,
+			dictionary: None,
+			chunk_sizes: ChunkSizes::default(),
+			solid: SolidBlock::default(),
+			outboard_threshold: None,
+			offset_from_start: false,
 		})
 	}
 
+	/// Create a new encoder for an encrypted Zarc, and write the header and keys.
+	///
+	/// A random content key is generated, which encrypts all content frames and the directory. It's
+	/// stored wrapped with each of the `keys`, any one of which can then open the Zarc, see
+	/// [`Decoder::open_with_key()`].
+	#[instrument(level = "trace", skip(writer, keys))]
+	pub fn new_encrypted(writer: &'writer mut W, keys: &[EncryptionKey]) -> Result<Self> {
+		Self::encrypted_with_output(Output::Borrowed(writer), keys)
+	}
+
+	pub(crate) fn encrypted_with_output(
+		writer: Output<'writer, W>,
+		keys: &[EncryptionKey],
+	) -> Result<Self> {
+		if keys.is_empty() {
+			return Err(Error::other("an encrypted zarc needs at least one key"));
+		}
+
+		let mut encoder = Self::with_output(writer)?;
+		let cipher = ContentCipher::generate()?;
+
+		let stanzas = keys
+			.iter()
+			.map(|key| cipher.wrap(key))
+			.collect::<Result<Vec<_>>>()?;
+		trace!(stanzas=%stanzas.len(), "write zarc keys");
+		let stanzas = minicbor::to_vec(&stanzas).map_err(Error::other)?;
+		encoder.offset += encoder.write_skippable_frame(KEYS_FRAME_NIBBLE, stanzas)?;
+
+		encoder.cipher = Some(cipher);
+		Ok(encoder)
+	}
+
+	/// Create an encoder that adds a new edition to an existing Zarc.
+	///
+	/// The `existing` Zarc must have had its directory read. Its editions, file entries, and frames
+	/// are carried over, and new entries are added to the next edition. Content that's already in
+	/// the Zarc isn't stored again. If it's encrypted, new content is encrypted with the same key.
+	///
+	/// The new edition records the digest of the existing directory, so the
+	/// [chain of editions](Decoder::verify_edition_chain) can be checked. It doesn't if the existing
+	/// directory can't be reproduced from its entries, as when they have keys this version doesn't
+	/// know: the new edition is then [unlinked](crate::decode::EditionLink::Unlinked).
+	///
+	/// The `writer` must be positioned at the start of the existing directory, that is, at the
+	/// [directory offset](crate::trailer::Trailer::directory_offset) from the Zarc's header (which
+	/// isn't the start of the file for an [embedded](Decoder::open_embedded) Zarc), as the directory and trailer
+	/// get overwritten; if writing to a file, truncate it there. Until the encoder is finalised, the
+	/// Zarc has no directory and cannot be read.
+	#[instrument(level = "trace", skip(writer, existing))]
+	pub fn append<R: OnDemand>(writer: &'writer mut W, existing: &Decoder<R>) -> Result<Self> {
+		Self::append_with_output(Output::Borrowed(writer), existing)
+	}
+
+	pub(crate) fn append_with_output<R>(
+		writer: Output<'writer, W>,
+		existing: &Decoder<R>,
+	) -> Result<Self> {
+		let edition = match existing.latest_edition() {
+			Some(latest) => latest
+				.number
+				.checked_add(1)
+				.ok_or_else(|| Error::other("too many editions"))?,
+			None => NonZeroU16::MIN,
+		};
+		trace!(%edition, "next edition");
+
+		// editions are chained by recomputing previous directories from the current one, which
+		// only works if the existing directory encodes to the same bytes again
+		let reproduced = match existing.latest_edition() {
+			Some(latest) => {
+				existing
+					.edition_directory_digest(latest.number)
+					.map_err(Error::other)?
+					== existing.trailer().digest
+			}
+			None => true,
+		};
+		let previous_digest = if reproduced {
+			Some(existing.trailer().digest.clone())
+		} else {
+			warn!("existing directory can't be reproduced, not linking the new edition to it");
+			None
+		};
+
+		let zstd = Self::create_zstd()?;
+		let offset = usize::try_from(existing.trailer().directory_offset).map_err(Error::other)?;
+
+		let mut encoder = Self {
+			writer,
+			zstd,
+			parameters: Vec::new(),
+			edition,
+			editions: existing.editions().cloned().collect(),
+			previous_digest,
+			written_at: None,
+			files: Vec::new(),
+			frames: existing
+				.frames()
+				.map(|frame| (frame.digest.clone(), frame.clone()))
+				.collect(),
+			files_by_name: BTreeMap::new(),
+			files_by_basename: HashMap::new(),
+			files_by_digest: HashMap::new(),
+			externals: existing.externals().cloned().collect(),
+			external_frames: existing
+				.external_frames()
+				.map(|frame| (frame.digest.clone(), frame.clone()))
+				.collect(),
+			signatures: existing.signatures().cloned().collect(),
+			signing_keys: Vec::new(),
+			cipher: existing.cipher().cloned(),
+			offset,
+			compress: true,
+			policy: None,
+			policy_zstd: None,
+			dictionary: None,
+			chunk_sizes: ChunkSizes::default(),
+			solid: SolidBlock::default(),
+			outboard_threshold: None,
+			offset_from_start: false,
+		};
+
+		for file in existing.files() {
+			encoder.add_file_entry(file.clone())?;
+		}
+
+		Ok(encoder)
+	}
+
+	/// Use another Zarc as a base for this one, making this a thin Zarc.
+	///
+	/// The `base` must have had its directory read. Content which is already in one of its frames
+	/// isn't stored in this Zarc, but referenced by digest. Reading that content back requires the
+	/// base to be available to the decoder, see [`Decoder::add_base()`].
+	///
+	/// The `name` is stored as a hint for finding the base, typically its filename.
+	#[instrument(level = "debug", skip(self, base))]
+	pub fn add_base<R>(&mut self, base: &Decoder<R>, name: Option<CborString>) -> Result<()> {
+		let external = base.trailer().digest.clone();
+		if self.externals.iter().any(|e| e.digest == external) {
+			trace!("base already added, skipping");
+			return Ok(());
+		}
+
+		for frame in base.frames() {
+			self.external_frames
+				.entry(frame.digest.clone())
+				.or_insert_with(|| ExternalFrame {
+					edition: self.edition,
+					external: external.clone(),
+					digest: frame.digest.clone(),
+					uncompressed: frame.uncompressed,
+				});
+		}
+
+		self.externals.push(External {
+			edition: self.edition,
+			digest: external,
+			name,
+		});
+		Ok(())
+	}
+
+	fn create_zstd<'a>() -> Result<CCtx<'a>> {
+		trace!("create zstd context");
+		let mut zstd =
+			CCtx::try_create().ok_or_else(|| Error::other("failed allocating zstd context"))?;
+		zstd.init(0).map_err(map_zstd_error)?;
+		Ok(zstd)
+	}
+
+	/// Create a compression context with the same parameters as the encoder's.
+	///
+	/// This doesn't load the dictionary.
+	fn create_zstd_with_parameters<'a>(&self) -> Result<CCtx<'a>> {
+		let mut zstd = Self::create_zstd()?;
+		for parameter in &self.parameters {
+			zstd.set_parameter(*parameter).map_err(map_zstd_error)?;
+		}
+		Ok(zstd)
+	}
+
 	/// Set a zstd parameter.
 	///
-	/// This will apply to future data frames.
+	/// This will apply to future data frames. A [compression policy](Encoder::set_compression_policy)
+	/// can override it for some frames.
 	#[instrument(level = "trace", skip(self))]
 	pub fn set_zstd_parameter(&mut self, parameter: ZstdParameter) -> Result<()> {
-		self.zstd
-			.set_parameter(parameter)
-			.map_err(map_zstd_error)
-			.map(drop)
+		self.zstd.set_parameter(parameter).map_err(map_zstd_error)?;
+		self.parameters.push(parameter);
+		self.policy_zstd = None;
+		Ok(())
+	}
+
+	/// Sign the Zarc with a key.
+	///
+	/// When the encoder is finalised, the directory digest is signed with this key, and the
+	/// signature stored in the directory. As the directory commits to the digest of every frame,
+	/// this authenticates all of the Zarc's content. Several keys can sign the same Zarc.
+	///
+	/// Signatures from previous editions are kept when appending, but they're over the directory
+	/// digest as of their edition.
+	#[instrument(level = "trace", skip(self, key), fields(key = ?key.verifying_key()))]
+	pub fn sign_with(&mut self, key: SigningKey) {
+		self.signing_keys.push(key);
+	}
+
+	/// Set when the current edition was written.
+	///
+	/// By default, this is the time when the encoder is finalised. For reproducible Zarcs, set
+	/// this to a fixed time, such as the `SOURCE_DATE_EPOCH` of the build.
+	#[instrument(level = "trace", skip(self))]
+	pub fn set_written_at(&mut self, timestamp: impl Into<Timestamp> + fmt::Debug) {
+		self.written_at = Some(timestamp.into());
+	}
+
+	/// Write the directory offset from the start of the Zarc.
+	///
+	/// By default, the [directory offset](crate::trailer::Trailer::directory_offset) is negative,
+	/// counting back from the end of the Zarc. That stays correct when the Zarc is appended to
+	/// another file, like a self-extracting archive, as the end of the Zarc is the end of the file.
+	/// A positive offset counts from the Zarc's header instead, so a decoder then needs to
+	/// [locate](crate::decode::Decoder::locate) the header first.
+	#[instrument(level = "trace", skip(self))]
+	pub fn set_directory_offset_from_start(&mut self, from_start: bool) {
+		self.offset_from_start = from_start;
 	}
 
 	/// Enable or disable compression.
 	///
-	/// This well apply to future data frames.
+	/// This will apply to future data frames. When enabled, a [compression
+	/// policy](Encoder::set_compression_policy) can still decide to store some content
+	/// uncompressed.
 	#[instrument(level = "trace", skip(self))]
 	pub fn enable_compression(&mut self, compress: bool) {
 		self.compress = compress;
@@ -22,27 +22,55 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 	pub fn add_file_entry(&mut self, entry: impl Into<File> + std::fmt::Debug) -> Result<()> {
 		let entry = entry.into();
 
-		if let Some(hash) = &entry.digest {
-			if !self.frames.contains_key(hash) {
+		for hash in entry.frame_digests() {
+			if !self.frames.contains_key(hash) && !self.external_frames.contains_key(hash) {
 				return Err(Error::other(
 					"cannot add file entry referencing unknown data frame",
 				));
 			}
 		}
 
+		// content already in a solid frame, like that of an existing Zarc, isn't stored again
+		if let (Some(digest), Some(slice)) = (&entry.digest, &entry.solid) {
+			self.solid.remember(digest.clone(), slice.clone());
+		}
+
+		self.push_file_entry(entry);
+		Ok(())
+	}
+
+	/// Add a file entry without checking its frames, returning its index.
+	pub(crate) fn push_file_entry(&mut self, entry: File) -> usize {
 		let name = entry.name.clone();
-		let digest = entry.digest.clone();
+		let digests: Vec<Digest> = entry
+			.digest
+			.iter()
+			.chain(entry.frame_digests())
+			// solid frames pending a write don't have a digest yet, see flush_solid()
+			.filter(|digest| !digest.0.is_empty())
+			.cloned()
+			.collect();
 
 		self.files.push(Some(entry));
 		let index = self.files.len() - 1;
 		trace!(index, "added file entry");
 
+		if let Some(basename) = name.0.last() {
+			self.files_by_basename
+				.entry(basename.clone())
+				.or_default()
+				.push(index);
+		}
 		self.files_by_name.entry(name).or_default().push(index);
-		if let Some(digest) = digest {
-			self.files_by_digest.entry(digest).or_default().push(index);
+		for digest in digests {
+			let indices = self.files_by_digest.entry(digest).or_default();
+			// the content digest is also the frame digest of single-frame files
+			if indices.last() != Some(&index) {
+				indices.push(index);
+			}
 		}
 
-		Ok(())
+		index
 	}
 
 	/// Get a builder for a file entry.
@@ -54,6 +82,8 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 			edition: self.edition,
 			name: name.into(),
 			digest: Default::default(),
+			chunks: Default::default(),
+			solid: Default::default(),
 			mode: Default::default(),
 			user: Default::default(),
 			group: Default::default(),
@@ -98,6 +128,25 @@ impl FileBuilder {
 	/// be checked later when the file is added to the encoder.
 	pub fn digest(&mut self, digest: impl Into<Digest>) -> &mut Self {
 		self.0.digest = Some(digest.into());
+		self.0.chunks = None;
+		self.0.solid = None;
+		self
+	}
+
+	/// Set the digests of chunked content.
+	///
+	/// The `digest` is of the whole content, and the `chunks` are the digests of the content frames,
+	/// in order, as returned by [`Encoder::add_chunked_data()`]. If there's only one chunk, this is
+	/// the same as setting the [digest](FileBuilder::digest) of that frame.
+	pub fn chunks(&mut self, digest: impl Into<Digest>, chunks: Vec<Digest>) -> &mut Self {
+		let digest = digest.into();
+		self.0.chunks = if chunks.len() == 1 && chunks[0] == digest {
+			None
+		} else {
+			Some(chunks)
+		};
+		self.0.digest = Some(digest);
+		self.0.solid = None;
 		self
 	}
 
@@ -106,6 +155,8 @@ impl FileBuilder {
 	/// This will clear the digest if it was set.
 	pub fn directory(&mut self) -> &mut Self {
 		self.0.digest = None;
+		self.0.chunks = None;
+		self.0.solid = None;
 		self.0.special = Some(SpecialFile {
 			kind: Some(SpecialFileKind::Directory),
 			..Default::default()
@@ -113,6 +164,21 @@ impl FileBuilder {
 		self
 	}
 
+	/// Make this a tombstone.
+	///
+	/// This records that the file was deleted in this edition. It will clear the digest if it was
+	/// set.
+	pub fn tombstone(&mut self) -> &mut Self {
+		self.0.digest = None;
+		self.0.chunks = None;
+		self.0.solid = None;
+		self.0.special = Some(SpecialFile {
+			kind: Some(SpecialFileKind::Tombstone),
+			..Default::default()
+		});
+		self
+	}
+
 	/// Set the POSIX mode of the file.
 	///
 	/// This does the same thing regardless of platform, so it can be used to set the mode of files
@@ -221,6 +287,42 @@ impl FileBuilder {
 		self
 	}
 
+	/// Remove metadata which depends on the host rather than on the file.
+	///
+	/// This removes the owning user and group, the accessed and created timestamps, and the file
+	/// attributes and extended attributes (like filesystem flags or SELinux labels), so that
+	/// packing the same files on different machines, or at different times, gives the same entry.
+	/// Use it for reproducible Zarcs, along with [`clamp_modified()`](FileBuilder::clamp_modified).
+	pub fn strip_host_metadata(&mut self) -> &mut Self {
+		self.0.user = None;
+		self.0.group = None;
+		self.0.attributes = None;
+		self.0.extended_attributes = None;
+		if let Some(ts) = self.0.timestamps.as_mut() {
+			ts.accessed = None;
+			ts.created = None;
+		}
+		self
+	}
+
+	/// Clamp the modified timestamp of the file to a maximum.
+	///
+	/// If the file was modified after `max`, its modified timestamp is set to `max`.
+	pub fn clamp_modified(&mut self, max: impl Into<Timestamp>) -> &mut Self {
+		let max = max.into();
+		if let Some(modified) = self
+			.0
+			.timestamps
+			.as_mut()
+			.and_then(|ts| ts.modified.as_mut())
+		{
+			if modified.0 > max.0 {
+				*modified = max;
+			}
+		}
+		self
+	}
+
 	/// Add user metadata.
 	pub fn user_metadata(
 		&mut self,
@@ -0,0 +1,297 @@
+use std::{
+	fmt,
+	io::Result,
+	ops::{Deref, DerefMut},
+};
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::{instrument, trace};
+
+use crate::{decode::Decoder, directory::File, encryption::EncryptionKey, integrity::Digest};
+
+use super::{Encoder, Output};
+
+/// Async Zarc encoder context, for tokio.
+///
+/// This writes Zarcs to an [`AsyncWrite`] without blocking, like an HTTP response body or an
+/// object store upload. It wraps an [`Encoder`], so the output is the same byte for byte: each
+/// call encodes into a buffer, which is then written out before the call returns.
+///
+/// The `Encoder` methods which don't write anything, like setting options, building and adding
+/// file entries, or adding bases, are available through [`DerefMut`]. The methods which write are
+/// here, as async methods. Writing methods called through `DerefMut` still work, but what they
+/// write is held in memory until the next async call.
+pub struct AsyncEncoder<'writer, W: AsyncWrite + Unpin> {
+	writer: &'writer mut W,
+	encoder: Encoder<'writer, Vec<u8>>,
+}
+
+impl<W: AsyncWrite + Unpin + fmt::Debug> fmt::Debug for AsyncEncoder<'_, W> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("AsyncEncoder")
+			.field("writer", &self.writer)
+			.field("encoder", &self.encoder)
+			.finish()
+	}
+}
+
+impl<'writer, W: AsyncWrite + Unpin> Deref for AsyncEncoder<'writer, W> {
+	type Target = Encoder<'writer, Vec<u8>>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.encoder
+	}
+}
+
+impl<W: AsyncWrite + Unpin> DerefMut for AsyncEncoder<'_, W> {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		&mut self.encoder
+	}
+}
+
+impl<'writer, W: AsyncWrite + Unpin> AsyncEncoder<'writer, W> {
+	/// Create a new encoder and write the header.
+	///
+	/// See [`Encoder::new()`].
+	#[instrument(level = "trace", skip(writer))]
+	pub async fn new(writer: &'writer mut W) -> Result<Self> {
+		let encoder = Encoder::with_output(Output::Owned(Vec::new()))?;
+		Self::start(writer, encoder).await
+	}
+
+	/// Create a new encoder for an encrypted Zarc, and write the header and keys.
+	///
+	/// See [`Encoder::new_encrypted()`].
+	#[instrument(level = "trace", skip(writer, keys))]
+	pub async fn new_encrypted(writer: &'writer mut W, keys: &[EncryptionKey]) -> Result<Self> {
+		let encoder = Encoder::encrypted_with_output(Output::Owned(Vec::new()), keys)?;
+		Self::start(writer, encoder).await
+	}
+
+	/// Create an encoder that adds a new edition to an existing Zarc.
+	///
+	/// See [`Encoder::append()`], including for where the `writer` must be positioned. The
+	/// `existing` Zarc can also be read with an [`AsyncDecoder`](crate::decode::AsyncDecoder),
+	/// which derefs to a [`Decoder`].
+	#[instrument(level = "trace", skip(writer, existing))]
+	pub async fn append<R>(writer: &'writer mut W, existing: &Decoder<R>) -> Result<Self> {
+		let encoder = Encoder::append_with_output(Output::Owned(Vec::new()), existing)?;
+		Self::start(writer, encoder).await
+	}
+
+	async fn start(writer: &'writer mut W, encoder: Encoder<'writer, Vec<u8>>) -> Result<Self> {
+		let mut encoder = Self { writer, encoder };
+		encoder.write_pending().await?;
+		Ok(encoder)
+	}
+
+	/// Write out what the encoder has buffered.
+	async fn write_pending(&mut self) -> Result<()> {
+		let pending = &mut *self.encoder.writer;
+		if !pending.is_empty() {
+			trace!(bytes=%pending.len(), "write buffer to writer");
+			self.writer.write_all(pending).await?;
+			pending.clear();
+		}
+
+		Ok(())
+	}
+
+	/// Call an encoder method, then write out what it wrote.
+	async fn encode<T>(
+		&mut self,
+		method: impl FnOnce(&mut Encoder<'writer, Vec<u8>>) -> Result<T>,
+	) -> Result<T> {
+		let value = method(&mut self.encoder)?;
+		self.write_pending().await?;
+		Ok(value)
+	}
+
+	/// Add a frame of data.
+	///
+	/// See [`Encoder::add_data_frame()`].
+	pub async fn add_data_frame(&mut self, content: &[u8]) -> Result<Digest> {
+		self.encode(|zarc| zarc.add_data_frame(content)).await
+	}
+
+	/// Add a frame of data for a file.
+	///
+	/// See [`Encoder::add_data_frame_for()`].
+	pub async fn add_data_frame_for(&mut self, file: &File, content: &[u8]) -> Result<Digest> {
+		self.encode(|zarc| zarc.add_data_frame_for(file, content))
+			.await
+	}
+
+	/// Add data split into frames at content-defined boundaries.
+	///
+	/// See [`Encoder::add_chunked_data()`].
+	pub async fn add_chunked_data(&mut self, content: &[u8]) -> Result<(Digest, Vec<Digest>)> {
+		self.encode(|zarc| zarc.add_chunked_data(content)).await
+	}
+
+	/// Add data for a file split into frames at content-defined boundaries.
+	///
+	/// See [`Encoder::add_chunked_data_for()`].
+	pub async fn add_chunked_data_for(
+		&mut self,
+		file: &File,
+		content: &[u8],
+	) -> Result<(Digest, Vec<Digest>)> {
+		self.encode(|zarc| zarc.add_chunked_data_for(file, content))
+			.await
+	}
+
+	/// Add data split into frames of a fixed size.
+	///
+	/// See [`Encoder::add_seekable_data()`].
+	pub async fn add_seekable_data(
+		&mut self,
+		content: &[u8],
+		frame_size: usize,
+	) -> Result<(Digest, Vec<Digest>)> {
+		self.encode(|zarc| zarc.add_seekable_data(content, frame_size))
+			.await
+	}
+
+	/// Add data for a file split into frames of a fixed size.
+	///
+	/// See [`Encoder::add_seekable_data_for()`].
+	pub async fn add_seekable_data_for(
+		&mut self,
+		file: &File,
+		content: &[u8],
+		frame_size: usize,
+	) -> Result<(Digest, Vec<Digest>)> {
+		self.encode(|zarc| zarc.add_seekable_data_for(file, content, frame_size))
+			.await
+	}
+
+	/// Add a frame of data compressed against another frame.
+	///
+	/// See [`Encoder::add_delta_frame()`].
+	pub async fn add_delta_frame(
+		&mut self,
+		content: &[u8],
+		reference: &Digest,
+		reference_content: &[u8],
+	) -> Result<Digest> {
+		self.encode(|zarc| zarc.add_delta_frame(content, reference, reference_content))
+			.await
+	}
+
+	/// Add a frame of data for a file compressed against another frame.
+	///
+	/// See [`Encoder::add_delta_frame_for()`].
+	pub async fn add_delta_frame_for(
+		&mut self,
+		file: &File,
+		content: &[u8],
+		reference: &Digest,
+		reference_content: &[u8],
+	) -> Result<Digest> {
+		self.encode(|zarc| zarc.add_delta_frame_for(file, content, reference, reference_content))
+			.await
+	}
+
+	/// Add a file entry with its content, packed in a solid frame.
+	///
+	/// See [`Encoder::add_solid_file()`].
+	pub async fn add_solid_file(
+		&mut self,
+		entry: impl Into<File> + fmt::Debug,
+		content: &[u8],
+	) -> Result<()> {
+		self.encode(|zarc| zarc.add_solid_file(entry, content))
+			.await
+	}
+
+	/// Use a dictionary to compress future content frames.
+	///
+	/// See [`Encoder::set_dictionary()`].
+	pub async fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<Digest> {
+		self.encode(|zarc| zarc.set_dictionary(dictionary)).await
+	}
+
+	/// Write the directory and trailer.
+	///
+	/// Flushes the writer and drops all state, returns the digest of the directory.
+	#[instrument(level = "debug", skip(self))]
+	pub async fn finalise(mut self) -> Result<Digest> {
+		let digest = self.encode(Encoder::finish).await?;
+		self.writer.flush().await?;
+		trace!("flushed writer");
+		Ok(digest)
+	}
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn same_output_as_encoder() {
+	use chrono::{TimeZone, Utc};
+
+	use crate::test_util::{name, varied};
+
+	let written_at = Utc
+		.timestamp_opt(1_700_000_000, 0)
+		.single()
+		.expect("valid timestamp");
+	let content = varied(300_000);
+
+	let mut expected = Vec::new();
+	let expected_digest = {
+		let mut zarc = Encoder::new(&mut expected).expect("new encoder");
+		zarc.set_written_at(written_at);
+
+		let digest = zarc.add_data_frame(b"hello").expect("add frame");
+		assert_eq!(zarc.add_data_frame(b"hello").expect("dedup frame"), digest);
+		let mut file = zarc.build_file(name("hello.txt"));
+		file.digest(digest);
+		zarc.add_file_entry(file).expect("add file");
+
+		let (digest, chunks) = zarc.add_chunked_data(&content).expect("add chunks");
+		let mut file = zarc.build_file(name("chunked.bin"));
+		file.chunks(digest, chunks);
+		zarc.add_file_entry(file).expect("add file");
+
+		let file = zarc.build_file(name("solid.txt"));
+		zarc.add_solid_file(file, b"solid").expect("add solid");
+		let mut dir = zarc.build_file(name("dir"));
+		dir.directory();
+		zarc.add_file_entry(dir).expect("add dir");
+
+		zarc.finalise().expect("finalise")
+	};
+
+	let mut actual = Vec::new();
+	let actual_digest = {
+		let mut zarc = AsyncEncoder::new(&mut actual).await.expect("new encoder");
+		zarc.set_written_at(written_at);
+
+		let digest = zarc.add_data_frame(b"hello").await.expect("add frame");
+		assert_eq!(
+			zarc.add_data_frame(b"hello").await.expect("dedup frame"),
+			digest
+		);
+		let mut file = zarc.build_file(name("hello.txt"));
+		file.digest(digest);
+		zarc.add_file_entry(file).expect("add file");
+
+		let (digest, chunks) = zarc.add_chunked_data(&content).await.expect("add chunks");
+		let mut file = zarc.build_file(name("chunked.bin"));
+		file.chunks(digest, chunks);
+		zarc.add_file_entry(file).expect("add file");
+
+		let file = zarc.build_file(name("solid.txt"));
+		zarc.add_solid_file(file, b"solid")
+			.await
+			.expect("add solid");
+		let mut dir = zarc.build_file(name("dir"));
+		dir.directory();
+		zarc.add_file_entry(dir).expect("add dir");
+
+		zarc.finalise().await.expect("finalise")
+	};
+
+	assert_eq!(actual_digest, expected_digest);
+	assert_eq!(actual, expected);
+}
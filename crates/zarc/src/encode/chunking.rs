@@ -0,0 +1,181 @@
+use std::io::{Error, Result, Write};
+
+use fastcdc::v2020::{
+	FastCDC, AVERAGE_MAX, AVERAGE_MIN, MAXIMUM_MAX, MAXIMUM_MIN, MINIMUM_MAX, MINIMUM_MIN,
+};
+use tracing::{instrument, trace};
+
+use crate::{directory::File, integrity::Digest};
+
+use super::Encoder;
+
+/// Sizes of content-defined chunks.
+///
+/// See [`Encoder::add_chunked_data()`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct ChunkSizes {
+	/// Minimum chunk size in bytes, between 64 bytes and 1 MiB.
+	pub min: u32,
+
+	/// Average chunk size in bytes, between 256 bytes and 4 MiB.
+	pub avg: u32,
+
+	/// Maximum chunk size in bytes, between 1 KiB and 16 MiB.
+	pub max: u32,
+}
+
+impl ChunkSizes {
+	/// Chunk sizes around an average: the minimum is a quarter and the maximum four times that.
+	pub fn around(avg: u32) -> Self {
+		Self {
+			min: (avg / 4).clamp(MINIMUM_MIN, MINIMUM_MAX),
+			avg: avg.clamp(AVERAGE_MIN, AVERAGE_MAX),
+			max: avg.saturating_mul(4).clamp(MAXIMUM_MIN, MAXIMUM_MAX),
+		}
+	}
+
+	fn check(&self) -> Result<()> {
+		if !(MINIMUM_MIN..=MINIMUM_MAX).contains(&self.min)
+			|| !(AVERAGE_MIN..=AVERAGE_MAX).contains(&self.avg)
+			|| !(MAXIMUM_MIN..=MAXIMUM_MAX).contains(&self.max)
+			|| self.min > self.avg
+			|| self.avg > self.max
+		{
+			return Err(Error::other(format!("invalid chunk sizes: {self:?}")));
+		}
+
+		Ok(())
+	}
+}
+
+impl Default for ChunkSizes {
+	/// 16 KiB minimum, 64 KiB average, 256 KiB maximum.
+	fn default() -> Self {
+		Self::around(64 * 1024)
+	}
+}
+
+impl<'writer, W: Write> Encoder<'writer, W> {
+	/// Set the sizes of content-defined chunks.
+	///
+	/// This will apply to future calls to [`add_chunked_data()`](Encoder::add_chunked_data).
+	#[instrument(level = "trace", skip(self))]
+	pub fn set_chunk_sizes(&mut self, sizes: ChunkSizes) -> Result<()> {
+		sizes.check()?;
+		self.chunk_sizes = sizes;
+		Ok(())
+	}
+
+	/// Add data split into frames at content-defined boundaries.
+	///
+	/// Processes the entire input in memory.
+	///
+	/// The content is split with FastCDC, so that similar content (e.g. a file with a few bytes
+	/// changed, or data inserted in the middle) produces mostly the same chunks. Each chunk is added
+	/// as with [`add_data_frame()`](Encoder::add_data_frame), so chunks already in the Zarc aren't
+	/// stored again.
+	///
+	/// Returns the hash of the whole content, and the hashes of the chunk frames in order, so they
+	/// can be referenced in a filemap entry with `FileBuilder::chunks()`.
+	#[instrument(level = "trace", skip(self, content))]
+	pub fn add_chunked_data(&mut self, content: &[u8]) -> Result<(Digest, Vec<Digest>)> {
+		self.add_chunks(None, content)
+	}
+
+	/// Add data for a file split into frames at content-defined boundaries.
+	///
+	/// This is the same as [`add_chunked_data()`](Encoder::add_chunked_data), but the file entry
+	/// is given to the [compression policy](Encoder::set_compression_policy) for each chunk, as
+	/// with [`add_data_frame_for()`](Encoder::add_data_frame_for).
+	#[instrument(level = "trace", skip(self, file, content))]
+	pub fn add_chunked_data_for(
+		&mut self,
+		file: &File,
+		content: &[u8],
+	) -> Result<(Digest, Vec<Digest>)> {
+		self.add_chunks(Some(file), content)
+	}
+
+	fn add_chunks(&mut self, file: Option<&File>, content: &[u8]) -> Result<(Digest, Vec<Digest>)> {
+		let digest = blake3::hash(content);
+		let digest = Digest(digest.as_bytes().to_vec());
+		trace!(uncompressed_size=%content.len(), digest=%format!("{digest:02x?}"), "computed digest");
+
+		if content.is_empty() {
+			return Ok((digest, vec![self.add_frame(file, content)?]));
+		}
+
+		let ChunkSizes { min, avg, max } = self.chunk_sizes;
+		let mut chunks = Vec::new();
+		for chunk in FastCDC::new(content, min, avg, max) {
+			trace!(offset=%chunk.offset, length=%chunk.length, "add chunk");
+			chunks
+				.push(self.add_frame(file, &content[chunk.offset..(chunk.offset + chunk.length)])?);
+		}
+
+		Ok((digest, chunks))
+	}
+
+	/// Add data split into frames of a fixed size.
+	///
+	/// Processes the entire input in memory.
+	///
+	/// Each frame is compressed independently, so the decoder can start reading from any frame
+	/// without decompressing the ones before it: this makes the content seekable, see
+	/// [`Decoder::read_at()`](crate::decode::Decoder::read_at). Smaller frames make seeking faster,
+	/// larger frames compress better.
+	///
+	/// Returns the hash of the whole content, and the hashes of the frames in order, as with
+	/// [`add_chunked_data()`](Encoder::add_chunked_data).
+	#[instrument(level = "trace", skip(self, content))]
+	pub fn add_seekable_data(
+		&mut self,
+		content: &[u8],
+		frame_size: usize,
+	) -> Result<(Digest, Vec<Digest>)> {
+		self.add_fixed_chunks(None, content, frame_size)
+	}
+
+	/// Add data for a file split into frames of a fixed size.
+	///
+	/// This is the same as [`add_seekable_data()`](Encoder::add_seekable_data), but the file entry
+	/// is given to the [compression policy](Encoder::set_compression_policy) for each frame, as
+	/// with [`add_data_frame_for()`](Encoder::add_data_frame_for).
+	#[instrument(level = "trace", skip(self, file, content))]
+	pub fn add_seekable_data_for(
+		&mut self,
+		file: &File,
+		content: &[u8],
+		frame_size: usize,
+	) -> Result<(Digest, Vec<Digest>)> {
+		self.add_fixed_chunks(Some(file), content, frame_size)
+	}
+
+	fn add_fixed_chunks(
+		&mut self,
+		file: Option<&File>,
+		content: &[u8],
+		frame_size: usize,
+	) -> Result<(Digest, Vec<Digest>)> {
+		if frame_size == 0 {
+			return Err(Error::other(
+				"seekable frame size must be at least one byte",
+			));
+		}
+
+		let digest = blake3::hash(content);
+		let digest = Digest(digest.as_bytes().to_vec());
+		trace!(uncompressed_size=%content.len(), digest=%format!("{digest:02x?}"), "computed digest");
+
+		if content.is_empty() {
+			return Ok((digest, vec![self.add_frame(file, content)?]));
+		}
+
+		let chunks = content
+			.chunks(frame_size)
+			.map(|chunk| self.add_frame(file, chunk))
+			.collect::<Result<_>>()?;
+
+		Ok((digest, chunks))
+	}
+}
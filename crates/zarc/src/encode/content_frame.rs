@@ -1,11 +1,19 @@
-use std::io::{Error, Result, Write};
+use std::{
+	io::{copy, Error, ErrorKind, Read, Result, Write},
+	num::NonZeroU16,
+};
 
 use tracing::{instrument, trace};
 use zstd_safe::ResetDirective;
 
-use crate::{directory::Frame, integrity::Digest, map_zstd_error};
+use crate::{
+	directory::{File, Frame},
+	encryption::CONTENT_AAD,
+	integrity::Digest,
+	map_zstd_error,
+};
 
-use super::Encoder;
+use super::{Compression, Encoder};
 
 impl<'writer, W: Write> Encoder<'writer, W> {
 	/// Add a frame of data.
@@ -14,35 +22,74 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 	///
 	/// Returns the hash of the data, so it can be referenced in a filemap entry.
 	///
-	/// If the content hashes to a frame that already exists, returns the hash without storing
-	/// a duplicate frame.
+	/// If the content hashes to a frame that already exists, either in this Zarc or in one of its
+	/// [bases](Encoder::add_base), returns the hash without storing a duplicate frame.
 	#[instrument(level = "trace", skip(self, content))]
 	pub fn add_data_frame(&mut self, content: &[u8]) -> Result<Digest> {
-		// collect pre-compression values
-		let offset = self.offset.try_into().map_err(Error::other)?;
-		let uncompressed_size = content.len();
+		self.add_frame(None, content)
+	}
 
+	/// Add a frame of data for a file.
+	///
+	/// This is the same as [`add_data_frame()`](Encoder::add_data_frame), but the file entry is
+	/// given to the [compression policy](Encoder::set_compression_policy), so it can decide based
+	/// on e.g. the file's name. The entry isn't added, nor is its digest set.
+	#[instrument(level = "trace", skip(self, file, content))]
+	pub fn add_data_frame_for(&mut self, file: &File, content: &[u8]) -> Result<Digest> {
+		self.add_frame(Some(file), content)
+	}
+
+	pub(crate) fn add_frame(&mut self, file: Option<&File>, content: &[u8]) -> Result<Digest> {
 		// compute content hash
 		let digest = blake3::hash(content);
 		let digest = Digest(digest.as_bytes().to_vec());
-		trace!(%uncompressed_size, digest=%format!("{digest:02x?}"), "computed digest");
+		trace!(uncompressed_size=%content.len(), digest=%format!("{digest:02x?}"), "computed digest");
 
 		if self.frames.contains_key(&digest) {
 			trace!("frame already exists, skipping");
 			return Ok(digest);
 		}
 
-		let bytes = if self.compress {
-			// start new compression context
-			self.zstd
-				.reset(ResetDirective::SessionOnly)
-				.map_err(map_zstd_error)?;
+		if self.external_frames.contains_key(&digest) {
+			trace!("frame exists in a base, skipping");
+			return Ok(digest);
+		}
 
-			self.write_compressed_frame(content)
-		} else {
-			self.write_uncompressed_frame(content)
-		}?;
+		let compression = self.compression(file, content);
+		self.write_content_frame(digest, content, compression)
+	}
+
+	/// Write a new content frame, as decided by the compression policy.
+	///
+	/// The `digest` must be the hash of the `content`.
+	pub(crate) fn write_content_frame(
+		&mut self,
+		digest: Digest,
+		content: &[u8],
+		compression: Compression,
+	) -> Result<Digest> {
+		// collect pre-compression values
+		let offset = self.offset.try_into().map_err(Error::other)?;
+		let uncompressed_size = content.len();
+
+		let dictionary = self.dictionary.as_ref().map(|(digest, _)| digest.clone());
+		let (bytes, dictionary) = match compression {
+			Compression::Compress => {
+				// start new compression context
+				self.zstd
+					.reset(ResetDirective::SessionOnly)
+					.map_err(map_zstd_error)?;
+
+				(self.write_compressed_frame(content)?, dictionary)
+			}
+			Compression::With(parameters) => (
+				self.write_compressed_frame_with(&parameters, content)?,
+				dictionary,
+			),
+			Compression::Store => (self.write_uncompressed_frame(content)?, None),
+		};
 		self.offset += bytes;
+		let outboard = self.add_outboard(content)?;
 
 		// push frame to list
 		self.frames.insert(
@@ -53,6 +100,73 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 				digest: digest.clone(),
 				length: bytes as _,
 				uncompressed: uncompressed_size as _,
+				dictionary,
+				reference: None,
+				outboard,
+			},
+		);
+
+		Ok(digest)
+	}
+
+	/// Add a frame of data that's already been framed elsewhere.
+	///
+	/// This copies the frame's bytes verbatim, without decompressing or recompressing, which is
+	/// how frames are moved from one Zarc to another. The `frame` is the metadata of the frame in
+	/// its source, and `reader` must yield the entire Zstandard frame, `frame.length` bytes long.
+	///
+	/// The content isn't read, so the digest is trusted as given. If the frame was compressed with
+	/// a dictionary or against a reference, or has an outboard, those frames must be added too.
+	///
+	/// If a frame with that digest already exists, returns the digest without storing a duplicate.
+	#[instrument(level = "trace", skip(self, reader))]
+	pub fn add_raw_frame(&mut self, frame: &Frame, reader: &mut impl Read) -> Result<Digest> {
+		self.copy_raw_frame(frame, self.edition, reader)
+	}
+
+	/// Copy a raw frame, marking it as added in the given edition.
+	pub(crate) fn copy_raw_frame(
+		&mut self,
+		frame: &Frame,
+		edition: NonZeroU16,
+		reader: &mut impl Read,
+	) -> Result<Digest> {
+		let digest = frame.digest.clone();
+		if self.frames.contains_key(&digest) {
+			trace!("frame already exists, skipping");
+			return Ok(digest);
+		}
+
+		let offset = self.offset.try_into().map_err(Error::other)?;
+		let mut reader = reader.take(frame.length);
+		let (read, bytes) = if self.cipher.is_some() {
+			let mut buffer = Vec::with_capacity(frame.length as _);
+			let read = reader.read_to_end(&mut buffer)? as u64;
+			(read, self.write_frame(&buffer, CONTENT_AAD)? as u64)
+		} else {
+			let bytes = copy(&mut reader, &mut *self.writer)?;
+			(bytes, bytes)
+		};
+		if read != frame.length {
+			return Err(Error::new(
+				ErrorKind::UnexpectedEof,
+				format!("raw frame is {read} bytes but should be {}", frame.length),
+			));
+		}
+		trace!(%bytes, "copied raw frame");
+		self.offset += usize::try_from(bytes).map_err(Error::other)?;
+
+		self.frames.insert(
+			digest.clone(),
+			Frame {
+				edition,
+				offset,
+				digest: digest.clone(),
+				length: bytes,
+				uncompressed: frame.uncompressed,
+				dictionary: frame.dictionary.clone(),
+				reference: frame.reference.clone(),
+				outboard: frame.outboard.clone(),
 			},
 		);
 
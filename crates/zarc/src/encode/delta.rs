@@ -0,0 +1,223 @@
+use std::io::{Error, ErrorKind, Result, Write};
+
+use tracing::{debug, instrument, trace};
+
+use crate::{
+	directory::{File, Frame, Pathname},
+	encryption::CONTENT_AAD,
+	integrity::Digest,
+	map_zstd_error,
+};
+
+use super::{Compression, Encoder, ZstdParameter};
+
+/// Content smaller than this isn't worth compressing against a reference.
+const MIN_DELTA_SIZE: u64 = 1024;
+
+/// How many references deep a frame can be.
+///
+/// Decompressing a frame requires decompressing its reference first, and so on, so this bounds
+/// the work for reading any one frame. Decoders refuse deeper chains.
+pub(crate) const MAX_DELTA_DEPTH: usize = 8;
+
+/// Smallest window zstd supports.
+const MIN_DELTA_WINDOW_LOG: u32 = 10;
+
+/// Largest window to use for delta frames.
+///
+/// This is the largest window that decoders accept without special configuration.
+const MAX_DELTA_WINDOW_LOG: u32 = 27;
+
+/// Magic number at the start of zstd dictionaries.
+const DICTIONARY_MAGIC: [u8; 4] = [0x37, 0xA4, 0x30, 0xEC];
+
+impl<'writer, W: Write> Encoder<'writer, W> {
+	/// Pick a frame to compress new content against.
+	///
+	/// This looks for content which is likely to be similar to the content of a file that's about
+	/// to be added, given its name and size: the latest version of the same pathname, or else the
+	/// latest file with the same file name in another directory. Candidates must be in a single
+	/// frame of their own, of a size comparable to the new content.
+	///
+	/// Returns `None` if there's no good candidate, or if the content is too small to be worth it.
+	/// To use the reference, pass it and its content to [`add_delta_frame()`](Encoder::add_delta_frame).
+	#[instrument(level = "trace", skip(self))]
+	pub fn delta_reference(&self, name: &Pathname, size: u64) -> Option<&Frame> {
+		if size < MIN_DELTA_SIZE {
+			return None;
+		}
+
+		let same_path = self.files_by_name.get(name).into_iter().flatten();
+		let same_name = name
+			.0
+			.last()
+			.and_then(|basename| self.files_by_basename.get(basename))
+			.into_iter()
+			.flatten();
+
+		same_path
+			.rev()
+			.chain(same_name.rev())
+			.filter_map(|index| self.files.get(*index).and_then(Option::as_ref))
+			.filter(|file| file.chunks.is_none() && file.solid.is_none())
+			.filter_map(|file| self.frames.get(file.digest.as_ref()?))
+			.find(|frame| {
+				(size / 2..=size.saturating_mul(2)).contains(&frame.uncompressed)
+					&& self.delta_depth(&frame.digest) < MAX_DELTA_DEPTH
+			})
+	}
+
+	/// Add a frame of data, compressed with reference to another frame.
+	///
+	/// Processes the entire input in memory.
+	///
+	/// This uses the content of the `reference` frame as a prefix to compress against: if the two
+	/// are similar, which is the case for successive versions of the same file, the new frame is
+	/// much smaller than it would be otherwise. Decompressing the frame then requires decompressing
+	/// the reference first. The `reference_content` must be the uncompressed content of the
+	/// `reference` frame, which must be in this Zarc or in one of its bases.
+	///
+	/// Returns the hash of the data, and doesn't store duplicates, like
+	/// [`add_data_frame()`](Encoder::add_data_frame). If compression is disabled, or if the
+	/// reference content looks like a zstd dictionary, this is the same as `add_data_frame()`.
+	///
+	/// The [compression policy](Encoder::set_compression_policy) is consulted: if it stores the
+	/// content, it's stored without reference, and its extra parameters apply on top of those
+	/// needed to compress against the reference.
+	#[instrument(level = "trace", skip(self, content, reference_content))]
+	pub fn add_delta_frame(
+		&mut self,
+		content: &[u8],
+		reference: &Digest,
+		reference_content: &[u8],
+	) -> Result<Digest> {
+		self.add_delta(None, content, reference, reference_content)
+	}
+
+	/// Add a frame of data for a file, compressed with reference to another frame.
+	///
+	/// This is the same as [`add_delta_frame()`](Encoder::add_delta_frame), but the file entry is
+	/// given to the [compression policy](Encoder::set_compression_policy), as with
+	/// [`add_data_frame_for()`](Encoder::add_data_frame_for).
+	#[instrument(level = "trace", skip(self, file, content, reference_content))]
+	pub fn add_delta_frame_for(
+		&mut self,
+		file: &File,
+		content: &[u8],
+		reference: &Digest,
+		reference_content: &[u8],
+	) -> Result<Digest> {
+		self.add_delta(Some(file), content, reference, reference_content)
+	}
+
+	fn add_delta(
+		&mut self,
+		file: Option<&File>,
+		content: &[u8],
+		reference: &Digest,
+		reference_content: &[u8],
+	) -> Result<Digest> {
+		if !self.compress || reference_content.starts_with(&DICTIONARY_MAGIC) {
+			return self.add_frame(file, content);
+		}
+
+		if !self.frames.contains_key(reference) && !self.external_frames.contains_key(reference) {
+			return Err(Error::other("cannot compress against unknown data frame"));
+		}
+
+		if blake3::hash(reference_content).as_bytes() != reference.as_slice() {
+			return Err(Error::new(
+				ErrorKind::InvalidInput,
+				"reference content doesn't match reference digest",
+			));
+		}
+
+		let offset = self.offset.try_into().map_err(Error::other)?;
+		let uncompressed_size = content.len();
+
+		let digest = blake3::hash(content);
+		let digest = Digest(digest.as_bytes().to_vec());
+		trace!(%uncompressed_size, digest=%format!("{digest:02x?}"), "computed digest");
+
+		if self.frames.contains_key(&digest) || self.external_frames.contains_key(&digest) {
+			trace!("frame already exists, skipping");
+			return Ok(digest);
+		}
+
+		let parameters = match self.compression(file, content) {
+			Compression::Compress => Vec::new(),
+			Compression::With(parameters) => parameters,
+			Compression::Store => {
+				trace!("policy stores the content, don't compress against reference");
+				return self.write_content_frame(digest, content, Compression::Store);
+			}
+		};
+
+		// the prefix only lives for this call, so it can't go in the encoder's context
+		let mut zstd = self.create_zstd_with_parameters()?;
+		for parameter in parameters {
+			zstd.set_parameter(parameter).map_err(map_zstd_error)?;
+		}
+
+		// the window needs to cover the reference for matches to be found in all of it
+		let window = (reference_content.len() + content.len())
+			.next_power_of_two()
+			.trailing_zeros()
+			.clamp(MIN_DELTA_WINDOW_LOG, MAX_DELTA_WINDOW_LOG);
+		trace!(%window, "set window to cover reference");
+		zstd.set_parameter(ZstdParameter::WindowLog(window))
+			.map_err(map_zstd_error)?;
+
+		// the regular match finders only remember the most recent positions of the prefix
+		zstd.set_parameter(ZstdParameter::EnableLongDistanceMatching(true))
+			.map_err(map_zstd_error)?;
+		zstd.ref_prefix(reference_content).map_err(map_zstd_error)?;
+
+		let mut buffer: Vec<u8> = Vec::with_capacity(content.len() + 1024.max(content.len() / 10));
+		zstd.compress2(&mut buffer, content)
+			.map_err(map_zstd_error)?;
+		debug!(
+			uncompressed=%uncompressed_size,
+			compressed=%buffer.len(),
+			"compressed against reference"
+		);
+
+		let bytes = self.write_frame(&buffer, CONTENT_AAD)?;
+		self.offset += bytes;
+		let outboard = self.add_outboard(content)?;
+
+		self.frames.insert(
+			digest.clone(),
+			Frame {
+				edition: self.edition,
+				offset,
+				digest: digest.clone(),
+				length: bytes as _,
+				uncompressed: uncompressed_size as _,
+				dictionary: None,
+				reference: Some(reference.clone()),
+				outboard,
+			},
+		);
+
+		Ok(digest)
+	}
+
+	/// How many references deep a frame is.
+	fn delta_depth(&self, digest: &Digest) -> usize {
+		let mut depth = 0;
+		let mut current = digest;
+		while let Some(reference) = self
+			.frames
+			.get(current)
+			.and_then(|frame| frame.reference.as_ref())
+		{
+			depth += 1;
+			current = reference;
+			if depth >= MAX_DELTA_DEPTH {
+				break;
+			}
+		}
+		depth
+	}
+}
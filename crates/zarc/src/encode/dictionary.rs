@@ -0,0 +1,75 @@
+use std::io::{Error, Result, Write};
+
+use tracing::{debug, instrument, trace};
+
+use crate::{integrity::Digest, map_zstd_error};
+
+use super::{Encoder, ZstdParameter};
+
+/// Train a zstd dictionary from samples of content.
+///
+/// Samples should be representative of the content to be compressed, typically whole small
+/// files. Zstd recommends a total sample size of about a hundred times the dictionary size, and
+/// a dictionary size of about 100 KiB.
+///
+/// This errors if there aren't enough samples to train a dictionary.
+#[instrument(level = "debug", skip(samples))]
+pub fn train_dictionary<S: AsRef<[u8]>>(samples: &[S], size: usize) -> Result<Vec<u8>> {
+	let sizes: Vec<usize> = samples.iter().map(|sample| sample.as_ref().len()).collect();
+	let buffer = samples
+		.iter()
+		.flat_map(|sample| sample.as_ref())
+		.copied()
+		.collect::<Vec<u8>>();
+	trace!(samples=%sizes.len(), bytes=%buffer.len(), "collected samples");
+
+	let mut dictionary = Vec::with_capacity(size);
+	zstd_safe::train_from_buffer(&mut dictionary, &buffer, &sizes).map_err(map_zstd_error)?;
+	debug!(bytes=%dictionary.len(), "trained dictionary");
+	Ok(dictionary)
+}
+
+impl<'writer, W: Write> Encoder<'writer, W> {
+	/// Use a dictionary to compress future content frames.
+	///
+	/// Dictionaries improve compression of small content a lot, provided it's similar to what the
+	/// dictionary was trained on (see [`train_dictionary()`]).
+	///
+	/// The dictionary is stored in the Zarc as a frame of its own, compressed without dictionary,
+	/// and returns its digest. Content frames record the digest of the dictionary they were
+	/// compressed with. Setting another dictionary replaces it for future frames.
+	///
+	/// This also enables the `DictIdFlag` zstd parameter.
+	#[instrument(level = "debug", skip(self, dictionary))]
+	pub fn set_dictionary(&mut self, dictionary: &[u8]) -> Result<Digest> {
+		if zstd_safe::get_dict_id(dictionary).is_none() {
+			return Err(Error::other("not a zstd dictionary"));
+		}
+
+		self.disable_dictionary()?;
+
+		// the dictionary must be in this zarc to be able to decompress its frames
+		let digest = blake3::hash(dictionary);
+		self.external_frames
+			.remove(&Digest(digest.as_bytes().to_vec()));
+
+		let digest = self.add_data_frame(dictionary)?;
+		trace!(digest=%format!("{digest:02x?}"), "stored dictionary");
+
+		self.zstd
+			.load_dictionary(dictionary)
+			.map_err(map_zstd_error)?;
+		self.set_zstd_parameter(ZstdParameter::DictIdFlag(true))?;
+		self.dictionary = Some((digest.clone(), dictionary.to_vec()));
+		Ok(digest)
+	}
+
+	/// Stop using a dictionary for future content frames.
+	#[instrument(level = "debug", skip(self))]
+	pub fn disable_dictionary(&mut self) -> Result<()> {
+		self.zstd.disable_dictionary().map_err(map_zstd_error)?;
+		self.dictionary = None;
+		self.policy_zstd = None;
+		Ok(())
+	}
+}
@@ -1,16 +1,22 @@
 use std::{
+	collections::{BTreeMap, HashMap, HashSet},
 	io::{Error, Result, Write},
 	mem::take,
 };
 
 use blake3::Hasher;
 use deku::DekuContainerWrite;
+use ed25519_dalek::Signer;
 use ozarc::framing::SKIPPABLE_FRAME_OVERHEAD;
 use tracing::{debug, instrument, trace};
 
 use crate::{
 	constants::ZARC_VERSION,
-	directory::{Edition, Element, ElementFrame, Timestamp},
+	directory::{
+		Edition, Element, ElementFrame, External, ExternalFrame, File, Frame, Pathname, Signature,
+		SignatureScheme, Timestamp,
+	},
+	encryption::DIRECTORY_AAD,
 	integrity::{Digest, DigestType},
 	trailer::Trailer,
 };
@@ -20,17 +26,26 @@ use super::Encoder;
 impl<'writer, W: Write> Encoder<'writer, W> {
 	#[instrument(level = "trace", skip(buf, hasher))]
 	fn write_element(buf: &mut Vec<u8>, hasher: &mut Hasher, element: &Element) -> Result<()> {
+		let bytes = Self::write_unhashed_element(buf, element)?;
+		hasher.update(&bytes);
+		Ok(())
+	}
+
+	/// Write an element which isn't part of the directory digest.
+	///
+	/// Returns the bytes of the element frame.
+	#[instrument(level = "trace", skip(buf))]
+	fn write_unhashed_element(buf: &mut Vec<u8>, element: &Element) -> Result<Vec<u8>> {
 		let frame = ElementFrame::create(element).map_err(Error::other)?;
 		let bytes = frame.to_bytes().map_err(Error::other)?;
 		buf.write_all(&bytes)?;
-		hasher.update(&bytes);
 		trace!(
 			kind = ?element.kind(),
 			length = %bytes.len(),
 			bytes = %format!("{bytes:02x?}"),
 			"wrote element"
 		);
-		Ok(())
+		Ok(bytes)
 	}
 
 	/// Write the directory and trailer.
@@ -38,61 +53,73 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 	/// Flushes the writer and drops all state, returns the digest of the directory.
 	#[instrument(level = "debug", skip(self))]
 	pub fn finalise(mut self) -> Result<Digest> {
-		let mut directory = Vec::new();
-		let digest_type = DigestType::Blake3;
-		let mut hasher = Hasher::new(); // TODO: get hasher from DigestType
-
-		Self::write_element(
-			&mut directory,
-			&mut hasher,
-			&Element::Edition(Box::new(Edition {
-				number: self.edition,
-				written_at: Timestamp::now(),
-				digest_type,
-				user_metadata: Default::default(),
-			})),
-		)?;
-
-		for (name, indices) in take(&mut self.files_by_name) {
-			debug!(?name, "write file and frame elements");
+		self.finish()
+	}
 
-			for index in indices {
-				let Some(file) = self.files.get_mut(index).and_then(Option::take) else {
-					// this shouldn't happen, but it's cheap to just skip instead of unwrapping
-					continue;
-				};
+	/// Write the directory and trailer, leaving the encoder empty.
+	pub(crate) fn finish(&mut self) -> Result<Digest> {
+		self.flush_solid()?;
 
-				// we always want to insert a frame element before the linked file element
-				if let Some(digest) = &file.digest {
-					// if we've already written it, this will be None
-					if let Some(frame) = self.frames.remove(digest) {
-						Self::write_element(
-							&mut directory,
-							&mut hasher,
-							&Element::Frame(Box::new(frame)),
-						)?;
-					}
-				}
+		// the directory must be readable without a dictionary
+		self.disable_dictionary()?;
 
-				Self::write_element(&mut directory, &mut hasher, &Element::File(Box::new(file)))?;
-			}
-		}
+		let mut directory = Vec::new();
+		let digest_type = DigestType::Blake3;
+		let mut hasher = Hasher::new(); // TODO: get hasher from DigestType
 
-		// we should have written every frame, but just in case
-		// (or if user inserted frames not linked to files)
-		for frame in take(&mut self.frames).into_values() {
-			Self::write_element(
-				&mut directory,
-				&mut hasher,
-				&Element::Frame(Box::new(frame)),
-			)?;
+		let mut editions = take(&mut self.editions);
+		editions.push(Edition {
+			number: self.edition,
+			written_at: self.written_at.unwrap_or_else(Timestamp::now),
+			digest_type,
+			previous_digest: self.previous_digest.take(),
+			user_metadata: Default::default(),
+		});
+
+		let files = take(&mut self.files_by_name)
+			.into_iter()
+			.map(|(name, indices)| {
+				let files = indices
+					.into_iter()
+					.filter_map(|index| self.files.get_mut(index).and_then(Option::take))
+					.collect();
+				(name, files)
+			})
+			.collect();
+
+		let elements = DirectoryElements {
+			editions,
+			externals: take(&mut self.externals),
+			files,
+			frames: take(&mut self.frames),
+			external_frames: take(&mut self.external_frames),
+		};
+		for element in elements.into_elements() {
+			Self::write_element(&mut directory, &mut hasher, &element)?;
 		}
 
 		let digest = hasher.finalize();
 		trace!(?digest, "hashed directory");
 		let digest = Digest(digest.as_bytes().to_vec());
 
-		let bytes = self.write_compressed_frame(&directory)?;
+		// signatures are over the digest, so they're written after and not hashed
+		for key in take(&mut self.signing_keys) {
+			debug!(key=?key.verifying_key(), "sign directory");
+			self.signatures.push(Signature {
+				edition: self.edition,
+				scheme: SignatureScheme::Ed25519,
+				public_key: key.verifying_key().to_bytes().to_vec(),
+				digest: digest.clone(),
+				signature: key.sign(&digest).to_bytes().to_vec(),
+			});
+		}
+		for signature in take(&mut self.signatures) {
+			Self::write_unhashed_element(&mut directory, &Element::Signature(Box::new(signature)))?;
+		}
+
+		let frame = self.compress_frame(&directory)?;
+		let directory_offset = self.offset;
+		let bytes = self.write_frame(&frame, DIRECTORY_AAD)?;
 		trace!(%bytes, "wrote directory");
 
 		let mut trailer = Trailer {
@@ -102,7 +129,11 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 			directory_uncompressed_size: directory.len() as _,
 			digest: digest.clone(),
 		};
-		trailer.directory_offset = -((bytes + SKIPPABLE_FRAME_OVERHEAD + trailer.len()) as i64);
+		trailer.directory_offset = if self.offset_from_start {
+			i64::try_from(directory_offset).map_err(Error::other)?
+		} else {
+			-((bytes + SKIPPABLE_FRAME_OVERHEAD + trailer.len()) as i64)
+		};
 		trace!(?trailer, "built trailer");
 
 		let trailer_bytes = trailer.to_bytes();
@@ -121,3 +152,79 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 		Ok(digest)
 	}
 }
+
+/// The content of a directory, before it's written.
+///
+/// This decides the order of elements in the directory. It must be stable: the directories of
+/// previous editions are recomputed from the current one to [verify the edition
+/// chain](crate::decode::Decoder::verify_edition_chain).
+#[derive(Debug, Default)]
+pub(crate) struct DirectoryElements {
+	pub editions: Vec<Edition>,
+	pub externals: Vec<External>,
+	pub files: BTreeMap<Pathname, Vec<File>>,
+	pub frames: HashMap<Digest, Frame>,
+	pub external_frames: HashMap<Digest, ExternalFrame>,
+}
+
+impl DirectoryElements {
+	/// Get the elements in the order they're written, excluding signatures.
+	pub fn into_elements(mut self) -> Vec<Element> {
+		let mut elements = Vec::new();
+
+		for edition in self.editions {
+			elements.push(Element::Edition(Box::new(edition)));
+		}
+
+		// frames compressed against an external frame need it even if no file references it
+		let dependencies: HashSet<Digest> = self
+			.frames
+			.values()
+			.filter_map(|frame| frame.reference.clone())
+			.collect();
+
+		self.externals.sort_by(|a, b| {
+			(a.edition, a.digest.as_slice()).cmp(&(b.edition, b.digest.as_slice()))
+		});
+		for external in self.externals {
+			elements.push(Element::External(Box::new(external)));
+		}
+
+		for (name, files) in self.files {
+			debug!(?name, "write file and frame elements");
+
+			for file in files {
+				// we always want to insert frame elements before the linked file element
+				for digest in file.frame_digests() {
+					// if we've already written it, this will be None
+					if let Some(frame) = self.frames.remove(digest) {
+						elements.push(Element::Frame(Box::new(frame)));
+					} else if let Some(frame) = self.external_frames.remove(digest) {
+						elements.push(Element::ExternalFrame(Box::new(frame)));
+					}
+				}
+
+				elements.push(Element::File(Box::new(file)));
+			}
+		}
+
+		// we should have written every frame, but just in case
+		// (or if user inserted frames not linked to files)
+		// external frames not linked to files or frames are useless, so they're not written
+		// these are sorted so the directory is the same for the same content
+		let mut frames: Vec<_> = self.frames.into_values().collect();
+		frames.sort_by_key(|frame| frame.offset);
+		for frame in frames {
+			elements.push(Element::Frame(Box::new(frame)));
+		}
+		let mut external_frames: Vec<_> = self.external_frames.into_values().collect();
+		external_frames.sort_by(|a, b| a.digest.as_slice().cmp(b.digest.as_slice()));
+		for frame in external_frames {
+			if dependencies.contains(&frame.digest) {
+				elements.push(Element::ExternalFrame(Box::new(frame)));
+			}
+		}
+
+		elements
+	}
+}
@@ -3,7 +3,10 @@ use std::io::{Result, Write};
 use deku::DekuContainerWrite;
 use tracing::{instrument, trace};
 
-use crate::map_zstd_error;
+use crate::{
+	encryption::{CONTENT_AAD, ENCRYPTED_FRAME_NIBBLE},
+	map_zstd_error,
+};
 
 use super::Encoder;
 
@@ -17,6 +20,12 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 	#[cfg_attr(feature = "expose-internals", visibility::make(pub))]
 	#[instrument(level = "trace", skip(self, data))]
 	pub(crate) fn write_compressed_frame(&mut self, data: &[u8]) -> Result<usize> {
+		let buffer = self.compress_frame(data)?;
+		self.write_frame(&buffer, CONTENT_AAD)
+	}
+
+	/// Compress a frame into memory.
+	pub(crate) fn compress_frame(&mut self, data: &[u8]) -> Result<Vec<u8>> {
 		// start with a buffer slightly larger than the input
 		let mut buffer: Vec<u8> = Vec::with_capacity(data.len() + 1024.max(data.len() / 10));
 
@@ -30,12 +39,7 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 			.compress2(&mut buffer, data)
 			.map_err(map_zstd_error)?;
 
-		trace!(
-			bytes = %format!("{buffer:02x?}"),
-			length = %buffer.len(),
-			"write buffer to writer"
-		);
-		self.writer.write(&buffer)
+		Ok(buffer)
 	}
 
 	/// Write an uncompressed frame.
@@ -56,7 +60,8 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 					checksum: false,
 					did_size: 0,
 				},
-				window_descriptor: None,
+				// 128 KiB, the maximum block size, so any decoder can read the raw blocks
+				window_descriptor: Some(7 << 3),
 				did: Vec::new(),
 				#[allow(clippy::unwrap_used)] // UNWRAP: realistically we'll never have more than u64 bytes of content
 				frame_content_size: u64::try_from(data.len()).unwrap().to_le_bytes().to_vec(),
@@ -80,7 +85,30 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 			last.header.last = true;
 		}
 
-		self.writer.write(&frame.to_bytes()?)
+		self.write_frame(&frame.to_bytes()?, CONTENT_AAD)
+	}
+
+	/// Write a Zstandard frame, which is already in memory.
+	///
+	/// If the Zarc is encrypted, the frame is encrypted with the given associated data, and written
+	/// in a Skippable frame instead.
+	///
+	/// Returns the amount of bytes written.
+	#[instrument(level = "trace", skip(self, frame, aad))]
+	pub(crate) fn write_frame(&mut self, frame: &[u8], aad: &[u8]) -> Result<usize> {
+		let Some(cipher) = &self.cipher else {
+			trace!(
+				bytes = %format!("{frame:02x?}"),
+				length = %frame.len(),
+				"write buffer to writer"
+			);
+			self.writer.write_all(frame)?;
+			return Ok(frame.len());
+		};
+
+		let sealed = cipher.seal(aad, frame)?;
+		trace!(length = %frame.len(), sealed = %sealed.len(), "encrypted frame");
+		self.write_skippable_frame(ENCRYPTED_FRAME_NIBBLE, sealed)
 	}
 
 	/// Write a skippable frame.
@@ -103,6 +131,7 @@ impl<'writer, W: Write> Encoder<'writer, W> {
 			length = %buffer.len(),
 			"write buffer to writer"
 		);
-		self.writer.write(&buffer)
+		self.writer.write_all(&buffer)?;
+		Ok(buffer.len())
 	}
 }
@@ -0,0 +1,455 @@
+use std::{
+	collections::{HashMap, HashSet},
+	io::{Error, Result, Write},
+	num::NonZeroU16,
+};
+
+use tracing::{debug, instrument, trace};
+
+use crate::{
+	decode::Decoder,
+	directory::{Edition, External, ExternalFrame, File, Frame, Pathname},
+	integrity::Digest,
+	ondemand::OnDemand,
+};
+
+use super::Encoder;
+
+/// What to do when merging in a file entry with the same pathname as an existing one.
+///
+/// See [`Encoder::merge()`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub enum MergeConflict {
+	/// Keep all entries.
+	///
+	/// The latest merged-in entry takes the current edition of the encoder, so it's the one that
+	/// gets unpacked by default, but the earlier entries remain in the directory.
+	#[default]
+	KeepBoth,
+
+	/// Remove the existing entries, keeping only the merged-in one.
+	///
+	/// Content frames which nothing references anymore once the existing entries are removed are
+	/// left out of the directory. They may already have been written, so to avoid storing them at
+	/// all when merging several Zarcs, use [`Encoder::merge_all()`].
+	LastWins,
+
+	/// Abort the merge with an error.
+	///
+	/// Directories don't conflict with other directories.
+	Error,
+}
+
+impl<'writer, W: Write> Encoder<'writer, W> {
+	/// Merge the contents of another Zarc into this one.
+	///
+	/// The `input` must have had its directory read. All its editions are added to this Zarc with
+	/// new numbers (keeping their timestamps and user metadata), and then all its file entries are
+	/// added, marked with their renumbered edition. Content frames are copied verbatim, without
+	/// recompressing, unless a frame with the same digest is already present.
+	///
+	/// If the input is a thin Zarc, its references to external frames are carried over as-is, so
+	/// the result is also thin against the same bases.
+	///
+	/// Within an input, entries with the same pathname are kept as they are. Entries conflicting
+	/// with pathnames already in the encoder are resolved with `on_conflict`. A real frame merged
+	/// in replaces a reference to an external frame with the same digest.
+	///
+	/// Merging bumps the current edition number of the encoder, and file entries already added are
+	/// moved to it, but builders obtained from [`build_file()`](Encoder::build_file) before the
+	/// merge will still have the old number: create builders after merging.
+	#[instrument(level = "debug", skip(self, input))]
+	pub fn merge<R: OnDemand>(
+		&mut self,
+		input: &Decoder<R>,
+		on_conflict: MergeConflict,
+	) -> Result<()> {
+		self.merge_with(input, on_conflict, |_| true)
+	}
+
+	/// Merge the contents of several other Zarcs into this one, in order.
+	///
+	/// Like calling [`merge()`](Encoder::merge) for each input in turn, except that with
+	/// [`MergeConflict::LastWins`], conflicts between inputs are resolved before anything is
+	/// copied: entries which a later input replaces are skipped, so their content frames aren't
+	/// stored only to be dropped.
+	#[instrument(level = "debug", skip(self, inputs))]
+	pub fn merge_all<R: OnDemand>(
+		&mut self,
+		inputs: &[Decoder<R>],
+		on_conflict: MergeConflict,
+	) -> Result<()> {
+		for (n, input) in inputs.iter().enumerate() {
+			if on_conflict != MergeConflict::LastWins {
+				self.merge(input, on_conflict)?;
+				continue;
+			}
+
+			let replaced: HashSet<&Pathname> = inputs[(n + 1)..]
+				.iter()
+				.flat_map(Decoder::files)
+				.map(|file| &file.name)
+				.collect();
+			self.merge_with(input, on_conflict, |file| !replaced.contains(&file.name))?;
+		}
+
+		Ok(())
+	}
+
+	/// Merge some of the contents of another Zarc into this one.
+	///
+	/// Like [`merge()`](Encoder::merge), but only file entries for which `filter` returns `true`
+	/// are added, and only the content frames they reference are copied. All editions are still
+	/// added, so their metadata is kept.
+	#[instrument(level = "debug", skip(self, input, filter))]
+	pub fn merge_with<R: OnDemand>(
+		&mut self,
+		input: &Decoder<R>,
+		on_conflict: MergeConflict,
+		mut filter: impl FnMut(&File) -> bool,
+	) -> Result<()> {
+		let mut editions = HashMap::new();
+		for edition in input.editions() {
+			let number = self.import_edition(edition)?;
+			debug!(from=%edition.number, to=%number, "imported edition");
+			editions.insert(edition.number, number);
+		}
+
+		let files: Vec<&File> = input
+			.files()
+			.filter(|file| {
+				let keep = filter(file);
+				if !keep {
+					trace!(name=?file.name, "filtered out");
+				}
+				keep
+			})
+			.collect();
+
+		// existing entries are in the current edition, which is newer than the imported ones, so
+		// the latest merged-in entry for each of their pathnames takes it too, and wins by order
+		let mut newest: HashMap<&Pathname, &File> = HashMap::new();
+		if on_conflict == MergeConflict::KeepBoth {
+			for file in files.iter().copied() {
+				if self.files_by_name.contains_key(&file.name) {
+					newest
+						.entry(&file.name)
+						.and_modify(|newest| {
+							if file.edition >= newest.edition {
+								*newest = file;
+							}
+						})
+						.or_insert(file);
+				}
+			}
+		}
+
+		let mut merged: HashSet<Pathname> = HashSet::new();
+		let mut removed: HashSet<Digest> = HashSet::new();
+		for original in files {
+			if !merged.contains(&original.name) {
+				for old in self.resolve_conflict(original, on_conflict)? {
+					removed.extend(old.frame_digests().cloned());
+				}
+			}
+
+			let mut file = original.clone();
+			file.edition = if newest
+				.get(&file.name)
+				.is_some_and(|newest| std::ptr::eq(*newest, original))
+			{
+				self.edition
+			} else {
+				editions.get(&file.edition).copied().unwrap_or(self.edition)
+			};
+
+			for digest in file.frame_digests() {
+				self.import_frame(input, digest, &editions)?;
+			}
+
+			merged.insert(file.name.clone());
+			self.add_file_entry(file)?;
+		}
+
+		self.drop_unreferenced_frames(removed);
+		Ok(())
+	}
+
+	/// Add an edition from another Zarc as a previous edition of this one.
+	///
+	/// The edition takes the current edition number, and the current edition is bumped to the next
+	/// number. Entries already in the encoder are moved to the new current edition.
+	///
+	/// Returns the number the imported edition now has.
+	fn import_edition(&mut self, edition: &Edition) -> Result<NonZeroU16> {
+		let number = self.edition;
+		let current = number
+			.checked_add(1)
+			.ok_or_else(|| Error::other("too many editions"))?;
+
+		for file in self.files.iter_mut().flatten() {
+			if file.edition == number {
+				file.edition = current;
+			}
+		}
+		for frame in self.frames.values_mut() {
+			if frame.edition == number {
+				frame.edition = current;
+			}
+		}
+
+		// the previous directory can't be recomputed once merged, so the chain is cut
+		self.editions.push(Edition {
+			number,
+			previous_digest: None,
+			..edition.clone()
+		});
+		self.edition = current;
+		Ok(number)
+	}
+
+	/// Copy a frame from another Zarc, along with the frames it depends on.
+	///
+	/// Frames which are external to the input are carried over as references. Frames compressed
+	/// with a dictionary or against a reference frame need that frame too, as do frames with an
+	/// outboard.
+	fn import_frame<R: OnDemand>(
+		&mut self,
+		input: &Decoder<R>,
+		digest: &Digest,
+		editions: &HashMap<NonZeroU16, NonZeroU16>,
+	) -> Result<()> {
+		if self.frames.contains_key(digest) {
+			return Ok(());
+		}
+
+		if let Some(frame) = input.external_frame(digest) {
+			self.import_external_frame(input, frame, editions);
+			return Ok(());
+		}
+
+		let (Some(frame), Some(mut reader)) = (
+			input.frame(digest),
+			input.read_raw_content_frame(digest).map_err(Error::other)?,
+		) else {
+			return Err(Error::other(
+				"cannot merge file entry referencing unknown data frame",
+			));
+		};
+
+		let edition = editions
+			.get(&frame.edition)
+			.copied()
+			.unwrap_or(self.edition);
+		self.copy_raw_frame(frame, edition, &mut reader)?;
+		if self.external_frames.remove(digest).is_some() {
+			trace!("replaced external frame with merged-in frame");
+		}
+
+		for dependency in dependencies(frame) {
+			self.import_frame(input, dependency, editions)?;
+		}
+
+		Ok(())
+	}
+
+	/// Carry over a reference to a frame in an external Zarc, and to that Zarc.
+	fn import_external_frame<R: OnDemand>(
+		&mut self,
+		input: &Decoder<R>,
+		frame: &ExternalFrame,
+		editions: &HashMap<NonZeroU16, NonZeroU16>,
+	) {
+		let renumber = |edition| editions.get(&edition).copied().unwrap_or(self.edition);
+
+		if !self.externals.iter().any(|e| e.digest == frame.external) {
+			if let Some(external) = input.externals().find(|e| e.digest == frame.external) {
+				self.externals.push(External {
+					edition: renumber(external.edition),
+					..external.clone()
+				});
+			}
+		}
+
+		self.external_frames
+			.entry(frame.digest.clone())
+			.or_insert_with(|| ExternalFrame {
+				edition: renumber(frame.edition),
+				..frame.clone()
+			});
+	}
+
+	/// Apply the conflict policy for a file entry about to be merged in.
+	///
+	/// Returns the entries which were removed.
+	fn resolve_conflict(&mut self, file: &File, on_conflict: MergeConflict) -> Result<Vec<File>> {
+		let Some(indices) = self.files_by_name.get(&file.name) else {
+			return Ok(Vec::new());
+		};
+
+		match on_conflict {
+			MergeConflict::KeepBoth => Ok(Vec::new()),
+			MergeConflict::Error => {
+				if file.is_dir()
+					&& indices.iter().all(|index| {
+						self.files
+							.get(*index)
+							.and_then(Option::as_ref)
+							.is_none_or(File::is_dir)
+					}) {
+					Ok(Vec::new())
+				} else {
+					Err(Error::other(format!(
+						"conflicting entries for path {:?}",
+						file.name.to_path()
+					)))
+				}
+			}
+			MergeConflict::LastWins => {
+				let mut removed = Vec::new();
+				for index in self.files_by_name.remove(&file.name).unwrap_or_default() {
+					let Some(old) = self.files.get_mut(index).and_then(Option::take) else {
+						continue;
+					};
+
+					trace!(index, "removed conflicting file entry");
+					for digest in old.digest.iter().chain(old.frame_digests()) {
+						if let Some(indices) = self.files_by_digest.get_mut(digest) {
+							indices.retain(|i| *i != index);
+						}
+					}
+					removed.push(old);
+				}
+				Ok(removed)
+			}
+		}
+	}
+
+	/// Forget frames which were referenced by removed entries, if nothing else references them.
+	///
+	/// The frames stay in the Zarc's data, but aren't listed in the directory. Frames they depend
+	/// on are then forgotten in turn, if nothing else references those either.
+	fn drop_unreferenced_frames(&mut self, mut candidates: HashSet<Digest>) {
+		while !candidates.is_empty() {
+			for file in self.files.iter().flatten() {
+				for digest in file.frame_digests() {
+					candidates.remove(digest);
+				}
+			}
+			for frame in self.frames.values() {
+				for digest in dependencies(frame) {
+					candidates.remove(digest);
+				}
+			}
+			if let Some((digest, _)) = &self.dictionary {
+				candidates.remove(digest);
+			}
+
+			let mut dependencies = HashSet::new();
+			for digest in candidates {
+				let Some(frame) = self.frames.remove(&digest) else {
+					continue;
+				};
+
+				trace!(digest=%format!("{digest:02x?}"), "dropped unreferenced frame");
+				self.solid.forget_frame(&digest);
+				dependencies.extend(self::dependencies(&frame).cloned());
+			}
+			candidates = dependencies;
+		}
+	}
+}
+
+/// The frames a frame needs to be read: its dictionary, reference, and outboard.
+fn dependencies(frame: &Frame) -> impl Iterator<Item = &Digest> {
+	frame
+		.dictionary
+		.iter()
+		.chain(frame.reference.iter())
+		.chain(frame.outboard.iter())
+}
+
+#[test]
+fn last_wins_leaves_no_orphan_frames() {
+	use crate::test_util::{name, open, zarc};
+
+	let inputs = [
+		zarc(&[("a.txt", b"old a"), ("b.txt", b"shared")]),
+		zarc(&[("a.txt", b"new a"), ("c.txt", b"shared")]),
+	];
+
+	let mut one_by_one = Vec::new();
+	let mut zarc = Encoder::new(&mut one_by_one).expect("new encoder");
+	for input in &inputs {
+		zarc.merge(input, MergeConflict::LastWins).expect("merge");
+	}
+	zarc.finalise().expect("finalise");
+
+	let mut all_at_once = Vec::new();
+	let mut zarc = Encoder::new(&mut all_at_once).expect("new encoder");
+	zarc.merge_all(&inputs, MergeConflict::LastWins)
+		.expect("merge");
+	zarc.finalise().expect("finalise");
+
+	// the replaced frame was written before the conflict was known, but isn't listed
+	assert!(all_at_once.len() < one_by_one.len());
+
+	for merged in [one_by_one, all_at_once] {
+		let merged = open(merged);
+
+		assert_eq!(merged.files().count(), 3);
+		assert_eq!(merged.frames().count(), 2, "shared content is stored once");
+
+		let a = merged.latest_file(name("a.txt")).expect("a.txt");
+		assert_eq!(merged.read_at(a, 0, 64).expect("read a.txt"), b"new a");
+		let c = merged.latest_file(name("c.txt")).expect("c.txt");
+		assert_eq!(merged.read_at(c, 0, 64).expect("read c.txt"), b"shared");
+	}
+}
+
+#[test]
+fn keep_both_prefers_merged_in_entries() {
+	use crate::test_util::{name, open, zarc};
+
+	let input = zarc(&[("a.txt", b"merged a")]);
+
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	let digest = zarc.add_data_frame(b"existing a").expect("add frame");
+	let mut file = zarc.build_file(name("a.txt"));
+	file.digest(digest);
+	zarc.add_file_entry(file).expect("add file");
+	zarc.merge(&input, MergeConflict::KeepBoth).expect("merge");
+	zarc.finalise().expect("finalise");
+
+	let merged = open(bytes);
+	assert_eq!(merged.files().count(), 2);
+	let a = merged.latest_file(name("a.txt")).expect("a.txt");
+	assert_eq!(merged.read_at(a, 0, 64).expect("read a.txt"), b"merged a");
+	assert!(
+		merged.files().all(|file| file.edition == a.edition),
+		"the existing entry keeps the current edition"
+	);
+	assert_eq!(merged.editions().count(), 2);
+}
+
+#[test]
+fn merged_frames_replace_external_frames() {
+	use crate::test_util::{name, open, zarc};
+
+	let base = zarc(&[("base.txt", b"shared")]);
+	let input = zarc(&[("b.txt", b"shared")]);
+
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	zarc.add_base(&base, None).expect("add base");
+	zarc.merge(&input, MergeConflict::KeepBoth).expect("merge");
+	zarc.finalise().expect("finalise");
+
+	let merged = open(bytes);
+	let b = merged.latest_file(name("b.txt")).expect("b.txt");
+	let digest = b.digest.as_ref().expect("digest");
+	assert!(merged.frame(digest).is_some());
+	assert!(merged.external_frame(digest).is_none());
+	assert_eq!(merged.read_at(b, 0, 64).expect("read b.txt"), b"shared");
+}
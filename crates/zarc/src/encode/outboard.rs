@@ -0,0 +1,69 @@
+use std::io::{Error, Result, Write};
+
+use tracing::{instrument, trace};
+
+use crate::{
+	directory::Frame,
+	integrity::{Digest, Outboard},
+};
+
+use super::Encoder;
+
+impl<'writer, W: Write> Encoder<'writer, W> {
+	/// Store BLAKE3 outboards for frames larger than this many bytes (uncompressed).
+	///
+	/// An outboard lets the decoder verify parts of a frame as it reads them, instead of only once
+	/// the entire frame has been read; see [`Decoder::read_range()`](crate::decode::Decoder::read_range).
+	/// It costs 32 bytes per 16 KiB of content, stored in its own frame.
+	///
+	/// This will apply to future frames. The default is `None`, which doesn't store outboards.
+	#[instrument(level = "trace", skip(self))]
+	pub fn set_outboard_threshold(&mut self, size: Option<usize>) {
+		self.outboard_threshold = size;
+	}
+
+	/// Write the outboard of some content, if it's large enough.
+	///
+	/// Returns the digest of the outboard's frame.
+	pub(crate) fn add_outboard(&mut self, content: &[u8]) -> Result<Option<Digest>> {
+		let Some(threshold) = self.outboard_threshold else {
+			return Ok(None);
+		};
+		if content.len() <= threshold {
+			return Ok(None);
+		}
+
+		let Some(outboard) = Outboard::compute(content) else {
+			return Ok(None);
+		};
+
+		let outboard = outboard.to_bytes();
+		let digest = Digest(blake3::hash(&outboard).as_bytes().to_vec());
+		if self.frames.contains_key(&digest) {
+			trace!("outboard already exists, skipping");
+			return Ok(Some(digest));
+		}
+
+		// outboards are hashes, so don't compress well
+		let offset = self.offset.try_into().map_err(Error::other)?;
+		let bytes = self.write_uncompressed_frame(&outboard)?;
+		self.offset += bytes;
+		trace!(%bytes, digest=%format!("{digest:02x?}"), "wrote outboard");
+
+		self.frames.insert(
+			digest.clone(),
+			Frame {
+				edition: self.edition,
+				offset,
+				digest: digest.clone(),
+				length: bytes as _,
+				uncompressed: outboard.len() as _,
+				dictionary: None,
+				reference: None,
+				outboard: None,
+			},
+		);
+
+		Ok(Some(digest))
+	}
+}
@@ -0,0 +1,364 @@
+use std::io::{Result, Write};
+
+use tracing::{debug, instrument, trace};
+
+use crate::{
+	directory::{CborString, File},
+	encryption::CONTENT_AAD,
+	map_zstd_error,
+};
+
+use super::{Encoder, ZstdParameter};
+
+/// How to store a content frame.
+///
+/// See [`CompressionPolicy`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum Compression {
+	/// Compress the content with zstd, with the encoder's parameters.
+	#[default]
+	Compress,
+
+	/// Compress the content with zstd, with these parameters on top of the encoder's.
+	///
+	/// For example, `With(vec![ZstdParameter::CompressionLevel(19)])` compresses with a higher
+	/// level, keeping the encoder's other parameters and dictionary.
+	With(Vec<ZstdParameter>),
+
+	/// Store the content uncompressed.
+	Store,
+}
+
+/// Decides how content frames are compressed.
+///
+/// Set one with [`Encoder::set_compression_policy()`]. It's consulted for every new content
+/// frame while compression is [enabled](Encoder::enable_compression), with the file entry the
+/// content is for when it's known (see [`Encoder::add_data_frame_for()`]), so it can vary the
+/// compression level, strategy, etc per file, or store some content uncompressed.
+///
+/// This is implemented for closures, and [`AutoCompression`] is a policy that detects content
+/// which doesn't compress well.
+pub trait CompressionPolicy {
+	/// Decide how to store some content.
+	///
+	/// The `file` has the metadata of the file the content is for, but its digest isn't set yet.
+	fn compression(&mut self, file: Option<&File>, content: &[u8]) -> Compression;
+}
+
+impl<F> CompressionPolicy for F
+where
+	F: FnMut(Option<&File>, &[u8]) -> Compression,
+{
+	fn compression(&mut self, file: Option<&File>, content: &[u8]) -> Compression {
+		self(file, content)
+	}
+}
+
+/// Compression policy which stores content that doesn't compress well.
+///
+/// Rules are checked in this order, and the first that matches decides:
+/// 1. the file's extension is in `compress_extensions`: compress;
+/// 2. the file's extension is in `store_extensions`: store;
+/// 3. the content is smaller than `compress_below`: compress;
+/// 4. the content's detected MIME type (see [`detect_mime_type()`]) matches one of
+///    `store_mime_types`: store;
+/// 5. a sample of the content is compressed at a fast level, and if that saves less than
+///    `min_gain` of its size: store; otherwise compress.
+///
+/// The default has lists of common already-compressed formats: archives, images, audio, video.
+#[derive(Clone, Debug)]
+pub struct AutoCompression {
+	/// Extensions of files to always compress, lowercase and without the dot.
+	pub compress_extensions: Vec<String>,
+
+	/// Extensions of files to always store, lowercase and without the dot.
+	pub store_extensions: Vec<String>,
+
+	/// MIME types of content to always store.
+	///
+	/// These can end with `/*` to match a whole category, like `video/*`.
+	pub store_mime_types: Vec<String>,
+
+	/// Content smaller than this many bytes is always compressed.
+	///
+	/// This is 1 KiB by default.
+	pub compress_below: usize,
+
+	/// How many bytes of content to try compressing.
+	///
+	/// Large content is sampled from its start and middle. This is 64 KiB by default.
+	pub sample_size: usize,
+
+	/// The fraction of the sample's size that compression must save, between 0 and 1.
+	///
+	/// This is 0.05 (5%) by default.
+	pub min_gain: f64,
+}
+
+impl Default for AutoCompression {
+	fn default() -> Self {
+		Self {
+			compress_extensions: Vec::new(),
+			store_extensions: [
+				"7z", "apk", "avif", "br", "bz2", "docx", "flac", "gif", "gz", "heic", "jar",
+				"jpeg", "jpg", "lz4", "m4a", "m4v", "mkv", "mov", "mp3", "mp4", "odt", "ogg",
+				"opus", "png", "pptx", "rar", "tbz2", "tgz", "txz", "webm", "webp", "whl", "woff2",
+				"xlsx", "xz", "zip", "zst",
+			]
+			.map(String::from)
+			.to_vec(),
+			store_mime_types: [
+				"application/gzip",
+				"application/vnd.rar",
+				"application/x-7z-compressed",
+				"application/x-bzip2",
+				"application/x-lz4",
+				"application/x-xz",
+				"application/zip",
+				"application/zstd",
+				"audio/*",
+				"font/woff2",
+				"image/gif",
+				"image/jpeg",
+				"image/png",
+				"image/webp",
+				"video/*",
+			]
+			.map(String::from)
+			.to_vec(),
+			compress_below: 1024,
+			sample_size: 64 * 1024,
+			min_gain: 0.05,
+		}
+	}
+}
+
+impl AutoCompression {
+	fn extension(file: Option<&File>) -> Option<String> {
+		let CborString::Text(name) = file?.name.0.last()? else {
+			return None;
+		};
+		let (_, extension) = name.rsplit_once('.')?;
+		Some(extension.to_ascii_lowercase())
+	}
+
+	fn sample<'c>(&self, content: &'c [u8]) -> Vec<&'c [u8]> {
+		if content.len() <= self.sample_size {
+			return vec![content];
+		}
+
+		let half = self.sample_size / 2;
+		let middle = content.len() / 2;
+		vec![&content[..half], &content[middle..(middle + half)]]
+	}
+}
+
+impl CompressionPolicy for AutoCompression {
+	#[instrument(level = "trace", skip(self, content))]
+	fn compression(&mut self, file: Option<&File>, content: &[u8]) -> Compression {
+		if let Some(extension) = Self::extension(file) {
+			if self.compress_extensions.contains(&extension) {
+				trace!(%extension, "extension is always compressed");
+				return Compression::Compress;
+			}
+
+			if self.store_extensions.contains(&extension) {
+				trace!(%extension, "extension is always stored");
+				return Compression::Store;
+			}
+		}
+
+		if content.len() < self.compress_below {
+			trace!(size=%content.len(), "content is small, compress");
+			return Compression::Compress;
+		}
+
+		if let Some(mime) = detect_mime_type(content) {
+			if self.store_mime_types.iter().any(|pattern| {
+				pattern == mime
+					|| pattern
+						.strip_suffix("/*")
+						.and_then(|category| mime.strip_prefix(category))
+						.is_some_and(|rest| rest.starts_with('/'))
+			}) {
+				trace!(%mime, "content type is always stored");
+				return Compression::Store;
+			}
+		}
+
+		let mut original = 0;
+		let mut compressed = 0;
+		let mut buffer = Vec::with_capacity(zstd_safe::compress_bound(self.sample_size));
+		for sample in self.sample(content) {
+			buffer.clear();
+			let Ok(bytes) = zstd_safe::compress(&mut buffer, sample, 1) else {
+				return Compression::Compress;
+			};
+			original += sample.len();
+			compressed += bytes;
+		}
+
+		let gain = 1.0 - (compressed as f64 / original as f64);
+		if gain < self.min_gain {
+			debug!(%gain, "content doesn't compress well, store");
+			Compression::Store
+		} else {
+			trace!(%gain, "content compresses, compress");
+			Compression::Compress
+		}
+	}
+}
+
+/// Detect the MIME type of some content from its magic number.
+///
+/// This only knows about a few formats which are already compressed, for use in
+/// [`AutoCompression`].
+pub fn detect_mime_type(content: &[u8]) -> Option<&'static str> {
+	const MAGICS: &[(&[u8], &str)] = &[
+		(b"\x1f\x8b", "application/gzip"),
+		(b"\x28\xb5\x2f\xfd", "application/zstd"),
+		(b"\xfd7zXZ\x00", "application/x-xz"),
+		(b"BZh", "application/x-bzip2"),
+		(b"\x04\x22\x4d\x18", "application/x-lz4"),
+		(b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+		(b"Rar!\x1a\x07", "application/vnd.rar"),
+		(b"PK\x03\x04", "application/zip"),
+		(b"\x89PNG\r\n\x1a\n", "image/png"),
+		(b"\xff\xd8\xff", "image/jpeg"),
+		(b"GIF8", "image/gif"),
+		(b"wOF2", "font/woff2"),
+		(b"OggS", "audio/ogg"),
+		(b"fLaC", "audio/flac"),
+		(b"ID3", "audio/mpeg"),
+		(b"\x1a\x45\xdf\xa3", "video/webm"),
+	];
+
+	if let Some((_, mime)) = MAGICS.iter().find(|(magic, _)| content.starts_with(magic)) {
+		return Some(mime);
+	}
+
+	match content.get(..12) {
+		Some([b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P']) => Some("image/webp"),
+		Some([_, _, _, _, b'f', b't', b'y', b'p', ..]) => Some("video/mp4"),
+		_ => None,
+	}
+}
+
+impl<'writer, W: Write> Encoder<'writer, W> {
+	/// Set the policy which decides how to compress content frames.
+	///
+	/// By default, all content is compressed with the encoder's
+	/// [parameters](Encoder::set_zstd_parameter). Frames written while compression is
+	/// [disabled](Encoder::enable_compression) are never compressed, regardless of the policy.
+	///
+	/// For frames compressed against a reference, the policy's parameters are applied on top of
+	/// those the reference needs, see [`add_delta_frame()`](Encoder::add_delta_frame). For solid
+	/// frames, see [`add_solid_file()`](Encoder::add_solid_file).
+	#[instrument(level = "trace", skip(self, policy))]
+	pub fn set_compression_policy(&mut self, policy: impl CompressionPolicy + 'static) {
+		self.policy = Some(Box::new(policy));
+	}
+
+	/// Decide how to store some content.
+	pub(crate) fn compression(&mut self, file: Option<&File>, content: &[u8]) -> Compression {
+		if !self.compress {
+			return Compression::Store;
+		}
+
+		match self.policy.as_mut() {
+			Some(policy) => policy.compression(file, content),
+			None => Compression::Compress,
+		}
+	}
+
+	/// Write a compressed frame with extra parameters.
+	///
+	/// The compression context is kept for the next frame, if it has the same parameters.
+	///
+	/// Returns the amount of bytes written.
+	#[instrument(level = "trace", skip(self, data))]
+	pub(crate) fn write_compressed_frame_with(
+		&mut self,
+		parameters: &[ZstdParameter],
+		data: &[u8],
+	) -> Result<usize> {
+		let zstd = match &mut self.policy_zstd {
+			Some((current, zstd)) if current == parameters => zstd,
+			_ => {
+				trace!("create zstd context for policy parameters");
+				let mut zstd = self.create_zstd_with_parameters()?;
+				if let Some((_, dictionary)) = &self.dictionary {
+					zstd.load_dictionary(dictionary).map_err(map_zstd_error)?;
+				}
+				for parameter in parameters {
+					zstd.set_parameter(*parameter).map_err(map_zstd_error)?;
+				}
+
+				&mut self.policy_zstd.insert((parameters.to_vec(), zstd)).1
+			}
+		};
+
+		let mut buffer: Vec<u8> = Vec::with_capacity(data.len() + 1024.max(data.len() / 10));
+		zstd.compress2(&mut buffer, data).map_err(map_zstd_error)?;
+		self.write_frame(&buffer, CONTENT_AAD)
+	}
+}
+
+#[test]
+fn policy_sees_files_of_all_frame_kinds() {
+	use crate::{
+		integrity::Digest,
+		test_util::{name, open},
+	};
+
+	let content =
+		|seed: u8| -> Vec<u8> { (0..200_000_u32).map(|n| (n % 7) as u8 ^ seed).collect() };
+
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	zarc.set_compression_policy(|file: Option<&File>, _: &[u8]| {
+		if file.is_some_and(|file| file.name.0.len() == 2) {
+			Compression::Store
+		} else {
+			Compression::Compress
+		}
+	});
+
+	let reference = content(1);
+	let reference_digest = zarc.add_data_frame(&reference).expect("add frame");
+
+	let mut file = zarc.build_file(name("stored/chunked.bin"));
+	let (digest, chunks) = zarc
+		.add_chunked_data_for(&file.0, &content(2))
+		.expect("add chunks");
+	file.chunks(digest, chunks.clone());
+	zarc.add_file_entry(file).expect("add file");
+
+	let mut file = zarc.build_file(name("stored/delta.bin"));
+	let delta = zarc
+		.add_delta_frame_for(&file.0, &content(3), &reference_digest, &reference)
+		.expect("add delta");
+	file.digest(delta.clone());
+	zarc.add_file_entry(file).expect("add file");
+
+	let file = zarc.build_file(name("stored/solid.txt"));
+	zarc.add_solid_file(file, &content(4)[..1000])
+		.expect("add solid");
+	zarc.finalise().expect("finalise");
+
+	let zarc = open(bytes);
+
+	let stored = |digest: &Digest| {
+		let frame = zarc.frame(digest).expect("frame");
+		frame.length >= frame.uncompressed && frame.reference.is_none()
+	};
+	assert!(!stored(&reference_digest));
+	assert!(chunks.iter().all(stored));
+	assert!(stored(&delta));
+
+	let solid = zarc
+		.latest_file(name("stored/solid.txt"))
+		.expect("solid.txt");
+	assert!(solid.solid.is_none(), "stored content gets its own frame");
+	assert!(stored(solid.digest.as_ref().expect("digest")));
+}
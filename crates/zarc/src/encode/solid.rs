@@ -0,0 +1,246 @@
+use std::{
+	collections::HashMap,
+	io::{Error, Result, Write},
+	mem::take,
+};
+
+use tracing::{debug, instrument, trace};
+
+use crate::{
+	directory::{File, SolidSlice},
+	integrity::Digest,
+};
+
+use super::{Compression, Encoder};
+
+/// State of the solid frame being built.
+#[derive(Debug)]
+pub(crate) struct SolidBlock {
+	/// Target size of solid frames.
+	size: usize,
+
+	/// Content of the current block.
+	buffer: Vec<u8>,
+
+	/// Indices of file entries in the current block, which don't yet have the frame digest.
+	pending: Vec<usize>,
+
+	/// Content in the current block, by digest: offset and length.
+	contents: HashMap<Digest, (u64, u64)>,
+
+	/// Content in solid frames already written, by digest.
+	written: HashMap<Digest, SolidSlice>,
+}
+
+impl Default for SolidBlock {
+	fn default() -> Self {
+		Self {
+			size: 1024 * 1024,
+			buffer: Vec::new(),
+			pending: Vec::new(),
+			contents: HashMap::new(),
+			written: HashMap::new(),
+		}
+	}
+}
+
+impl SolidBlock {
+	/// Record content which is in a solid frame already written.
+	pub(crate) fn remember(&mut self, digest: Digest, slice: SolidSlice) {
+		self.written.entry(digest).or_insert(slice);
+	}
+
+	/// Forget the content of a solid frame which was dropped, so it isn't referenced again.
+	pub(crate) fn forget_frame(&mut self, frame: &Digest) {
+		self.written.retain(|_, slice| &slice.frame != frame);
+	}
+}
+
+impl<'writer, W: Write> Encoder<'writer, W> {
+	/// Set the target size of solid frames, in bytes.
+	///
+	/// This will apply to future calls to [`add_solid_file()`](Encoder::add_solid_file). Larger
+	/// frames compress better, but reading a file from a solid frame requires decompressing the
+	/// frame up to the end of the file, so smaller frames make random access faster.
+	///
+	/// The default is 1 MiB.
+	#[instrument(level = "trace", skip(self))]
+	pub fn set_solid_block_size(&mut self, size: usize) -> Result<()> {
+		if size == 0 {
+			return Err(Error::other("solid block size cannot be zero"));
+		}
+
+		self.solid.size = size;
+		Ok(())
+	}
+
+	/// Whether content of this size goes in a solid frame.
+	///
+	/// Content which doesn't is stored in its own frame by
+	/// [`add_solid_file()`](Encoder::add_solid_file), so it may be better added another way, like
+	/// with [`add_chunked_data()`](Encoder::add_chunked_data).
+	pub fn fits_solid_block(&self, size: usize) -> bool {
+		size < self.solid.size
+	}
+
+	/// Add a file entry with its content, packed in a solid frame.
+	///
+	/// Solid frames hold the content of many small files concatenated, which compresses much
+	/// better than a frame per file. The file entry references its slice of the frame, and gets
+	/// the digest of its content. Don't set the digest yourself, it will be overwritten.
+	///
+	/// Content is buffered until there's enough to fill a [block](Encoder::set_solid_block_size),
+	/// and the frame is written then, or when the encoder is finalised. Content that's at least
+	/// the size of a block is instead stored in its own frame, as with
+	/// [`add_data_frame()`](Encoder::add_data_frame).
+	///
+	/// The [compression policy](Encoder::set_compression_policy) is consulted with the entry:
+	/// content it wants stored, or compressed with other parameters, goes in its own frame too.
+	/// Solid frames themselves are given to the policy without a file entry.
+	///
+	/// If the content is already in the Zarc, either in a frame of its own or in a solid frame, it
+	/// isn't stored again.
+	#[instrument(level = "trace", skip(self, content))]
+	pub fn add_solid_file(
+		&mut self,
+		entry: impl Into<File> + std::fmt::Debug,
+		content: &[u8],
+	) -> Result<()> {
+		let mut entry = entry.into();
+		entry.chunks = None;
+		entry.solid = None;
+
+		if !self.fits_solid_block(content.len()) {
+			trace!("content is larger than a block, use own frame");
+			entry.digest = Some(self.add_data_frame_for(&entry, content)?);
+			return self.add_file_entry(entry);
+		}
+
+		let digest = blake3::hash(content);
+		let digest = Digest(digest.as_bytes().to_vec());
+		trace!(uncompressed_size=%content.len(), digest=%format!("{digest:02x?}"), "computed digest");
+		entry.digest = Some(digest.clone());
+
+		if self.frames.contains_key(&digest) || self.external_frames.contains_key(&digest) {
+			trace!("content already exists in a frame, skipping");
+			return self.add_file_entry(entry);
+		}
+
+		if let Some(slice) = self.solid.written.get(&digest) {
+			trace!("content already exists in a solid frame, skipping");
+			entry.solid = Some(slice.clone());
+			return self.add_file_entry(entry);
+		}
+
+		let (offset, length) = if let Some(existing) = self.solid.contents.get(&digest) {
+			trace!("content already exists in the current block, skipping");
+			*existing
+		} else {
+			// the block is compressed as a whole, so content the policy wants compressed
+			// differently gets its own frame
+			if self.compress && self.policy.is_some() {
+				let compression = self.compression(Some(&entry), content);
+				if compression != Compression::Compress {
+					trace!(?compression, "policy decided otherwise, use own frame");
+					self.write_content_frame(digest, content, compression)?;
+					return self.add_file_entry(entry);
+				}
+			}
+
+			if self.solid.buffer.len() + content.len() > self.solid.size {
+				self.flush_solid()?;
+			}
+
+			let offset = self.solid.buffer.len() as u64;
+			let length = content.len() as u64;
+			self.solid.buffer.extend_from_slice(content);
+			self.solid.contents.insert(digest.clone(), (offset, length));
+			(offset, length)
+		};
+
+		// the frame digest gets filled in when the block is written
+		entry.solid = Some(SolidSlice {
+			frame: Digest(Vec::new()),
+			offset,
+			length,
+		});
+		let index = self.push_file_entry(entry);
+		self.solid.pending.push(index);
+
+		Ok(())
+	}
+
+	/// Write the current solid frame, if there's anything in it.
+	#[instrument(level = "trace", skip(self))]
+	pub(crate) fn flush_solid(&mut self) -> Result<()> {
+		if self.solid.pending.is_empty() {
+			return Ok(());
+		}
+
+		let buffer = take(&mut self.solid.buffer);
+		let frame = self.add_data_frame(&buffer)?;
+		debug!(bytes=%buffer.len(), files=%self.solid.pending.len(), "wrote solid frame");
+
+		for index in take(&mut self.solid.pending) {
+			// the entry might have been removed in the meantime
+			if let Some(solid) = self
+				.files
+				.get_mut(index)
+				.and_then(Option::as_mut)
+				.and_then(|file| file.solid.as_mut())
+			{
+				solid.frame = frame.clone();
+				self.files_by_digest
+					.entry(frame.clone())
+					.or_default()
+					.push(index);
+			}
+		}
+
+		for (digest, (offset, length)) in take(&mut self.solid.contents) {
+			self.solid.written.insert(
+				digest,
+				SolidSlice {
+					frame: frame.clone(),
+					offset,
+					length,
+				},
+			);
+		}
+
+		Ok(())
+	}
+}
+
+#[test]
+fn append_reuses_solid_content() {
+	use crate::test_util::{name, open};
+
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	let file = zarc.build_file(name("a.txt"));
+	zarc.add_solid_file(file, b"small content")
+		.expect("add solid");
+	let file = zarc.build_file(name("c.txt"));
+	zarc.add_solid_file(file, b"other content")
+		.expect("add solid");
+	zarc.finalise().expect("finalise");
+	let existing = open(bytes.clone());
+
+	bytes.truncate(usize::try_from(existing.trailer().directory_offset).expect("offset"));
+	let mut zarc = Encoder::append(&mut bytes, &existing).expect("append");
+	let file = zarc.build_file(name("b.txt"));
+	zarc.add_solid_file(file, b"small content")
+		.expect("add solid");
+	zarc.finalise().expect("finalise");
+
+	let appended = open(bytes);
+	assert_eq!(appended.frames().count(), 1, "content is stored once");
+	let a = appended.latest_file(name("a.txt")).expect("a.txt");
+	let b = appended.latest_file(name("b.txt")).expect("b.txt");
+	assert_eq!(a.solid, b.solid);
+	assert_eq!(
+		appended.read_at(b, 0, 64).expect("read b.txt"),
+		b"small content"
+	);
+}
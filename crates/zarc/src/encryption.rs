@@ -0,0 +1,398 @@
+//! Zarc encryption keys and the Keys frame.
+//!
+//! An encrypted Zarc has a random _content key_, which encrypts every content frame and the
+//! directory with XChaCha20-Poly1305. The content key is itself encrypted ("wrapped") with one or
+//! more [`EncryptionKey`]s, and the resulting [`KeyStanza`]s are stored in a Skippable frame right
+//! after the [header][crate::header]. Any one of the encryption keys can then open the Zarc.
+//!
+//! Encrypted frames are stored as Skippable frames with the [`ENCRYPTED_FRAME_NIBBLE`], containing
+//! a random nonce prefix followed by the Zstandard frame sealed in segments of 64 KiB, each with its
+//! own tag. Frames are thus decrypted a segment at a time as they're read, and never need to be held
+//! in memory entirely.
+
+use std::{
+	error::Error,
+	fmt,
+	io::{self, Read},
+};
+
+use chacha20poly1305::{
+	aead::{Aead, KeyInit, Payload},
+	XChaCha20Poly1305, XNonce,
+};
+use minicbor::{Decode, Encode};
+
+/// Magic nibble of the Skippable frame containing the [`KeyStanza`]s.
+pub const KEYS_FRAME_NIBBLE: u8 = 0x1;
+
+/// Magic nibble of the Skippable frames containing encrypted Zstandard frames.
+pub const ENCRYPTED_FRAME_NIBBLE: u8 = 0xE;
+
+const NONCE_LENGTH: usize = 24;
+
+/// Length of the random part of segment nonces, the rest is the segment counter and last flag.
+const NONCE_PREFIX_LENGTH: usize = 19;
+
+/// Length of the authentication tag of each segment.
+const TAG_LENGTH: usize = 16;
+
+/// Plaintext length of the segments of encrypted frames.
+const SEGMENT_LENGTH: usize = 64 * 1024;
+
+/// Associated data for a key stanza.
+const STANZA_AAD: &[u8] = b"zarc key stanza";
+
+/// Associated data for a content frame.
+pub(crate) const CONTENT_AAD: &[u8] = b"zarc content frame";
+
+/// Associated data for the directory frame.
+pub(crate) const DIRECTORY_AAD: &[u8] = b"zarc directory frame";
+
+/// A key that can open an encrypted Zarc.
+///
+/// This is a 32-byte symmetric key. Keep it secret!
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl fmt::Debug for EncryptionKey {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("EncryptionKey").field(&"secret").finish()
+	}
+}
+
+impl EncryptionKey {
+	/// Generate a new random key.
+	pub fn generate() -> io::Result<Self> {
+		random().map(Self)
+	}
+
+	/// Use some bytes as a key.
+	pub fn from_bytes(bytes: [u8; 32]) -> Self {
+		Self(bytes)
+	}
+
+	/// Get the key's bytes.
+	pub fn as_bytes(&self) -> &[u8; 32] {
+		&self.0
+	}
+}
+
+/// Zarc Key Stanza
+///
+/// The content key of an encrypted Zarc, wrapped with one [`EncryptionKey`].
+///
+/// [Spec](https://github.com/passcod/zarc/blob/main/SPEC.md#zarc-keys)
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+#[cbor(map)]
+pub struct KeyStanza {
+	/// Encryption algorithm.
+	#[n(0)]
+	pub scheme: EncryptionScheme,
+
+	/// Nonce and encrypted content key.
+	#[n(1)]
+	#[cbor(with = "minicbor::bytes")]
+	pub wrapped_key: Vec<u8>,
+}
+
+/// Available encryption algorithms.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Encode, Decode)]
+#[cbor(index_only)]
+pub enum EncryptionScheme {
+	/// XChaCha20-Poly1305, with 24-byte random nonces.
+	#[n(1)]
+	XChaCha20Poly1305 = 1,
+}
+
+/// The content key of an encrypted Zarc.
+#[derive(Clone)]
+pub(crate) struct ContentCipher([u8; 32]);
+
+impl fmt::Debug for ContentCipher {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("ContentCipher").field(&"secret").finish()
+	}
+}
+
+impl ContentCipher {
+	/// Generate a new random content key.
+	pub fn generate() -> io::Result<Self> {
+		random().map(Self)
+	}
+
+	/// Wrap the content key with an encryption key.
+	pub fn wrap(&self, key: &EncryptionKey) -> io::Result<KeyStanza> {
+		Ok(KeyStanza {
+			scheme: EncryptionScheme::XChaCha20Poly1305,
+			wrapped_key: seal(&key.0, STANZA_AAD, &self.0)?,
+		})
+	}
+
+	/// Unwrap the content key from a stanza, if the encryption key opens it.
+	pub fn unwrap_from(stanza: &KeyStanza, key: &EncryptionKey) -> Option<Self> {
+		match stanza.scheme {
+			EncryptionScheme::XChaCha20Poly1305 => open(&key.0, STANZA_AAD, &stanza.wrapped_key)
+				.and_then(|bytes| bytes.try_into().ok())
+				.map(Self),
+		}
+	}
+
+	/// Encrypt a frame.
+	///
+	/// Returns the nonce prefix followed by the sealed segments.
+	pub fn seal(&self, aad: &[u8], frame: &[u8]) -> io::Result<Vec<u8>> {
+		let mut prefix = [0; NONCE_PREFIX_LENGTH];
+		getrandom::getrandom(&mut prefix).map_err(io::Error::other)?;
+
+		// an empty frame still has one (empty) segment, so it can't be truncated away
+		let segments = frame.len().div_ceil(SEGMENT_LENGTH).max(1);
+		let mut sealed =
+			Vec::with_capacity(NONCE_PREFIX_LENGTH + frame.len() + segments * TAG_LENGTH);
+		sealed.extend_from_slice(&prefix);
+
+		let cipher = XChaCha20Poly1305::new((&self.0).into());
+		for index in 0..segments {
+			let start = index * SEGMENT_LENGTH;
+			let msg = &frame[start..(start + SEGMENT_LENGTH).min(frame.len())];
+			let index = u32::try_from(index).map_err(|_| io::Error::other("frame is too large"))?;
+			let nonce = segment_nonce(&prefix, index, index as usize + 1 == segments);
+			let ciphertext = cipher
+				.encrypt(XNonce::from_slice(&nonce), Payload { msg, aad })
+				.map_err(|_| io::Error::other("encryption failed"))?;
+			sealed.extend(ciphertext);
+		}
+
+		Ok(sealed)
+	}
+
+	/// Decrypt a frame as it's read.
+	///
+	/// The `reader` must yield the `length` bytes of the frame as sealed, without the Skippable
+	/// frame header. This reads the nonce prefix; segments are then read and decrypted as needed.
+	pub fn decryptor<R: Read>(
+		&self,
+		aad: &'static [u8],
+		mut reader: R,
+		length: u64,
+	) -> io::Result<Decryptor<R>> {
+		let sealed = length
+			.checked_sub((NONCE_PREFIX_LENGTH + TAG_LENGTH) as u64)
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, DecryptionFailed))?
+			+ TAG_LENGTH as u64;
+
+		// every segment, including the last, must at least have its tag
+		let last = sealed % (SEGMENT_LENGTH + TAG_LENGTH) as u64;
+		if last != 0 && last < TAG_LENGTH as u64 {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, DecryptionFailed));
+		}
+
+		let mut prefix = [0; NONCE_PREFIX_LENGTH];
+		reader.read_exact(&mut prefix)?;
+
+		let segments = sealed.div_ceil((SEGMENT_LENGTH + TAG_LENGTH) as u64);
+		Ok(Decryptor {
+			cipher: XChaCha20Poly1305::new((&self.0).into()),
+			aad,
+			reader,
+			prefix,
+			plaintext_length: sealed - segments * TAG_LENGTH as u64,
+			unread: sealed,
+			index: 0,
+			sealed: Vec::new(),
+			segment: Vec::new(),
+			position: 0,
+		})
+	}
+}
+
+/// Reader of an encrypted frame, decrypting it a segment at a time.
+///
+/// Each segment's tag is checked before any of it is returned. Reads fail with an
+/// [`InvalidData`](io::ErrorKind::InvalidData) error wrapping [`DecryptionFailed`] if a segment was
+/// tampered with, or if the frame was truncated.
+pub(crate) struct Decryptor<R> {
+	cipher: XChaCha20Poly1305,
+	aad: &'static [u8],
+	reader: R,
+	prefix: [u8; NONCE_PREFIX_LENGTH],
+	plaintext_length: u64,
+
+	/// Sealed bytes not yet read.
+	unread: u64,
+
+	/// Index of the next segment.
+	index: u32,
+
+	/// Buffer for the sealed segment.
+	sealed: Vec<u8>,
+
+	/// The current decrypted segment, and how much of it has been returned.
+	segment: Vec<u8>,
+	position: usize,
+}
+
+impl<R: fmt::Debug> fmt::Debug for Decryptor<R> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("Decryptor")
+			.field("cipher", &"secret")
+			.field("reader", &self.reader)
+			.field("plaintext_length", &self.plaintext_length)
+			.field("unread", &self.unread)
+			.field("index", &self.index)
+			.finish_non_exhaustive()
+	}
+}
+
+impl<R: Read> Decryptor<R> {
+	/// Length of the decrypted frame.
+	pub fn plaintext_length(&self) -> u64 {
+		self.plaintext_length
+	}
+
+	/// Read and decrypt the next segment.
+	fn next_segment(&mut self) -> io::Result<()> {
+		let length = self.unread.min((SEGMENT_LENGTH + TAG_LENGTH) as u64) as usize;
+		self.sealed.resize(length, 0);
+		self.reader.read_exact(&mut self.sealed)?;
+		self.unread -= length as u64;
+
+		let nonce = segment_nonce(&self.prefix, self.index, self.unread == 0);
+		self.segment = self
+			.cipher
+			.decrypt(
+				XNonce::from_slice(&nonce),
+				Payload {
+					msg: &self.sealed,
+					aad: self.aad,
+				},
+			)
+			.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, DecryptionFailed))?;
+		self.position = 0;
+		self.index = self
+			.index
+			.checked_add(1)
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, DecryptionFailed))?;
+		Ok(())
+	}
+}
+
+impl<R: Read> Read for Decryptor<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.position == self.segment.len() {
+			if self.unread == 0 {
+				return Ok(0);
+			}
+
+			self.next_segment()?;
+		}
+
+		let available = &self.segment[self.position..];
+		let length = available.len().min(buf.len());
+		buf[..length].copy_from_slice(&available[..length]);
+		self.position += length;
+		Ok(length)
+	}
+}
+
+/// A segment of an encrypted frame failed to decrypt.
+///
+/// The ciphertext or associated data were tampered with, or the frame was truncated.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DecryptionFailed;
+
+impl fmt::Display for DecryptionFailed {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("decryption failed")
+	}
+}
+
+impl Error for DecryptionFailed {}
+
+/// Nonce of a segment: the frame's random prefix, the segment index, and whether it's the last.
+fn segment_nonce(prefix: &[u8; NONCE_PREFIX_LENGTH], index: u32, last: bool) -> [u8; NONCE_LENGTH] {
+	let mut nonce = [0; NONCE_LENGTH];
+	nonce[..NONCE_PREFIX_LENGTH].copy_from_slice(prefix);
+	nonce[NONCE_PREFIX_LENGTH..(NONCE_LENGTH - 1)].copy_from_slice(&index.to_be_bytes());
+	nonce[NONCE_LENGTH - 1] = last.into();
+	nonce
+}
+
+fn random() -> io::Result<[u8; 32]> {
+	let mut bytes = [0; 32];
+	getrandom::getrandom(&mut bytes).map_err(io::Error::other)?;
+	Ok(bytes)
+}
+
+fn seal(key: &[u8; 32], aad: &[u8], msg: &[u8]) -> io::Result<Vec<u8>> {
+	let mut nonce = [0; NONCE_LENGTH];
+	getrandom::getrandom(&mut nonce).map_err(io::Error::other)?;
+
+	let ciphertext = XChaCha20Poly1305::new(key.into())
+		.encrypt(XNonce::from_slice(&nonce), Payload { msg, aad })
+		.map_err(|_| io::Error::other("encryption failed"))?;
+
+	let mut sealed = Vec::with_capacity(NONCE_LENGTH + ciphertext.len());
+	sealed.extend_from_slice(&nonce);
+	sealed.extend(ciphertext);
+	Ok(sealed)
+}
+
+fn open(key: &[u8; 32], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+	if sealed.len() < NONCE_LENGTH {
+		return None;
+	}
+
+	let (nonce, msg) = sealed.split_at(NONCE_LENGTH);
+	XChaCha20Poly1305::new(key.into())
+		.decrypt(XNonce::from_slice(nonce), Payload { msg, aad })
+		.ok()
+}
+
+#[test]
+fn frames_decrypt_by_segment() {
+	use std::io::Cursor;
+
+	let cipher = ContentCipher::generate().expect("generate");
+	let decrypt = |sealed: &[u8]| -> io::Result<Vec<u8>> {
+		let mut decryptor =
+			cipher.decryptor(CONTENT_AAD, Cursor::new(sealed), sealed.len() as u64)?;
+		let length = decryptor.plaintext_length();
+		let mut plaintext = Vec::new();
+		decryptor.read_to_end(&mut plaintext)?;
+		assert_eq!(plaintext.len() as u64, length);
+		Ok(plaintext)
+	};
+
+	for length in [
+		0,
+		1,
+		SEGMENT_LENGTH,
+		SEGMENT_LENGTH + 1,
+		3 * SEGMENT_LENGTH + 5,
+	] {
+		let frame: Vec<u8> = (0..length).map(|n| n as u8).collect();
+		let sealed = cipher.seal(CONTENT_AAD, &frame).expect("seal");
+		assert_eq!(decrypt(&sealed).expect("decrypt"), frame, "length {length}");
+	}
+
+	let frame = vec![42; 2 * SEGMENT_LENGTH + 100];
+	let sealed = cipher.seal(CONTENT_AAD, &frame).expect("seal");
+
+	let mut tampered = sealed.clone();
+	tampered[NONCE_PREFIX_LENGTH + SEGMENT_LENGTH + TAG_LENGTH + 1] ^= 1;
+	let err = decrypt(&tampered).expect_err("tampered segment");
+	assert!(err
+		.get_ref()
+		.is_some_and(|inner| inner.is::<DecryptionFailed>()));
+
+	let truncated = &sealed[..NONCE_PREFIX_LENGTH + 2 * (SEGMENT_LENGTH + TAG_LENGTH)];
+	let err = decrypt(truncated).expect_err("truncated frame");
+	assert!(err
+		.get_ref()
+		.is_some_and(|inner| inner.is::<DecryptionFailed>()));
+
+	let mut decryptor = cipher
+		.decryptor(DIRECTORY_AAD, Cursor::new(&sealed), sealed.len() as u64)
+		.expect("decryptor");
+	assert!(decryptor.read_to_end(&mut Vec::new()).is_err(), "wrong aad");
+}
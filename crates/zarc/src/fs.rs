@@ -0,0 +1,527 @@
+//! Read-only filesystem view of a Zarc.
+//!
+//! [`ZarcFs`] gives access to the files in a Zarc by path, like [`std::fs`] does for the local
+//! filesystem: stat a path, list a directory, open a file. Nothing is extracted, content is read
+//! from the Zarc as needed.
+//!
+//! Paths are resolved against the current view of the Zarc: for each name, the entry from the
+//! latest edition is used, and deleted files don't exist, as with
+//! [`Decoder::latest_file()`]. Directories exist if they have an entry, or implicitly if any file
+//! is under them. Links are followed within the Zarc.
+
+use std::{
+	collections::VecDeque,
+	io::{Error, ErrorKind, Read, Result},
+	path::{Component, Path},
+};
+
+use crate::{
+	decode::{Decoder, FileHandle},
+	directory::{CborString, File, Pathname, Timestamp},
+	ondemand::OnDemand,
+};
+
+/// How many links are followed when resolving a path, before giving up.
+const MAX_LINKS: usize = 40;
+
+/// Read-only filesystem view of a Zarc.
+///
+/// The decoder must have had its directory read. If it's a thin Zarc, its bases must have been
+/// added to read content from them.
+///
+/// Errors are I/O errors, with the same kinds as [`std::fs`] would return, like
+/// [`NotFound`](ErrorKind::NotFound) or [`NotADirectory`](ErrorKind::NotADirectory). Errors from
+/// the decoder are returned as I/O errors wrapping the decoder error.
+#[derive(Debug)]
+pub struct ZarcFs<'decoder, R> {
+	decoder: &'decoder Decoder<R>,
+}
+
+impl<R> Clone for ZarcFs<'_, R> {
+	fn clone(&self) -> Self {
+		*self
+	}
+}
+
+impl<R> Copy for ZarcFs<'_, R> {}
+
+/// What a path leads to.
+struct Resolved<'decoder> {
+	/// The path, with links resolved.
+	name: Pathname,
+
+	/// The entry at that path, or `None` for the root and implicit directories.
+	entry: Option<&'decoder File>,
+}
+
+/// A step in resolving a path.
+enum Step {
+	Parent,
+	Name(CborString),
+}
+
+impl<'decoder, R> ZarcFs<'decoder, R> {
+	/// Make a filesystem view of a Zarc.
+	pub fn new(decoder: &'decoder Decoder<R>) -> Self {
+		Self { decoder }
+	}
+
+	/// Get the metadata of a path, following links.
+	pub fn metadata(&self, path: impl AsRef<Path>) -> Result<Metadata<'decoder>> {
+		let resolved = self.resolve(path.as_ref(), true)?;
+		self.metadata_of(resolved.entry)
+	}
+
+	/// Get the metadata of a path, without following a symlink at the end of the path.
+	pub fn symlink_metadata(&self, path: impl AsRef<Path>) -> Result<Metadata<'decoder>> {
+		let resolved = self.resolve(path.as_ref(), false)?;
+		self.metadata_of(resolved.entry)
+	}
+
+	/// Whether a path exists, following links.
+	pub fn exists(&self, path: impl AsRef<Path>) -> bool {
+		self.resolve(path.as_ref(), true).is_ok()
+	}
+
+	/// Resolve a path to its pathname in the Zarc, following links.
+	pub fn canonicalize(&self, path: impl AsRef<Path>) -> Result<Pathname> {
+		self.resolve(path.as_ref(), true)
+			.map(|resolved| resolved.name)
+	}
+
+	/// List the contents of a directory, following links.
+	///
+	/// This includes the implicit directories under it, which don't have entries of their own.
+	/// Entries are sorted by name.
+	pub fn read_dir(&self, path: impl AsRef<Path>) -> Result<Vec<DirEntry<'decoder>>> {
+		let dir = self.resolve(path.as_ref(), true)?;
+		if dir.entry.is_some_and(|entry| !entry.is_dir()) {
+			return Err(Error::new(ErrorKind::NotADirectory, "not a directory"));
+		}
+
+		let depth = dir.name.0.len();
+		let mut entries: Vec<DirEntry<'decoder>> = Vec::new();
+		for file in self.decoder.latest_files_under(dir.name.clone()) {
+			let name = &file.name.0[depth];
+			if entries
+				.last()
+				.is_some_and(|entry| entry.file_name() == name)
+			{
+				// a file under a directory which is already listed
+				continue;
+			}
+
+			// entries for a name come before the entries under it, so if there's none for the
+			// name, it's an implicit directory
+			let entry = (file.name.0.len() == depth + 1).then_some(file);
+			entries.push(DirEntry {
+				path: Pathname(file.name.0[..=depth].to_vec()),
+				file_type: entry.map_or(FileType::Dir, FileType::of),
+				entry,
+			});
+		}
+
+		Ok(entries)
+	}
+
+	/// Resolve a path, step by step.
+	///
+	/// Links are replaced by their target, which is then resolved in turn. The last component is
+	/// only resolved if it's not a symlink, or if `follow` is true; hardlinks are always resolved.
+	fn resolve(&self, path: &Path, follow: bool) -> Result<Resolved<'decoder>> {
+		let mut steps = VecDeque::new();
+		for component in path.components() {
+			match component {
+				Component::Prefix(_) => {
+					return Err(Error::new(ErrorKind::NotFound, "path has a prefix"));
+				}
+				Component::RootDir => steps.clear(),
+				Component::CurDir => {}
+				Component::ParentDir => steps.push_back(Step::Parent),
+				Component::Normal(name) => steps.push_back(Step::Name(name.into())),
+			}
+		}
+
+		let mut name = Vec::new();
+		let mut entry: Option<&File> = None;
+		let mut links = 0;
+		while let Some(step) = steps.pop_front() {
+			if entry.is_some_and(|entry| !entry.is_dir()) {
+				return Err(Error::new(ErrorKind::NotADirectory, "not a directory"));
+			}
+
+			match step {
+				Step::Parent => {
+					name.pop();
+					entry = self.decoder.latest_file(Pathname(name.clone()));
+				}
+				Step::Name(component) => {
+					name.push(component);
+					let pathname = Pathname(name.clone());
+					entry = self.decoder.latest_file(pathname.clone());
+
+					let Some(file) = entry else {
+						if self.decoder.latest_files_under(pathname).next().is_none() {
+							return Err(Error::new(
+								ErrorKind::NotFound,
+								"no such file or directory",
+							));
+						}
+
+						continue;
+					};
+
+					if file.is_hardlink() || (file.is_symlink() && (follow || !steps.is_empty())) {
+						links += 1;
+						if links > MAX_LINKS {
+							return Err(Error::other("too many levels of links"));
+						}
+
+						let target = file.link_target_pathname().ok_or_else(|| {
+							Error::new(ErrorKind::NotFound, "link target is outside the zarc")
+						})?;
+						for component in target.0.into_iter().rev() {
+							steps.push_front(Step::Name(component));
+						}
+
+						name.clear();
+						entry = None;
+					}
+				}
+			}
+		}
+
+		Ok(Resolved {
+			name: Pathname(name),
+			entry,
+		})
+	}
+
+	fn metadata_of(&self, entry: Option<&'decoder File>) -> Result<Metadata<'decoder>> {
+		let Some(file) = entry else {
+			return Ok(Metadata {
+				file_type: FileType::Dir,
+				len: 0,
+				entry: None,
+			});
+		};
+
+		let file_type = FileType::of(file);
+		let len = if file_type == FileType::File {
+			self.decoder
+				.segments(file)
+				.map_err(Error::other)?
+				.and_then(|segments| segments.last().map(|last| last.start + last.length))
+				.unwrap_or(0)
+		} else {
+			0
+		};
+
+		Ok(Metadata {
+			file_type,
+			len,
+			entry: Some(file),
+		})
+	}
+}
+
+impl<'decoder, R: OnDemand> ZarcFs<'decoder, R> {
+	/// Open a file for reading, following links.
+	///
+	/// The handle implements [`Read`] and [`Seek`](std::io::Seek), see
+	/// [`Decoder::open_file()`].
+	pub fn open(&self, path: impl AsRef<Path>) -> Result<FileHandle<'decoder, R>> {
+		let resolved = self.resolve(path.as_ref(), true)?;
+		let Some(file) = resolved.entry.filter(|file| !file.is_dir()) else {
+			return Err(Error::new(ErrorKind::IsADirectory, "is a directory"));
+		};
+
+		let segments = self.decoder.segments(file).map_err(Error::other)?;
+		Ok(self.decoder.file_handle(segments.unwrap_or_default()))
+	}
+
+	/// Read the entire content of a file, following links.
+	pub fn read(&self, path: impl AsRef<Path>) -> Result<Vec<u8>> {
+		let mut file = self.open(path)?;
+		let mut content = Vec::with_capacity(file.size() as _);
+		file.read_to_end(&mut content)?;
+		Ok(content)
+	}
+}
+
+/// The type of a file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileType {
+	/// A regular file.
+	File,
+
+	/// A directory.
+	Dir,
+
+	/// A symlink.
+	Symlink,
+}
+
+impl FileType {
+	fn of(file: &File) -> Self {
+		if file.is_dir() {
+			Self::Dir
+		} else if file.is_symlink() {
+			Self::Symlink
+		} else {
+			Self::File
+		}
+	}
+}
+
+/// Metadata about a file.
+///
+/// This is returned by [`ZarcFs::metadata()`] and [`ZarcFs::symlink_metadata()`].
+#[derive(Clone, Debug)]
+pub struct Metadata<'decoder> {
+	file_type: FileType,
+	len: u64,
+	entry: Option<&'decoder File>,
+}
+
+impl<'decoder> Metadata<'decoder> {
+	/// The type of the file.
+	pub fn file_type(&self) -> FileType {
+		self.file_type
+	}
+
+	/// Whether this is a directory.
+	pub fn is_dir(&self) -> bool {
+		self.file_type == FileType::Dir
+	}
+
+	/// Whether this is a regular file.
+	pub fn is_file(&self) -> bool {
+		self.file_type == FileType::File
+	}
+
+	/// Whether this is a symlink.
+	pub fn is_symlink(&self) -> bool {
+		self.file_type == FileType::Symlink
+	}
+
+	/// The size of the file's content in bytes.
+	///
+	/// This is zero for directories and symlinks.
+	pub fn len(&self) -> u64 {
+		self.len
+	}
+
+	/// Whether the file has no content.
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// The POSIX mode of the file, if it was stored.
+	pub fn mode(&self) -> Option<u32> {
+		self.entry.and_then(|file| file.mode)
+	}
+
+	/// The last modification time of the file, if it was stored.
+	pub fn modified(&self) -> Option<&'decoder Timestamp> {
+		self.entry
+			.and_then(|file| file.timestamps.as_ref())
+			.and_then(|timestamps| timestamps.modified.as_ref())
+	}
+
+	/// The file entry in the directory.
+	///
+	/// This is `None` for the root and for implicit directories.
+	pub fn entry(&self) -> Option<&'decoder File> {
+		self.entry
+	}
+}
+
+/// An entry in a directory listing.
+///
+/// This is returned by [`ZarcFs::read_dir()`].
+#[derive(Clone, Debug)]
+pub struct DirEntry<'decoder> {
+	path: Pathname,
+	file_type: FileType,
+	entry: Option<&'decoder File>,
+}
+
+impl<'decoder> DirEntry<'decoder> {
+	/// The full pathname of the entry.
+	pub fn path(&self) -> &Pathname {
+		&self.path
+	}
+
+	/// The name of the entry within its directory.
+	pub fn file_name(&self) -> &CborString {
+		// UNWRAP: entries are always under a directory, so their path isn't empty
+		#[allow(clippy::unwrap_used)]
+		self.path.0.last().unwrap()
+	}
+
+	/// The type of the entry, without following symlinks.
+	pub fn file_type(&self) -> FileType {
+		self.file_type
+	}
+
+	/// The file entry in the directory.
+	///
+	/// This is `None` for implicit directories.
+	pub fn entry(&self) -> Option<&'decoder File> {
+		self.entry
+	}
+}
+
+/// A small tree with directories, implicit directories, and symlinks.
+#[cfg(test)]
+fn tree() -> crate::decode::Decoder<std::sync::Arc<[u8]>> {
+	use crate::{
+		directory::{LinkTarget, SpecialFile, SpecialFileKind},
+		encode::Encoder,
+		test_util::{name, open},
+	};
+
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	for (path, content) in [("a/b/c.txt", "c"), ("a/b/d.txt", "d"), ("a/e.txt", "e")] {
+		let file = zarc.build_file(name(path));
+		zarc.add_solid_file(file, content.as_bytes())
+			.expect("add solid");
+	}
+
+	let mut dir = zarc.build_file(name("a/empty"));
+	dir.directory();
+	zarc.add_file_entry(dir).expect("add dir");
+
+	for (path, target) in [
+		("link", "a/b"),
+		("a/up", "../a/e.txt"),
+		("loop1", "loop2"),
+		("loop2", "loop1"),
+	] {
+		let mut link = zarc.build_file(name(path));
+		link.0.special = Some(SpecialFile {
+			kind: Some(SpecialFileKind::InternalSymlink),
+			link_target: Some(LinkTarget::from(Path::new(target))),
+		});
+		zarc.add_file_entry(link).expect("add link");
+	}
+
+	zarc.finalise().expect("finalise");
+	open(bytes)
+}
+
+#[test]
+fn resolves_symlinks() {
+	use crate::test_util::name;
+
+	let zarc = tree();
+	let fs = ZarcFs::new(&zarc);
+
+	let link = fs.symlink_metadata("link").expect("link metadata");
+	assert!(link.is_symlink());
+	assert_eq!(link.entry().map(|entry| &entry.name), Some(&name("link")));
+	assert!(fs.metadata("link").expect("target metadata").is_dir());
+
+	assert!(fs
+		.symlink_metadata("a/up")
+		.expect("link metadata")
+		.is_symlink());
+	let up = fs.metadata("a/up").expect("target metadata");
+	assert!(up.is_file());
+	assert_eq!(up.len(), 1);
+
+	for (path, canonical) in [
+		("a/b/../e.txt", "a/e.txt"),
+		("/a/./b/c.txt", "a/b/c.txt"),
+		("link/c.txt", "a/b/c.txt"),
+		// the parent of a symlink's target, not of the symlink
+		("link/../e.txt", "a/e.txt"),
+		("a/up", "a/e.txt"),
+		("a", "a"),
+	] {
+		assert_eq!(
+			fs.canonicalize(path).expect("canonicalize"),
+			name(canonical),
+			"{path}"
+		);
+	}
+	assert_eq!(fs.canonicalize("..").expect("root"), Pathname(Vec::new()));
+
+	assert!(fs
+		.symlink_metadata("loop1")
+		.expect("link metadata")
+		.is_symlink());
+	for path in ["loop1", "loop2/x"] {
+		fs.canonicalize(path).expect_err("symlink loop");
+		fs.metadata(path).expect_err("symlink loop");
+		assert!(!fs.exists(path));
+	}
+}
+
+#[test]
+fn lists_and_reads_files() {
+	use crate::test_util::name;
+
+	let zarc = tree();
+	let fs = ZarcFs::new(&zarc);
+
+	let list = |path: &str| -> Vec<(Pathname, FileType, bool)> {
+		fs.read_dir(path)
+			.expect("read dir")
+			.into_iter()
+			.map(|entry| {
+				(
+					entry.path().clone(),
+					entry.file_type(),
+					entry.entry().is_some(),
+				)
+			})
+			.collect()
+	};
+
+	assert_eq!(
+		list(""),
+		[
+			(name("a"), FileType::Dir, false),
+			(name("link"), FileType::Symlink, true),
+			(name("loop1"), FileType::Symlink, true),
+			(name("loop2"), FileType::Symlink, true),
+		]
+	);
+	assert_eq!(
+		list("a"),
+		[
+			(name("a/b"), FileType::Dir, false),
+			(name("a/e.txt"), FileType::File, true),
+			(name("a/empty"), FileType::Dir, true),
+			(name("a/up"), FileType::Symlink, true),
+		]
+	);
+	assert_eq!(list("link"), list("a/b"));
+	assert_eq!(list("a/b").len(), 2);
+	assert!(list("a/empty").is_empty());
+	assert_eq!(
+		fs.read_dir("a/e.txt").expect_err("file").kind(),
+		ErrorKind::NotADirectory
+	);
+
+	assert_eq!(fs.read("a/b/c.txt").expect("read"), b"c");
+	assert_eq!(fs.read("link/d.txt").expect("read"), b"d");
+	assert_eq!(fs.read("a/up").expect("read"), b"e");
+	for (path, kind) in [
+		("missing", ErrorKind::NotFound),
+		("a/missing", ErrorKind::NotFound),
+		("link/missing", ErrorKind::NotFound),
+		("a/e.txt/x", ErrorKind::NotADirectory),
+		("a/b", ErrorKind::IsADirectory),
+	] {
+		assert_eq!(fs.open(path).expect_err(path).kind(), kind, "{path}");
+		assert_eq!(fs.read(path).expect_err(path).kind(), kind, "{path}");
+	}
+	assert_eq!(
+		fs.metadata("missing").expect_err("missing").kind(),
+		ErrorKind::NotFound
+	);
+}
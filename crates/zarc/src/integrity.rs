@@ -1,8 +1,14 @@
 //! Types supporting file integrity (checksums).
 
+use blake3::hazmat::{
+	merge_subtrees_non_root, merge_subtrees_root, ChainingValue, HasherExt, Mode,
+};
 use deku::prelude::*;
 use minicbor::{data::Type, Decode, Decoder, Encode, Encoder};
 
+#[doc(no_inline)]
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
 /// Digest newtype.
 ///
 /// This is a wrapper around a byte vector, which is the actual digest.
@@ -116,3 +122,176 @@ impl DigestType {
 		}
 	}
 }
+
+/// Size of the groups of content hashed into an [`Outboard`].
+///
+/// This is 16 BLAKE3 chunks, so an outboard is 1/512th of the size of the content.
+pub const OUTBOARD_GROUP_SIZE: usize = 16 * 1024;
+
+/// BLAKE3 outboard of some content.
+///
+/// This holds the chaining values of each [group](OUTBOARD_GROUP_SIZE) of the content, which are
+/// the leaves of the BLAKE3 hash tree. The parents are recomputed from them, up to the root, which
+/// is the content digest. Once the outboard has been checked against the digest, each group of
+/// the content can be checked on its own, without having the rest of the content.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Outboard(Vec<ChainingValue>);
+
+impl Outboard {
+	/// Compute the outboard of some content.
+	///
+	/// Returns None if the content fits in a single group, as then its digest is enough.
+	pub fn compute(content: &[u8]) -> Option<Self> {
+		if content.len() <= OUTBOARD_GROUP_SIZE {
+			return None;
+		}
+
+		Some(Self(
+			content
+				.chunks(OUTBOARD_GROUP_SIZE)
+				.enumerate()
+				.map(|(index, group)| group_chaining_value(index, group))
+				.collect(),
+		))
+	}
+
+	/// Parse an outboard and check it against the digest of the content.
+	///
+	/// Returns None if the outboard doesn't match, or isn't for content of that length.
+	pub fn verified(bytes: &[u8], length: u64, digest: &Digest) -> Option<Self> {
+		let groups = usize::try_from(length).ok()?.div_ceil(OUTBOARD_GROUP_SIZE);
+		if groups < 2 || bytes.len() != groups * blake3::OUT_LEN {
+			return None;
+		}
+
+		let outboard = Self(
+			bytes
+				.chunks_exact(blake3::OUT_LEN)
+				.map(|cv| cv.try_into().ok())
+				.collect::<Option<_>>()?,
+		);
+
+		let root = merge_subtrees(&outboard.0, true);
+		(root.as_slice() == digest.as_slice()).then_some(outboard)
+	}
+
+	/// Get the bytes of the outboard, to store it.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		self.0.concat()
+	}
+
+	/// Check a group of content, given its index (its offset divided by [`OUTBOARD_GROUP_SIZE`]).
+	pub fn check_group(&self, index: usize, group: &[u8]) -> bool {
+		use subtle::ConstantTimeEq;
+		self.0
+			.get(index)
+			.is_some_and(|expected| group_chaining_value(index, group).ct_eq(expected).into())
+	}
+}
+
+fn group_chaining_value(index: usize, group: &[u8]) -> ChainingValue {
+	let mut hasher = blake3::Hasher::new();
+	hasher.set_input_offset((index * OUTBOARD_GROUP_SIZE) as u64);
+	hasher.update(group);
+	hasher.finalize_non_root()
+}
+
+/// Compute the chaining value of a subtree from its leaves, or the root hash if `root`.
+///
+/// The left subtree always has the largest power of two of groups, like BLAKE3 does with chunks.
+fn merge_subtrees(leaves: &[ChainingValue], root: bool) -> ChainingValue {
+	if leaves.len() == 1 {
+		return leaves[0];
+	}
+
+	let split = 1 << (usize::BITS - 1 - (leaves.len() - 1).leading_zeros());
+	let left = merge_subtrees(&leaves[..split], false);
+	let right = merge_subtrees(&leaves[split..], false);
+	if root {
+		*merge_subtrees_root(&left, &right, Mode::Hash).as_bytes()
+	} else {
+		merge_subtrees_non_root(&left, &right, Mode::Hash)
+	}
+}
+
+#[test]
+fn outboard_root_is_content_digest() {
+	use crate::test_util::random;
+
+	for groups in [2, 3, 5, 16] {
+		// the last group is partial
+		let content = random(groups * OUTBOARD_GROUP_SIZE - 100);
+		let digest = Digest::from(blake3::hash(&content));
+
+		let outboard = Outboard::compute(&content).expect("outboard");
+		assert_eq!(outboard.0.len(), groups);
+		assert_eq!(
+			merge_subtrees(&outboard.0, true).as_slice(),
+			digest.as_slice(),
+			"{groups} groups"
+		);
+		assert_eq!(
+			Outboard::verified(&outboard.to_bytes(), content.len() as u64, &digest),
+			Some(outboard.clone()),
+			"{groups} groups"
+		);
+		let longer = (content.len() + OUTBOARD_GROUP_SIZE) as u64;
+		assert!(Outboard::verified(&outboard.to_bytes(), longer, &digest).is_none());
+		let other = Digest::from(blake3::hash(&content[1..]));
+		assert!(Outboard::verified(&outboard.to_bytes(), content.len() as u64, &other).is_none());
+
+		for (index, group) in content.chunks(OUTBOARD_GROUP_SIZE).enumerate() {
+			assert!(outboard.check_group(index, group));
+			assert!(!outboard.check_group(index + 1, group));
+
+			let mut flipped = group.to_vec();
+			flipped[group.len() / 2] ^= 1;
+			assert!(!outboard.check_group(index, &flipped));
+		}
+	}
+
+	assert!(Outboard::compute(&random(OUTBOARD_GROUP_SIZE)).is_none());
+}
+
+#[test]
+fn read_range_stops_at_tampered_group() {
+	use crate::{
+		decode::error::ErrorKind,
+		encode::Encoder,
+		test_util::{error_kind, open, random},
+	};
+
+	let content = random(5 * OUTBOARD_GROUP_SIZE);
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	zarc.set_outboard_threshold(Some(0));
+	let digest = zarc.add_data_frame(&content).expect("add frame");
+	zarc.finalise().expect("finalise");
+
+	// random content is stored as is, so it can be found and changed in the zarc
+	let tampered = 3 * OUTBOARD_GROUP_SIZE + 100;
+	let needle = &content[tampered..(tampered + 64)];
+	let at = bytes
+		.windows(needle.len())
+		.position(|window| window == needle)
+		.expect("stored content");
+	bytes[at] ^= 1;
+
+	let zarc = open(bytes);
+	let mut range = zarc
+		.read_range(&digest, 0..content.len() as u64)
+		.expect("read range")
+		.expect("frame");
+	assert!(range.has_outboard());
+
+	let mut read = Vec::new();
+	let err = loop {
+		match range.next().expect("ends with an error") {
+			Ok(chunk) => read.extend(chunk),
+			Err(err) => break err,
+		}
+	};
+	assert_eq!(error_kind(&err), Some(ErrorKind::ContentIntegrity));
+	assert_eq!(read, content[..(3 * OUTBOARD_GROUP_SIZE)]);
+	assert!(range.next().is_none());
+}
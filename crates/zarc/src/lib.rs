@@ -15,6 +15,8 @@ mod constants;
 pub mod decode;
 pub mod directory;
 pub mod encode;
+pub mod encryption;
+pub mod fs;
 pub mod header;
 pub mod integrity;
 #[cfg(feature = "metadata")]
@@ -22,6 +24,8 @@ pub mod metadata;
 pub mod ondemand;
 #[cfg(unix)]
 pub mod owner_cache;
+#[cfg(test)]
+pub(crate) mod test_util;
 pub mod trailer;
 
 pub(crate) fn map_zstd_error(code: usize) -> std::io::Error {
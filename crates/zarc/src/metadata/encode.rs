@@ -1,7 +1,7 @@
 //! Helpers to read file metadata to encode [`File`]s.
 
 use std::{
-	collections::HashMap,
+	collections::BTreeMap,
 	fs::{self, Metadata},
 	io::Result,
 	num::NonZeroU16,
@@ -52,6 +52,8 @@ pub fn build_filemap(edition: NonZeroU16, path: &Path, follow_links: bool) -> Re
 	Ok(File {
 		edition,
 		digest: None,
+		chunks: None,
+		solid: None,
 		name,
 		user: owner_user(&meta)
 			.map_err(|err| warn!(%err, "can't resolve user"))
@@ -212,8 +214,8 @@ pub fn posix_mode(meta: &Metadata) -> Option<u32> {
 pub fn file_attributes(
 	path: &Path,
 	meta: &Metadata,
-) -> Result<Option<HashMap<String, AttributeValue>>> {
-	let mut attrs = HashMap::new();
+) -> Result<Option<BTreeMap<String, AttributeValue>>> {
+	let mut attrs = BTreeMap::new();
 	#[cfg(target_os = "linux")]
 	{
 		use e2p_fileflags::{FileFlags, Flags};
@@ -340,13 +342,12 @@ pub fn file_attributes(
 /// - NetBSD
 ///
 #[instrument(level = "trace")]
-pub fn file_extended_attributes(path: &Path) -> Result<Option<HashMap<String, AttributeValue>>> {
+pub fn file_extended_attributes(path: &Path) -> Result<Option<BTreeMap<String, AttributeValue>>> {
 	#[cfg(unix)]
 	{
 		if xattr::SUPPORTED_PLATFORM {
 			let list = xattr::list(path)?;
-			let size_hint = list.size_hint();
-			let mut map = HashMap::with_capacity(size_hint.1.unwrap_or(size_hint.0));
+			let mut map = BTreeMap::new();
 			for osname in list {
 				match osname.to_str() {
 					None => tracing::error!(?osname, ?path, "not storing non-Unicode xattr"),
@@ -3,12 +3,20 @@
 //! This is a trait that allows for obtaining multiple reader+seeker instances from a single byte
 //! source. Zarc uses it to allow for reading from multiple places in the source at the same time.
 //!
-//! This is implemented for files ([`Path`] and [`PathBuf`]) in this crate.
+//! This is implemented for files ([`Path`] and [`PathBuf`]), for bytes in memory ([`Arc<[u8]>`]
+//! and `&'static [u8]`), with the `mmap` feature for memory-mapped files ([`MmapFile`]), and with
+//! the `http` feature for files served over HTTP ([`HttpSource`]). [`SubRange`] wraps any of these
+//! to read a Zarc embedded in a larger file.
+//!
+//! With the `tokio` feature, [`AsyncOnDemand`] is the same for async readers, used by the
+//! [`AsyncDecoder`](crate::decode::AsyncDecoder).
 
 use std::{
 	fs::File,
-	io::{Read, Result, Seek},
+	io::{Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom},
+	ops::Range,
 	path::{Path, PathBuf},
+	sync::Arc,
 };
 
 /// On-demand independent readers for a byte source.
@@ -18,6 +26,26 @@ pub trait OnDemand {
 
 	/// Open an independent reader for this byte source.
 	fn open(&self) -> Result<Self::Reader>;
+
+	/// Open an independent reader to read a range of this byte source.
+	///
+	/// The reader starts at the start of the range. It can still seek and read anywhere: the range
+	/// is a hint for sources which fetch data ahead of reads, like [`HttpSource`]. The decoder uses
+	/// this when it knows how long what it's about to read is, like frames. The default opens a
+	/// reader and seeks it.
+	fn open_range(&self, range: Range<u64>) -> Result<Self::Reader> {
+		let mut reader = self.open()?;
+		reader.seek(SeekFrom::Start(range.start))?;
+		Ok(reader)
+	}
+
+	/// Get the entire byte source as a slice, if it's in memory.
+	///
+	/// When this returns a slice, the decoder decompresses straight from it instead of reading
+	/// frames through a reader, which avoids copying. The default returns `None`.
+	fn as_slice(&self) -> Option<&[u8]> {
+		None
+	}
 }
 
 impl OnDemand for &Path {
@@ -35,3 +63,162 @@ impl OnDemand for PathBuf {
 		File::open(self)
 	}
 }
+
+impl OnDemand for Arc<[u8]> {
+	type Reader = Cursor<Arc<[u8]>>;
+
+	fn open(&self) -> Result<Self::Reader> {
+		Ok(Cursor::new(self.clone()))
+	}
+
+	fn as_slice(&self) -> Option<&[u8]> {
+		Some(self)
+	}
+}
+
+impl OnDemand for &'static [u8] {
+	type Reader = Cursor<&'static [u8]>;
+
+	fn open(&self) -> Result<Self::Reader> {
+		Ok(Cursor::new(self))
+	}
+
+	fn as_slice(&self) -> Option<&[u8]> {
+		Some(self)
+	}
+}
+
+/// A byte range within another source.
+///
+/// This is for reading a Zarc which is embedded in a larger file, like an archive appended to an
+/// executable, or stored uncompressed inside another Zarc's frame. Readers opened from this start
+/// at the `offset` within the source, and end `length` bytes later; seeks are relative to that.
+///
+/// If you don't know where the Zarc is, see [`Decoder::locate()`](crate::decode::Decoder::locate).
+#[derive(Clone, Debug)]
+pub struct SubRange<T> {
+	source: T,
+	offset: u64,
+	length: u64,
+}
+
+impl<T> SubRange<T> {
+	/// Expose `length` bytes of a source, starting at `offset`.
+	pub fn new(source: T, offset: u64, length: u64) -> Self {
+		Self {
+			source,
+			offset,
+			length,
+		}
+	}
+
+	/// The offset of the range within the source.
+	pub fn offset(&self) -> u64 {
+		self.offset
+	}
+
+	/// The length of the range.
+	pub fn length(&self) -> u64 {
+		self.length
+	}
+
+	/// Get the source back.
+	pub fn into_inner(self) -> T {
+		self.source
+	}
+}
+
+impl<T: OnDemand> OnDemand for SubRange<T> {
+	type Reader = SubRangeReader<T::Reader>;
+
+	fn open(&self) -> Result<Self::Reader> {
+		let mut reader = self.source.open()?;
+		reader.seek(SeekFrom::Start(self.offset))?;
+		Ok(SubRangeReader {
+			reader,
+			offset: self.offset,
+			length: self.length,
+			position: 0,
+		})
+	}
+
+	fn open_range(&self, range: Range<u64>) -> Result<Self::Reader> {
+		let end = range.end.min(self.length).max(range.start);
+		let reader = self
+			.source
+			.open_range((self.offset + range.start)..(self.offset + end))?;
+		Ok(SubRangeReader {
+			reader,
+			offset: self.offset,
+			length: self.length,
+			position: range.start,
+		})
+	}
+
+	fn as_slice(&self) -> Option<&[u8]> {
+		let start = usize::try_from(self.offset).ok()?;
+		let end = start.checked_add(usize::try_from(self.length).ok()?)?;
+		self.source.as_slice()?.get(start..end)
+	}
+}
+
+/// Reader for a [`SubRange`].
+#[derive(Debug)]
+pub struct SubRangeReader<R> {
+	reader: R,
+	offset: u64,
+	length: u64,
+	position: u64,
+}
+
+impl<R: Read> Read for SubRangeReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+		let left = self.length.saturating_sub(self.position);
+		let max = usize::try_from(left).unwrap_or(usize::MAX).min(buf.len());
+		if max == 0 {
+			return Ok(0);
+		}
+
+		let bytes = self.reader.read(&mut buf[..max])?;
+		self.position += bytes as u64;
+		Ok(bytes)
+	}
+}
+
+impl<R: Seek> Seek for SubRangeReader<R> {
+	fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+		let position = match pos {
+			SeekFrom::Start(offset) => Some(offset),
+			SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+			SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+		}
+		.ok_or_else(|| {
+			Error::new(
+				ErrorKind::InvalidInput,
+				"invalid seek to a negative or overflowing position",
+			)
+		})?;
+
+		let absolute = self
+			.offset
+			.checked_add(position)
+			.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "seek overflows the source"))?;
+		self.reader.seek(SeekFrom::Start(absolute))?;
+		self.position = position;
+		Ok(position)
+	}
+}
+
+#[cfg(feature = "tokio")]
+pub use self::async_ondemand::AsyncOnDemand;
+#[cfg(feature = "http")]
+pub use self::http::{HttpReader, HttpSource};
+#[cfg(feature = "mmap")]
+pub use self::mmap::MmapFile;
+
+#[cfg(feature = "tokio")]
+mod async_ondemand;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "mmap")]
+mod mmap;
@@ -0,0 +1,56 @@
+use std::{
+	future::{ready, Future},
+	io::{Cursor, Result},
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use tokio::{
+	fs::File,
+	io::{AsyncRead, AsyncSeek},
+};
+
+/// On-demand independent async readers for a byte source.
+///
+/// This is the async counterpart of [`OnDemand`](super::OnDemand), used by the
+/// [`AsyncDecoder`](crate::decode::AsyncDecoder). It's implemented for files ([`Path`] and
+/// [`PathBuf`]) and for bytes in memory ([`Arc<[u8]>`] and `&'static [u8]`).
+pub trait AsyncOnDemand: Send + Sync {
+	/// The output reader type.
+	type Reader: AsyncRead + AsyncSeek + Unpin + Send;
+
+	/// Open an independent reader for this byte source.
+	fn open(&self) -> impl Future<Output = Result<Self::Reader>> + Send;
+}
+
+impl AsyncOnDemand for &Path {
+	type Reader = File;
+
+	fn open(&self) -> impl Future<Output = Result<Self::Reader>> + Send {
+		File::open(*self)
+	}
+}
+
+impl AsyncOnDemand for PathBuf {
+	type Reader = File;
+
+	fn open(&self) -> impl Future<Output = Result<Self::Reader>> + Send {
+		File::open(self)
+	}
+}
+
+impl AsyncOnDemand for Arc<[u8]> {
+	type Reader = Cursor<Arc<[u8]>>;
+
+	fn open(&self) -> impl Future<Output = Result<Self::Reader>> + Send {
+		ready(Ok(Cursor::new(self.clone())))
+	}
+}
+
+impl AsyncOnDemand for &'static [u8] {
+	type Reader = Cursor<&'static [u8]>;
+
+	fn open(&self) -> impl Future<Output = Result<Self::Reader>> + Send {
+		ready(Ok(Cursor::new(*self)))
+	}
+}
@@ -0,0 +1,594 @@
+use std::{
+	collections::{HashMap, VecDeque},
+	fmt,
+	io::{Error, ErrorKind, Read, Result, Seek, SeekFrom},
+	ops::Range,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Mutex,
+	},
+};
+
+use tracing::{debug, trace};
+
+use super::OnDemand;
+
+/// Size of the blocks which are fetched and cached.
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// How much is fetched at once, at least.
+const READAHEAD: u64 = 4 * BLOCK_SIZE;
+
+/// How many blocks are kept in the cache.
+const CACHE_BLOCKS: usize = 256;
+
+/// A file served over HTTP, read with `Range` requests.
+///
+/// The server must support range requests. Opening the source fetches the end of the file, which
+/// holds the Zarc trailer and, for smaller Zarcs, the directory. Readers then fetch data in blocks,
+/// which are cached and shared between all readers of the source, and read ahead of what's asked.
+///
+/// When the decoder knows the extent of what it's reading, like a frame or the directory, it's
+/// fetched in a single request, and large frames are streamed from that request instead of cached.
+///
+/// Clones share the same connection pool and cache.
+#[derive(Clone)]
+pub struct HttpSource {
+	inner: Arc<Inner>,
+}
+
+struct Inner {
+	agent: ureq::Agent,
+	url: String,
+	length: u64,
+	cache: Mutex<BlockCache>,
+	requests: AtomicU64,
+}
+
+impl fmt::Debug for HttpSource {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("HttpSource")
+			.field("url", &self.inner.url)
+			.field("length", &self.inner.length)
+			.field("requests", &self.requests())
+			.finish_non_exhaustive()
+	}
+}
+
+impl HttpSource {
+	/// Open a file served over HTTP.
+	///
+	/// This makes one request, for the end of the file.
+	pub fn new(url: impl Into<String>) -> Result<Self> {
+		Self::with_agent(ureq::Agent::new(), url)
+	}
+
+	/// Open a file served over HTTP, with a configured [`ureq::Agent`].
+	///
+	/// Use this to set timeouts, proxies, or TLS settings. The agent must not ask for compressed
+	/// responses, as ranges are of the file as stored.
+	pub fn with_agent(agent: ureq::Agent, url: impl Into<String>) -> Result<Self> {
+		let inner = Inner {
+			agent,
+			url: url.into(),
+			length: 0,
+			cache: Mutex::new(BlockCache::default()),
+			requests: AtomicU64::new(0),
+		};
+
+		let (range, length, body) = inner.request(&format!("bytes=-{READAHEAD}"))?;
+		debug!(url=%inner.url, %length, "opened http source");
+
+		let mut inner = inner;
+		inner.length = length;
+		inner.store(range, body)?;
+
+		Ok(Self {
+			inner: Arc::new(inner),
+		})
+	}
+
+	/// The URL of the file.
+	pub fn url(&self) -> &str {
+		&self.inner.url
+	}
+
+	/// The length of the file.
+	pub fn length(&self) -> u64 {
+		self.inner.length
+	}
+
+	/// How many requests were made for this source so far.
+	pub fn requests(&self) -> u64 {
+		self.inner.requests.load(Ordering::Relaxed)
+	}
+}
+
+impl OnDemand for HttpSource {
+	type Reader = HttpReader;
+
+	fn open(&self) -> Result<Self::Reader> {
+		Ok(HttpReader {
+			source: self.clone(),
+			position: 0,
+			hint: None,
+			stream: None,
+		})
+	}
+
+	fn open_range(&self, range: Range<u64>) -> Result<Self::Reader> {
+		Ok(HttpReader {
+			source: self.clone(),
+			position: range.start,
+			hint: Some(range),
+			stream: None,
+		})
+	}
+}
+
+impl Inner {
+	/// Make a range request.
+	///
+	/// Returns the range the server responded with, the length of the file, and the body.
+	fn request(&self, range: &str) -> Result<(Range<u64>, u64, Box<dyn Read + Send + Sync>)> {
+		trace!(url=%self.url, %range, "http range request");
+		self.requests.fetch_add(1, Ordering::Relaxed);
+		let response = self
+			.agent
+			.get(&self.url)
+			.set("Range", range)
+			.call()
+			.map_err(|err| match err {
+				ureq::Error::Status(status, _) => {
+					Error::other(format!("http status {status} for {range} of {}", self.url))
+				}
+				ureq::Error::Transport(err) => Error::other(err),
+			})?;
+
+		if response.status() != 206 {
+			return Err(Error::new(
+				ErrorKind::Unsupported,
+				format!("{} doesn't support range requests", self.url),
+			));
+		}
+
+		let content_range = response.header("Content-Range").unwrap_or_default();
+		let (start, end, length) = parse_content_range(content_range).ok_or_else(|| {
+			Error::new(
+				ErrorKind::InvalidData,
+				format!("invalid content-range {content_range:?} from {}", self.url),
+			)
+		})?;
+
+		Ok((start..end, length, response.into_reader()))
+	}
+
+	/// Fetch a range into the cache.
+	fn fetch(&self, range: Range<u64>) -> Result<()> {
+		let (got, _, body) = self.request(&format!("bytes={}-{}", range.start, range.end - 1))?;
+		if got != range {
+			return Err(Error::new(
+				ErrorKind::InvalidData,
+				format!("asked for {range:?} but got {got:?} from {}", self.url),
+			));
+		}
+
+		self.store(got, body)
+	}
+
+	/// Read a response body and store its whole blocks in the cache.
+	///
+	/// The last block of the file is shorter, and is also stored.
+	fn store(&self, range: Range<u64>, body: impl Read) -> Result<()> {
+		let mut data = Vec::with_capacity((range.end - range.start) as _);
+		body.take(range.end - range.start).read_to_end(&mut data)?;
+		if data.len() as u64 != range.end - range.start {
+			return Err(ErrorKind::UnexpectedEof.into());
+		}
+
+		let mut cache = self
+			.cache
+			.lock()
+			.map_err(|_| Error::other("poisoned cache"))?;
+		let mut block = range.start.div_ceil(BLOCK_SIZE);
+		loop {
+			let start = block * BLOCK_SIZE;
+			let end = (start + BLOCK_SIZE).min(self.length);
+			if start >= end || end > range.end {
+				break;
+			}
+
+			let offset = (start - range.start) as usize;
+			cache.insert(
+				block,
+				data[offset..(offset + (end - start) as usize)].into(),
+			);
+			block += 1;
+		}
+
+		Ok(())
+	}
+
+	fn cached(&self, block: u64) -> Option<Arc<[u8]>> {
+		self.cache.lock().ok()?.get(block)
+	}
+}
+
+/// Parse a `Content-Range` header, as `start..end` and the complete length.
+fn parse_content_range(header: &str) -> Option<(u64, u64, u64)> {
+	let (range, length) = header.strip_prefix("bytes ")?.split_once('/')?;
+	let (start, last) = range.split_once('-')?;
+	let start: u64 = start.parse().ok()?;
+	let end = last.parse::<u64>().ok()?.checked_add(1)?;
+	let length = length.parse().ok()?;
+	(start < end && end <= length).then_some((start, end, length))
+}
+
+/// Blocks of the file, evicted oldest first.
+#[derive(Debug, Default)]
+struct BlockCache {
+	blocks: HashMap<u64, Arc<[u8]>>,
+	order: VecDeque<u64>,
+}
+
+impl BlockCache {
+	fn get(&self, block: u64) -> Option<Arc<[u8]>> {
+		self.blocks.get(&block).cloned()
+	}
+
+	fn insert(&mut self, block: u64, data: Arc<[u8]>) {
+		if self.blocks.insert(block, data).is_some() {
+			return;
+		}
+
+		self.order.push_back(block);
+		while self.order.len() > CACHE_BLOCKS {
+			if let Some(old) = self.order.pop_front() {
+				self.blocks.remove(&old);
+			}
+		}
+	}
+}
+
+/// Reader for an [`HttpSource`].
+pub struct HttpReader {
+	source: HttpSource,
+	position: u64,
+	hint: Option<Range<u64>>,
+	stream: Option<Stream>,
+}
+
+/// A response being read as it arrives.
+struct Stream {
+	position: u64,
+	end: u64,
+	body: Box<dyn Read + Send + Sync>,
+}
+
+impl fmt::Debug for HttpReader {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("HttpReader")
+			.field("source", &self.source)
+			.field("position", &self.position)
+			.field("hint", &self.hint)
+			.field(
+				"stream",
+				&self
+					.stream
+					.as_ref()
+					.map(|stream| stream.position..stream.end),
+			)
+			.finish()
+	}
+}
+
+impl HttpReader {
+	fn read_stream(&mut self, buf: &mut [u8]) -> Option<Result<usize>> {
+		let stream = self
+			.stream
+			.as_mut()
+			.filter(|stream| stream.position == self.position && stream.position < stream.end)?;
+
+		let max = usize::try_from(stream.end - stream.position)
+			.unwrap_or(usize::MAX)
+			.min(buf.len());
+		Some(match stream.body.read(&mut buf[..max]) {
+			Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+			Ok(bytes) => {
+				stream.position += bytes as u64;
+				self.position += bytes as u64;
+				Ok(bytes)
+			}
+			Err(err) => Err(err),
+		})
+	}
+
+	fn read_cached(&mut self, buf: &mut [u8]) -> Option<usize> {
+		let block = self.position / BLOCK_SIZE;
+		let data = self.source.inner.cached(block)?;
+		let offset = (self.position - block * BLOCK_SIZE) as usize;
+		let bytes = data.len().saturating_sub(offset).min(buf.len());
+		buf[..bytes].copy_from_slice(&data[offset..(offset + bytes)]);
+		self.position += bytes as u64;
+		Some(bytes)
+	}
+}
+
+impl Read for HttpReader {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+		let length = self.source.length();
+		if buf.is_empty() || self.position >= length {
+			return Ok(0);
+		}
+
+		if let Some(result) = self.read_stream(buf) {
+			return result;
+		}
+
+		if let Some(bytes) = self.read_cached(buf) {
+			return Ok(bytes);
+		}
+
+		// fetch what's left of the hinted range, or at least the readahead
+		let wanted = self
+			.hint
+			.as_ref()
+			.filter(|hint| hint.contains(&self.position))
+			.map_or(0, |hint| hint.end.min(length));
+		let start = self.position;
+		let end = wanted.max(start + READAHEAD).min(length);
+
+		if wanted > start + READAHEAD {
+			debug!(%start, end=%wanted, "stream range");
+			let (got, _, body) = self
+				.source
+				.inner
+				.request(&format!("bytes={start}-{}", wanted - 1))?;
+			if got != (start..wanted) {
+				return Err(Error::new(
+					ErrorKind::InvalidData,
+					format!("asked for {start}..{wanted} but got {got:?}"),
+				));
+			}
+
+			self.stream = Some(Stream {
+				position: start,
+				end: wanted,
+				body,
+			});
+			return self.read_stream(buf).unwrap_or(Ok(0));
+		}
+
+		// align to blocks so the cache can be used
+		let block_start = start - start % BLOCK_SIZE;
+		let block_end = end
+			.div_ceil(BLOCK_SIZE)
+			.saturating_mul(BLOCK_SIZE)
+			.min(length);
+		debug!(start=%block_start, end=%block_end, "fetch range");
+		self.source.inner.fetch(block_start..block_end)?;
+
+		Ok(self.read_cached(buf).unwrap_or(0))
+	}
+}
+
+impl Seek for HttpReader {
+	fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+		let position = match pos {
+			SeekFrom::Start(offset) => Some(offset),
+			SeekFrom::End(offset) => self.source.length().checked_add_signed(offset),
+			SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+		}
+		.ok_or_else(|| {
+			Error::new(
+				ErrorKind::InvalidInput,
+				"invalid seek to a negative or overflowing position",
+			)
+		})?;
+
+		self.position = position;
+		Ok(position)
+	}
+}
+
+/// Serve a file on localhost, answering range requests unless `ranges` is false.
+///
+/// Returns the URL of the file, and the `Range` headers of the requests made so far.
+#[cfg(test)]
+fn serve(file: Vec<u8>, ranges: bool) -> (String, Arc<Mutex<Vec<String>>>) {
+	use std::{
+		io::{BufRead, BufReader, Write},
+		net::TcpListener,
+		thread,
+	};
+
+	let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+	let url = format!(
+		"http://{}/test.zarc",
+		listener.local_addr().expect("address")
+	);
+	let file: Arc<[u8]> = file.into();
+	let requested = Arc::new(Mutex::new(Vec::new()));
+
+	let log = requested.clone();
+	thread::spawn(move || {
+		for mut stream in listener.incoming().flatten() {
+			let file = file.clone();
+			let log = log.clone();
+			thread::spawn(move || {
+				let mut range = None;
+				let mut reader = BufReader::new(&stream);
+				loop {
+					let mut line = String::new();
+					if reader.read_line(&mut line).unwrap_or(0) == 0 {
+						return;
+					}
+
+					match line.trim_end().split_once(':') {
+						Some((name, value)) if name.eq_ignore_ascii_case("range") => {
+							range = Some(value.trim().to_string());
+						}
+						Some(_) => {}
+						None if line.trim_end().is_empty() => break,
+						None => {}
+					}
+				}
+
+				let length = file.len() as u64;
+				let spec = range.clone().filter(|_| ranges);
+				let (start, end) = match spec.as_deref().and_then(|spec| {
+					let (start, last) = spec.strip_prefix("bytes=")?.split_once('-')?;
+					Some(if start.is_empty() {
+						(length.saturating_sub(last.parse().ok()?), length)
+					} else {
+						(
+							start.parse().ok()?,
+							(last.parse::<u64>().ok()? + 1).min(length),
+						)
+					})
+				}) {
+					Some(range) => range,
+					None => (0, length),
+				};
+				if let Some(range) = range {
+					log.lock().expect("log").push(range);
+				}
+
+				let mut head = if spec.is_some() {
+					format!(
+						"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {start}-{}/{length}\r\n",
+						end - 1
+					)
+				} else {
+					"HTTP/1.1 200 OK\r\n".into()
+				};
+				head.push_str(&format!(
+					"Content-Length: {}\r\nConnection: close\r\n\r\n",
+					end - start
+				));
+				stream
+					.write_all(head.as_bytes())
+					.and_then(|()| stream.write_all(&file[(start as usize)..(end as usize)]))
+					.ok();
+			});
+		}
+	});
+
+	(url, requested)
+}
+
+#[test]
+fn reads_zarc_over_http() {
+	use crate::{
+		decode::Decoder,
+		test_util::{name, random, zarc_bytes},
+	};
+
+	let big = random(600_000);
+	let bytes = zarc_bytes(&[("small.txt", b"hello over http"), ("big.bin", &big)]);
+	assert!(
+		bytes.len() as u64 > 2 * READAHEAD,
+		"zarc is larger than the suffix"
+	);
+
+	let (url, requested) = serve(bytes.clone(), true);
+	let source = HttpSource::new(&url).expect("open source");
+	assert_eq!(source.length(), bytes.len() as u64);
+	assert_eq!(source.requests(), 1);
+	assert_eq!(
+		requested.lock().expect("log").first().map(String::as_str),
+		Some(format!("bytes=-{READAHEAD}").as_str()),
+		"opening fetches the end of the file"
+	);
+
+	let mut zarc = Decoder::open(source.clone()).expect("open zarc");
+	zarc.read_directory().expect("read directory");
+	let mut names: Vec<_> = zarc.files().map(|file| file.name.clone()).collect();
+	names.sort();
+	assert_eq!(names, [name("big.bin"), name("small.txt")]);
+
+	for (path, content) in [("small.txt", &b"hello over http"[..]), ("big.bin", &big)] {
+		let before = source.requests();
+		let file = zarc.latest_file(name(path)).expect("file");
+		assert_eq!(
+			zarc.read_at(file, 0, content.len()).expect("read file"),
+			content,
+			"{path}"
+		);
+		assert!(
+			source.requests() - before <= 2,
+			"{path} took {} requests",
+			source.requests() - before
+		);
+	}
+
+	// past the start of the file and before the end fetched when opening, this is in the middle
+	// of the big frame, which is streamed instead of cached
+	let block = READAHEAD / BLOCK_SIZE;
+	assert!(READAHEAD < source.length() - READAHEAD - BLOCK_SIZE);
+	assert!(source.inner.cached(block).is_none());
+}
+
+#[test]
+fn rejects_servers_without_ranges() {
+	let (url, _) = serve(vec![0; 100], false);
+	let err = HttpSource::new(url).expect_err("no range support");
+	assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn stores_whole_blocks() {
+	let inner = Inner {
+		agent: ureq::Agent::new(),
+		url: String::new(),
+		length: 3 * BLOCK_SIZE + 10,
+		cache: Mutex::new(BlockCache::default()),
+		requests: AtomicU64::new(0),
+	};
+	let data: Vec<u8> = (0..inner.length).map(|n| n as u8).collect();
+	let store = |range: Range<u64>| {
+		inner
+			.store(
+				range.clone(),
+				&data[(range.start as usize)..(range.end as usize)],
+			)
+			.expect("store");
+	};
+
+	// only the blocks the range covers entirely are kept
+	store(100..(2 * BLOCK_SIZE + 50));
+	assert!(inner.cached(0).is_none());
+	assert_eq!(
+		inner.cached(1).as_deref(),
+		Some(&data[(BLOCK_SIZE as usize)..(2 * BLOCK_SIZE as usize)])
+	);
+	assert!(inner.cached(2).is_none());
+
+	// the last block is shorter
+	store((2 * BLOCK_SIZE)..inner.length);
+	assert_eq!(
+		inner.cached(2).map(|block| block.len()),
+		Some(BLOCK_SIZE as usize)
+	);
+	assert_eq!(
+		inner.cached(3).as_deref(),
+		Some(&data[(3 * BLOCK_SIZE as usize)..])
+	);
+
+	// a body shorter than its range is an error
+	assert!(inner.store(0..BLOCK_SIZE, &data[..10]).is_err());
+}
+
+#[test]
+fn parses_content_range() {
+	assert_eq!(parse_content_range("bytes 0-99/1000"), Some((0, 100, 1000)));
+	assert_eq!(
+		parse_content_range("bytes 900-999/1000"),
+		Some((900, 1000, 1000))
+	);
+	assert_eq!(parse_content_range("bytes 0-99/*"), None);
+	assert_eq!(parse_content_range("bytes 0-1000/1000"), None);
+	assert_eq!(parse_content_range("bytes 100-99/1000"), None);
+	assert_eq!(parse_content_range("0-99/1000"), None);
+	assert_eq!(parse_content_range("bytes */1000"), None);
+}
@@ -0,0 +1,83 @@
+use std::{
+	fs::File,
+	io::{Cursor, Result},
+	path::Path,
+	sync::Arc,
+};
+
+use memmap2::Mmap;
+
+use super::OnDemand;
+
+/// A memory-mapped file.
+///
+/// Create one with [`MmapFile::map()`]. Readers opened from this share the mapping, and the
+/// decoder decompresses straight from it.
+///
+/// The file must not be modified while it's mapped: this is undefined behaviour.
+#[derive(Clone, Debug)]
+pub struct MmapFile(Arc<Mmap>);
+
+impl MmapFile {
+	/// Map a file into memory.
+	///
+	/// # Safety
+	///
+	/// The file must not be modified, truncated, or removed while the mapping exists, which
+	/// includes all readers opened from it. See [`Mmap::map()`].
+	pub unsafe fn map(path: impl AsRef<Path>) -> Result<Self> {
+		let file = File::open(path)?;
+		Mmap::map(&file).map(|map| Self(Arc::new(map)))
+	}
+}
+
+impl AsRef<[u8]> for MmapFile {
+	fn as_ref(&self) -> &[u8] {
+		&self.0
+	}
+}
+
+impl OnDemand for MmapFile {
+	type Reader = Cursor<MmapFile>;
+
+	fn open(&self) -> Result<Self::Reader> {
+		Ok(Cursor::new(self.clone()))
+	}
+
+	fn as_slice(&self) -> Option<&[u8]> {
+		Some(&self.0)
+	}
+}
+
+#[test]
+fn decodes_mapped_zarc() {
+	use crate::{
+		decode::Decoder,
+		test_util::{name, random, zarc_bytes},
+	};
+
+	let big = random(300_000);
+	let path = std::env::temp_dir().join(format!("zarc-mmap-{}.zarc", std::process::id()));
+	std::fs::write(
+		&path,
+		zarc_bytes(&[("small.txt", b"mapped"), ("big.bin", &big)]),
+	)
+	.expect("write zarc");
+
+	// SAFETY: the file isn't changed until the mapping is dropped
+	let map = unsafe { MmapFile::map(&path) }.expect("map");
+	assert!(map.as_slice().is_some());
+	let mut zarc = Decoder::open(map).expect("open");
+	zarc.read_directory().expect("read directory");
+	for (path, content) in [("small.txt", &b"mapped"[..]), ("big.bin", &big)] {
+		let file = zarc.latest_file(name(path)).expect("file");
+		assert_eq!(
+			zarc.read_at(file, 0, content.len()).expect("read file"),
+			content,
+			"{path}"
+		);
+	}
+
+	drop(zarc);
+	std::fs::remove_file(&path).expect("clean up");
+}
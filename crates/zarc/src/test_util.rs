@@ -0,0 +1,66 @@
+//! Fixtures shared by tests.
+
+use std::{path::Path, sync::Arc};
+
+use crate::{
+	decode::{
+		error::{Error, ErrorKind},
+		Decoder,
+	},
+	directory::Pathname,
+	encode::Encoder,
+};
+
+/// A pathname from a relative path.
+pub(crate) fn name(name: &str) -> Pathname {
+	Pathname::from_normal_components(Path::new(name))
+}
+
+/// Random bytes, which don't compress.
+pub(crate) fn random(length: usize) -> Vec<u8> {
+	let mut bytes = vec![0; length];
+	blake3::Hasher::new().finalize_xof().fill(&mut bytes);
+	bytes
+}
+
+/// Bytes which compress somewhat but have few repeats, which chunk at content-defined boundaries.
+pub(crate) fn varied(length: usize) -> Vec<u8> {
+	(0..length as u64)
+		.map(|n| (n.wrapping_mul(6_364_136_223_846_793_005) >> 56) as u8)
+		.collect()
+}
+
+/// Write a zarc in memory with one data frame per file.
+pub(crate) fn zarc_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
+	let mut bytes = Vec::new();
+	let mut zarc = Encoder::new(&mut bytes).expect("new encoder");
+	for (path, content) in files {
+		let digest = zarc.add_data_frame(content).expect("add frame");
+		let mut file = zarc.build_file(name(path));
+		file.digest(digest);
+		zarc.add_file_entry(file).expect("add file");
+	}
+	zarc.finalise().expect("finalise");
+	bytes
+}
+
+/// Open a zarc in memory and read its directory.
+pub(crate) fn open(bytes: impl Into<Arc<[u8]>>) -> Decoder<Arc<[u8]>> {
+	let mut zarc = Decoder::open(bytes.into()).expect("open");
+	zarc.read_directory().expect("read directory");
+	zarc
+}
+
+/// Write a zarc in memory with one data frame per file, and open it.
+pub(crate) fn zarc(files: &[(&str, &[u8])]) -> Decoder<Arc<[u8]>> {
+	open(zarc_bytes(files))
+}
+
+/// The kind of a decoder error, if it has one.
+pub(crate) fn error_kind(err: &Error) -> Option<ErrorKind> {
+	match err {
+		Error::Simple(err) => Some(err.kind),
+		Error::Source(err) => Some(err.kind),
+		_ => None,
+	}
+}